@@ -1,11 +1,12 @@
 use anyhow::Result;
 use manager::qmp::qapi::QMPCommand;
+use manager::spec::VmSpec;
 use manager::vm::VM;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let id = "testid".to_string();
-    let mut vm = VM::new(id).await;
+    let mut vm = VM::new(id, VmSpec::default()).await;
 
     eprintln!("Starting VM");
     let _events = vm.start().await?;
@@ -21,8 +22,11 @@ async fn main() -> Result<()> {
     println!("query-status -- {reply}");
 
     eprintln!("Stopping VM");
-    vm.stop().await.unwrap();
-    eprintln!("VM process is stopped and namespaces removed");
+    let outcome = vm.stop().await?;
+    eprintln!(
+        "VM process is stopped and namespaces removed -- {:?}",
+        outcome
+    );
     eprintln!("Starting VM again");
     let _events = vm.start().await?;
 
@@ -0,0 +1,16 @@
+//! Generate the `qapi` module from the schema under `qapi/`
+use qapi_spec_parser::{Options, Output};
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    let schema_root = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    let options = Options::default()
+        .output(Output::Serde)
+        .doctest_crate("manager::qapi")
+        .command_trait("qapi_old_hack::Command");
+    let code = qapi_spec_parser::generate(&schema_root, &options)?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    std::fs::write(out_dir.join("qapi.rs"), code)?;
+    println!("cargo:rerun-if-changed=qapi");
+    Ok(())
+}
@@ -0,0 +1,411 @@
+//! Full and incremental backups of the disks of a running VM
+//!
+//! A disk that was backed up has a dirty bitmap named `backup` on its active
+//! layer, holding what the guest wrote since the last backup. A full backup
+//! copies the whole disk and starts the bitmap over, an incremental backup
+//! only copies what the bitmap holds. Either way the bitmap only moves on once
+//! the backup succeeded. On qcow2 images the bitmap is stored in the image and
+//! outlives QEMU; on raw images, or once a snapshot put a new layer on top,
+//! the next backup has to be a full one again.
+//!
+//! QEMU either pushes a backup into a qcow2 image, which for incremental
+//! backups is backed by the image of the backup before it, or exports the
+//! disk as it was at one point in time over NBD, for a backup tool to pull.
+//! `Backup` is what gets recorded of either; every incremental backup builds
+//! on the one before it, back to a full backup.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    Full,
+    /// Only what changed since the backup before it
+    Incremental,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupTarget {
+    /// A qcow2 image QEMU wrote the backup to
+    Image(PathBuf),
+    /// An export of the NBD server of the VM listening on `socket`. Clients
+    /// of incremental backups find what changed in the `qemu:dirty-bitmap:`
+    /// metadata context of the export.
+    Nbd { socket: PathBuf, export: String },
+}
+
+/// A backup of one disk
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Backup {
+    pub disk: String,
+    pub kind: BackupKind,
+    pub target: BackupTarget,
+    /// The point in time the backup holds the disk at
+    pub started_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "runtime")]
+pub use commands::*;
+
+#[cfg(feature = "runtime")]
+mod commands {
+    use super::{Backup, BackupKind};
+    use crate::qapi::{
+        self, BitmapSyncMode, BlockDeviceInfo, BlockDirtyBitmap, BlockDirtyBitmapAdd,
+        BlockDirtyBitmapAddWrapper, BlockDirtyBitmapMerge, BlockDirtyBitmapMergeWrapper,
+        BlockDirtyBitmapOrStr, BlockDirtyBitmapWrapper, BlockExportAdd, BlockExportDel,
+        BlockExportOptions, BlockExportOptionsBranch, BlockExportOptionsNbd, BlockdevBackup,
+        BlockdevBackupWrapper, Event as _, MirrorSyncMode, NbdServerStart, SocketAddressLegacy,
+        SocketAddressLegacyBranch, Transaction, TransactionAction, TransactionActionBranch,
+        UnixSocketAddress, UnixSocketAddressWrapper,
+    };
+    use crate::qmp::qapi::{Event, QMPCommand};
+    use crate::spec::DiskFormat;
+    use crate::storage::Layer;
+    use anyhow::{anyhow, Result};
+    use std::path::Path;
+
+    /// The bitmap holding the changes since the last backup
+    pub const BITMAP: &str = "backup";
+
+    /// The changes an incremental export holds, taken out of `BITMAP` when
+    /// the export started
+    pub const EXPORT_BITMAP: &str = "backup-export";
+
+    /// The node a backup is written to
+    pub fn target_node(disk: &str) -> String {
+        format!("bak-{}", disk)
+    }
+
+    /// The node keeping the point in time view of an exported disk
+    pub fn fleecing_node(disk: &str) -> String {
+        format!("pit-{}", disk)
+    }
+
+    /// The id of the NBD export of a disk, which is also its export name
+    pub fn export_id(disk: &str) -> String {
+        format!("export-{}", disk)
+    }
+
+    /// Whether the node `node` has a `BITMAP` to base an incremental backup on,
+    /// going by the reply to `storage::query_nodes`
+    pub fn has_bitmap(nodes: &[BlockDeviceInfo], node: &str) -> bool {
+        nodes
+            .iter()
+            .filter(|info| info.node_name.as_deref() == Some(node))
+            .flat_map(|info| info.dirty_bitmaps.iter().flatten())
+            .any(|bitmap| bitmap.name.as_deref() == Some(BITMAP))
+    }
+
+    /// The kind of backup of `disk` that builds on `parent`, the last backup
+    /// of the disk. Incremental backups need the changes since `parent` in
+    /// `BITMAP`.
+    pub fn kind(disk: &str, parent: Option<&Backup>, has_bitmap: bool) -> Result<BackupKind> {
+        let Some(parent) = parent else {
+            return Ok(BackupKind::Full);
+        };
+        if parent.disk != disk {
+            return Err(
+                anyhow! {"a backup of disk {} can not build on one of disk {}", disk, parent.disk},
+            );
+        }
+        if !has_bitmap {
+            return Err(
+                anyhow! {"disk {} has no changes recorded since its last backup, it needs a full backup", disk},
+            );
+        }
+        Ok(BackupKind::Incremental)
+    }
+
+    /// Whether `event` tells the export `id` is gone
+    pub fn export_deleted(event: &Event, id: &str) -> bool {
+        if event.event != qapi::BlockExportDeletedEvent::NAME {
+            return false;
+        }
+        let Some(data) = event.data.clone() else {
+            return false;
+        };
+        match serde_json::from_value::<qapi::BlockExportDeletedEvent>(data) {
+            Ok(deleted) => deleted.id == id,
+            Err(_) => false,
+        }
+    }
+
+    fn transaction(actions: Vec<TransactionActionBranch>) -> QMPCommand {
+        let actions = actions.into_iter().map(TransactionAction::new).collect();
+        QMPCommand::new(&Transaction::new(actions))
+    }
+
+    /// Start tracking changes to `layer` in `BITMAP`, kept in the image where
+    /// the format allows it
+    fn add_bitmap(layer: &Layer) -> TransactionActionBranch {
+        let add = BlockDirtyBitmapAdd::new(layer.node.clone(), BITMAP.to_string())
+            .persistent(layer.format == DiskFormat::Qcow2);
+        TransactionActionBranch::BlockDirtyBitmapAdd(BlockDirtyBitmapAddWrapper::new(add))
+    }
+
+    fn bitmap(layer: &Layer, name: &str) -> BlockDirtyBitmapWrapper {
+        BlockDirtyBitmapWrapper::new(BlockDirtyBitmap::new(layer.node.clone(), name.to_string()))
+    }
+
+    fn merge(layer: &Layer, from: &str, into: &str) -> TransactionActionBranch {
+        let merge = BlockDirtyBitmapMerge::new(
+            layer.node.clone(),
+            into.to_string(),
+            vec![BlockDirtyBitmapOrStr::Local(from.to_string())],
+        );
+        TransactionActionBranch::BlockDirtyBitmapMerge(BlockDirtyBitmapMergeWrapper::new(merge))
+    }
+
+    fn backup(job_id: &str, layer: &Layer, target: &str, sync: MirrorSyncMode) -> BlockdevBackup {
+        BlockdevBackup::new(layer.node.clone(), sync, target.to_string())
+            .job_id(job_id.to_string())
+            .auto_dismiss(false)
+    }
+
+    /// Start the job `job_id` copying `layer` into the node `target`. Nodes
+    /// without `BITMAP` can only get a full backup, which adds one.
+    pub fn push(
+        job_id: &str,
+        layer: &Layer,
+        target: &str,
+        kind: BackupKind,
+        has_bitmap: bool,
+    ) -> QMPCommand {
+        let sync = match kind {
+            BackupKind::Full => MirrorSyncMode::Full,
+            BackupKind::Incremental => MirrorSyncMode::Incremental,
+        };
+        let mut backup = backup(job_id, layer, target, sync);
+        let mut actions = Vec::new();
+        if has_bitmap {
+            // Left as it was unless the backup succeeds
+            backup = backup
+                .bitmap(BITMAP.to_string())
+                .bitmap_mode(BitmapSyncMode::OnSuccess);
+        } else {
+            actions.push(add_bitmap(layer));
+        }
+        actions.push(TransactionActionBranch::BlockdevBackup(
+            BlockdevBackupWrapper::new(backup),
+        ));
+        transaction(actions)
+    }
+
+    /// Start the job `job_id` keeping the point in time view of `layer` in the
+    /// node `fleecing`: whatever the guest is about to overwrite is copied
+    /// there first. For incremental exports, what changed up to now moves from
+    /// `BITMAP` to `EXPORT_BITMAP`.
+    pub fn fleece(
+        job_id: &str,
+        layer: &Layer,
+        fleecing: &str,
+        kind: BackupKind,
+        has_bitmap: bool,
+    ) -> QMPCommand {
+        let mut actions = Vec::new();
+        match kind {
+            BackupKind::Full if has_bitmap => {
+                actions.push(TransactionActionBranch::BlockDirtyBitmapClear(bitmap(
+                    layer, BITMAP,
+                )));
+            }
+            BackupKind::Full => actions.push(add_bitmap(layer)),
+            BackupKind::Incremental => {
+                let frozen =
+                    BlockDirtyBitmapAdd::new(layer.node.clone(), EXPORT_BITMAP.to_string())
+                        .disabled(true);
+                actions.push(TransactionActionBranch::BlockDirtyBitmapAdd(
+                    BlockDirtyBitmapAddWrapper::new(frozen),
+                ));
+                actions.push(merge(layer, BITMAP, EXPORT_BITMAP));
+                actions.push(TransactionActionBranch::BlockDirtyBitmapClear(bitmap(
+                    layer, BITMAP,
+                )));
+            }
+        }
+        actions.push(TransactionActionBranch::BlockdevBackup(
+            BlockdevBackupWrapper::new(backup(job_id, layer, fleecing, MirrorSyncMode::None)),
+        ));
+        transaction(actions)
+    }
+
+    /// Settle the bitmaps of `layer` once an export ended. Unless the backup
+    /// tool `pulled` all of it, the changes it held go back into `BITMAP`, or,
+    /// for a full export, `BITMAP` goes so the next backup is a full one.
+    pub fn unfreeze(layer: &Layer, kind: BackupKind, pulled: bool) -> Option<QMPCommand> {
+        let remove = |name| TransactionActionBranch::BlockDirtyBitmapRemove(bitmap(layer, name));
+        let actions = match (kind, pulled) {
+            (BackupKind::Full, true) => return None,
+            (BackupKind::Full, false) => vec![remove(BITMAP)],
+            (BackupKind::Incremental, true) => vec![remove(EXPORT_BITMAP)],
+            (BackupKind::Incremental, false) => {
+                vec![merge(layer, EXPORT_BITMAP, BITMAP), remove(EXPORT_BITMAP)]
+            }
+        };
+        Some(transaction(actions))
+    }
+
+    /// Drop `BITMAP` from `layer`, after a full backup that added it failed
+    pub fn remove_bitmap(layer: &Layer) -> QMPCommand {
+        transaction(vec![TransactionActionBranch::BlockDirtyBitmapRemove(
+            bitmap(layer, BITMAP),
+        )])
+    }
+
+    /// Start the NBD server of the VM on the unix socket `socket`
+    pub fn start_nbd(socket: &Path) -> QMPCommand {
+        let socket = UnixSocketAddress::new(socket.to_string_lossy().into_owned());
+        let addr = SocketAddressLegacy::new(SocketAddressLegacyBranch::Unix(
+            UnixSocketAddressWrapper::new(socket),
+        ));
+        QMPCommand::new(&NbdServerStart::new(addr))
+    }
+
+    /// Export the node `fleecing` of `disk` read only, along with
+    /// `EXPORT_BITMAP` of `layer` for incremental exports
+    pub fn export(disk: &str, layer: &Layer, fleecing: &str, kind: BackupKind) -> QMPCommand {
+        let mut nbd = BlockExportOptionsNbd::new().name(export_id(disk));
+        if kind == BackupKind::Incremental {
+            let frozen = BlockDirtyBitmap::new(layer.node.clone(), EXPORT_BITMAP.to_string());
+            nbd = nbd.bitmaps(vec![BlockDirtyBitmapOrStr::External(frozen)]);
+        }
+        let export = BlockExportOptions::new(
+            export_id(disk),
+            fleecing.to_string(),
+            BlockExportOptionsBranch::Nbd(nbd),
+        )
+        .writable(false);
+        QMPCommand::new(&BlockExportAdd::new(export))
+    }
+
+    /// Remove the export of `disk`. It is only gone once QEMU sent
+    /// `BLOCK_EXPORT_DELETED` for it.
+    pub fn unexport(disk: &str) -> QMPCommand {
+        QMPCommand::new(&BlockExportDel::new(export_id(disk)))
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::*;
+    use crate::qmp::qapi::QMPCommand;
+    use crate::spec::{DiskBus, DiskFormat, DiskProtocol, DiskSpec};
+    use crate::storage::DiskChain;
+    use serde_json::json;
+
+    fn layer() -> crate::storage::Layer {
+        let disk = DiskSpec {
+            id: "data".into(),
+            path: "/var/lib/qrab/data.qcow2".into(),
+            protocol: DiskProtocol::File,
+            format: DiskFormat::Qcow2,
+            bus: DiskBus::Virtio,
+            read_only: false,
+            boot_index: None,
+            throttle: None,
+        };
+        DiskChain::new(&disk).active().clone()
+    }
+
+    #[test]
+    fn test_kind() {
+        let full = Backup {
+            disk: "data".into(),
+            kind: BackupKind::Full,
+            target: BackupTarget::Image("/backup/data-0.qcow2".into()),
+            started_at: Utc::now(),
+        };
+        assert_eq!(kind("data", None, false).unwrap(), BackupKind::Full);
+        assert_eq!(
+            kind("data", Some(&full), true).unwrap(),
+            BackupKind::Incremental
+        );
+        assert!(kind("data", Some(&full), false).is_err());
+        assert!(kind("root", Some(&full), true).is_err());
+    }
+
+    #[test]
+    fn test_push() {
+        assert_eq!(
+            serde_json::to_value(push(
+                "bak-data",
+                &layer(),
+                "bak-data",
+                BackupKind::Full,
+                false
+            ))
+            .unwrap(),
+            json!({"execute": "transaction", "arguments": { "actions": [
+                { "type": "block-dirty-bitmap-add", "data": {
+                    "node": "format-data", "name": "backup", "persistent": true,
+                }},
+                { "type": "blockdev-backup", "data": {
+                    "job-id": "bak-data", "device": "format-data", "sync": "full",
+                    "auto-dismiss": false, "target": "bak-data",
+                }},
+            ]}})
+        );
+        assert_eq!(
+            serde_json::to_value(push(
+                "bak-data",
+                &layer(),
+                "bak-data",
+                BackupKind::Incremental,
+                true
+            ))
+            .unwrap(),
+            json!({"execute": "transaction", "arguments": { "actions": [
+                { "type": "blockdev-backup", "data": {
+                    "job-id": "bak-data", "device": "format-data", "sync": "incremental",
+                    "bitmap": "backup", "bitmap-mode": "on-success", "auto-dismiss": false,
+                    "target": "bak-data",
+                }},
+            ]}})
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let layer = layer();
+        let actions = |cmd: QMPCommand| -> Vec<String> {
+            let cmd = serde_json::to_value(cmd).unwrap();
+            let actions = cmd["arguments"]["actions"].as_array().unwrap().clone();
+            actions
+                .iter()
+                .map(|action| action["type"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            actions(fleece(
+                "pit-data",
+                &layer,
+                "pit-data",
+                BackupKind::Incremental,
+                true
+            )),
+            vec![
+                "block-dirty-bitmap-add",
+                "block-dirty-bitmap-merge",
+                "block-dirty-bitmap-clear",
+                "blockdev-backup",
+            ]
+        );
+        assert_eq!(
+            actions(unfreeze(&layer, BackupKind::Incremental, false).unwrap()),
+            vec!["block-dirty-bitmap-merge", "block-dirty-bitmap-remove"]
+        );
+        assert!(unfreeze(&layer, BackupKind::Full, true).is_none());
+        assert_eq!(
+            serde_json::to_value(export("data", &layer, "pit-data", BackupKind::Incremental))
+                .unwrap(),
+            json!({"execute": "block-export-add", "arguments": {
+                "type": "nbd", "id": "export-data", "node-name": "pit-data", "writable": false,
+                "name": "export-data",
+                "bitmaps": [{ "node": "format-data", "name": "backup-export" }],
+            }})
+        );
+    }
+}
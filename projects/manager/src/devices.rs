@@ -0,0 +1,222 @@
+//! QMP commands that build a VM from its `VmSpec`
+//!
+//! `VM::start` sends these while QEMU waits in `--preconfig`, before any guest
+//! code runs: the memory and the backends of the disks and NICs. The board
+//! and its buses only come to be when QEMU leaves preconfig, so the guest
+//! devices are given on the command line, where QEMU creates them right then,
+//! on top of the backends. The per device builders do not depend on that
+//! stage, so the same commands can hotplug a NIC into a running VM. Disks are
+//! built by `manager::storage` and NICs by `manager::network`.
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::network;
+use crate::qapi::{
+    Command, Empty, MemoryBackendProperties, NumaCpuOptions, NumaNodeOptions, NumaOptions,
+    NumaOptionsBranch, ObjectAdd, ObjectOptions, ObjectOptionsBranch, SetNumaNode,
+};
+use crate::qmp::qapi::{Arguments, QMPCommand};
+use crate::spec::{DiskBus, VmSpec, MEMORY_BACKEND};
+use crate::storage;
+
+/// `device_add`, which QEMU's schema leaves untyped: the properties a device
+/// takes depend on its `driver`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceAdd(pub Arguments);
+
+impl Command for DeviceAdd {
+    const NAME: &'static str = "device_add";
+    type Returns = Empty;
+}
+
+impl DeviceAdd {
+    pub(crate) fn new(properties: Value) -> Self {
+        match properties {
+            Value::Object(properties) => Self(properties),
+            _ => unreachable!("device properties are always an object"),
+        }
+    }
+}
+
+fn memory_backend(id: &str, memory_mib: u64) -> QMPCommand {
+    let memory = MemoryBackendProperties::new(memory_mib * 1024 * 1024).prealloc(true);
+    let backend = ObjectOptions::new(
+        id.to_string(),
+        ObjectOptionsBranch::MemoryBackendRam(memory),
+    );
+    QMPCommand::new(&ObjectAdd::new(backend))
+}
+
+/// The guest memory, and the NUMA nodes holding it when there are any
+pub fn memory(spec: &VmSpec) -> Vec<QMPCommand> {
+    if spec.numa.is_empty() {
+        return vec![memory_backend(MEMORY_BACKEND, spec.memory_mib)];
+    }
+    let mut commands = Vec::new();
+    for (nodeid, node) in spec.numa.iter().enumerate() {
+        let memdev = format!("ram-node{}", nodeid);
+        commands.push(memory_backend(&memdev, node.memory_mib));
+        let options = NumaNodeOptions::new().nodeid(nodeid as u16).memdev(memdev);
+        commands.push(QMPCommand::new(&SetNumaNode::new(NumaOptions::new(
+            NumaOptionsBranch::Node(options),
+        ))));
+        for socket in &node.sockets {
+            let options = NumaCpuOptions::new()
+                .node_id(nodeid as i64)
+                .socket_id(i64::from(*socket));
+            commands.push(QMPCommand::new(&SetNumaNode::new(NumaOptions::new(
+                NumaOptionsBranch::Cpu(options),
+            ))));
+        }
+    }
+    commands
+}
+
+/// Everything to send during preconfig, in order. `tap_fds` holds the fd
+/// QEMU inherited for each NIC with a tap backend, by NIC id.
+pub fn preconfig(spec: &VmSpec, tap_fds: &HashMap<String, String>) -> Vec<QMPCommand> {
+    let mut commands = memory(spec);
+    commands.extend(spec.disks.iter().flat_map(storage::backend));
+    for nic in &spec.nics {
+        let tap_fd = tap_fds.get(&nic.id).map(String::as_str);
+        commands.push(network::netdev(nic, tap_fd));
+    }
+    commands
+}
+
+/// The guest devices of the disks and NICs of `spec` as `-device` options,
+/// taking the same JSON as `device_add`
+pub fn args(spec: &VmSpec) -> Vec<String> {
+    let mut devices = Vec::new();
+    if spec.disks.iter().any(|disk| disk.bus == DiskBus::Scsi) {
+        devices.push(storage::scsi_controller());
+    }
+    devices.extend(spec.disks.iter().map(storage::device));
+    devices.extend(spec.nics.iter().map(network::device));
+
+    let mut args = Vec::new();
+    for DeviceAdd(properties) in devices {
+        args.push("-device".to_string());
+        args.push(Value::Object(properties).to_string());
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::{
+        CpuSpec, DiskFormat, DiskProtocol, DiskSpec, NetBackend, NicModel, NicSpec, NumaNodeSpec,
+    };
+    use serde_json::json;
+
+    fn to_json(commands: Vec<QMPCommand>) -> Vec<Value> {
+        commands
+            .iter()
+            .map(|cmd| serde_json::to_value(cmd).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_preconfig() {
+        let spec = VmSpec {
+            memory_mib: 512,
+            disks: vec![DiskSpec {
+                id: "root".into(),
+                path: "/var/lib/qrab/root.qcow2".into(),
+                protocol: DiskProtocol::File,
+                format: DiskFormat::Qcow2,
+                bus: DiskBus::Virtio,
+                read_only: false,
+                boot_index: Some(0),
+                throttle: None,
+            }],
+            nics: vec![NicSpec {
+                id: "eth0".into(),
+                model: NicModel::VirtioNet,
+                mac: Some("52:54:00:12:34:56".into()),
+                backend: NetBackend::Tap {
+                    ifname: "tap0".into(),
+                },
+            }],
+            ..Default::default()
+        };
+        let tap_fds = HashMap::from([("eth0".to_string(), "7".to_string())]);
+        let devices: Vec<Value> = args(&spec)
+            .chunks(2)
+            .map(|arg| {
+                assert_eq!(arg[0], "-device");
+                serde_json::from_str(&arg[1]).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            devices,
+            vec![
+                json!({
+                    "driver": "virtio-blk-pci", "drive": "format-root", "id": "root",
+                    "bootindex": 0,
+                }),
+                json!({
+                    "driver": "virtio-net-pci", "netdev": "net-eth0", "id": "eth0",
+                    "mac": "52:54:00:12:34:56",
+                }),
+            ]
+        );
+        assert_eq!(
+            to_json(preconfig(&spec, &tap_fds)),
+            vec![
+                json!({"execute": "object-add", "arguments": {
+                    "qom-type": "memory-backend-ram", "id": "ram0",
+                    "size": 536870912u64, "prealloc": true,
+                }}),
+                json!({"execute": "blockdev-add", "arguments": {
+                    "node-name": "format-root", "driver": "qcow2", "read-only": false,
+                    "file": {
+                        "driver": "file", "node-name": "file-root", "read-only": false,
+                        "filename": "/var/lib/qrab/root.qcow2",
+                    },
+                }}),
+                json!({"execute": "netdev_add", "arguments": {
+                    "type": "tap", "id": "net-eth0", "fd": "7",
+                }}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numa_memory() {
+        let spec = VmSpec {
+            memory_mib: 2048,
+            cpu: CpuSpec {
+                sockets: 2,
+                ..Default::default()
+            },
+            numa: vec![
+                NumaNodeSpec {
+                    memory_mib: 1024,
+                    sockets: vec![0],
+                },
+                NumaNodeSpec {
+                    memory_mib: 1024,
+                    sockets: vec![1],
+                },
+            ],
+            ..Default::default()
+        };
+        let commands = to_json(memory(&spec));
+        assert_eq!(commands.len(), 6);
+        assert_eq!(
+            commands[1],
+            json!({"execute": "set-numa-node", "arguments": {
+                "type": "node", "nodeid": 0, "memdev": "ram-node0",
+            }})
+        );
+        assert_eq!(
+            commands[5],
+            json!({"execute": "set-numa-node", "arguments": {
+                "type": "cpu", "node-id": 1, "socket-id": 1,
+            }})
+        );
+    }
+}
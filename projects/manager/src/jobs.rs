@@ -0,0 +1,97 @@
+//! Long running jobs QEMU works on in the background
+//!
+//! Image creation, commit, stream, backup and the other block jobs share one
+//! interface: each is started by its own command with a `job-id`, announces
+//! every status it moves through with `JOB_STATUS_CHANGE` and is listed with
+//! its progress by `query-jobs`. Jobs that copy data until told otherwise,
+//! like an active commit, wait in `ready` for a `job-complete`. The manager
+//! starts jobs without `auto-dismiss`, so a job that failed stays `concluded`
+//! until its error was read and it got a `job-dismiss`.
+use crate::qapi::{self, Event as _};
+pub use crate::qapi::{JobInfo, JobStatus, JobType};
+use crate::qmp::qapi::{Event, QMPCommand};
+
+/// The status the job `id` moved to, if `event` is about it
+pub fn status_change(event: &Event, id: &str) -> Option<JobStatus> {
+    if event.event != qapi::JobStatusChangeEvent::NAME {
+        return None;
+    }
+    let change: qapi::JobStatusChangeEvent = serde_json::from_value(event.data.clone()?).ok()?;
+    (change.id == id).then_some(change.status)
+}
+
+/// How far along `job` is, from 0 to 1. Jobs that do not know how much work
+/// is ahead report nothing.
+pub fn progress(job: &JobInfo) -> Option<f64> {
+    if job.total_progress <= 0 {
+        return None;
+    }
+    Some(job.current_progress as f64 / job.total_progress as f64)
+}
+
+pub fn query() -> qapi::QueryJobs {
+    qapi::QueryJobs::new()
+}
+
+/// Abort the job `id`. It still has to be dismissed once concluded.
+pub fn cancel(id: &str) -> QMPCommand {
+    QMPCommand::new(&qapi::JobCancel::new(id.to_string()))
+}
+
+/// Let the job `id` finish once it is ready
+pub fn complete(id: &str) -> QMPCommand {
+    QMPCommand::new(&qapi::JobComplete::new(id.to_string()))
+}
+
+/// Forget the concluded job `id`
+pub fn dismiss(id: &str) -> QMPCommand {
+    QMPCommand::new(&qapi::JobDismiss::new(id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn change(id: &str, status: &str) -> Event {
+        serde_json::from_value(json!({
+            "timestamp": { "seconds": 0, "microseconds": 0 },
+            "event": "JOB_STATUS_CHANGE",
+            "data": { "id": id, "status": status },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_status_change() {
+        assert_eq!(
+            status_change(&change("commit-data", "ready"), "commit-data"),
+            Some(JobStatus::Ready)
+        );
+        assert_eq!(
+            status_change(&change("stream-root", "ready"), "commit-data"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let job: JobInfo = serde_json::from_value(json!({
+            "id": "stream-data", "type": "stream", "status": "running",
+            "current-progress": 256, "total-progress": 1024,
+        }))
+        .unwrap();
+        assert_eq!(progress(&job), Some(0.25));
+        assert_eq!(
+            progress(&JobInfo {
+                total_progress: 0,
+                ..job
+            }),
+            None
+        );
+        assert_eq!(
+            serde_json::to_value(dismiss("stream-data")).unwrap(),
+            json!({ "execute": "job-dismiss", "arguments": { "id": "stream-data" } })
+        );
+    }
+}
@@ -1,3 +1,18 @@
+pub mod backup;
+#[cfg(feature = "runtime")]
+pub mod devices;
+#[cfg(feature = "runtime")]
+pub mod jobs;
+pub mod lifecycle;
+#[cfg(feature = "runtime")]
+pub mod network;
+pub mod qapi;
+pub mod spec;
+#[cfg(feature = "runtime")]
+pub mod storage;
+#[cfg(feature = "runtime")]
 pub mod utils;
+#[cfg(feature = "runtime")]
 pub mod vm;
+#[cfg(feature = "runtime")]
 pub use qmp;
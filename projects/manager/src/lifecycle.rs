@@ -0,0 +1,307 @@
+//! The lifecycle of a VM as the manager sees it
+//!
+//! ```text
+//! Defined -> Starting -> Preconfig -> Running <-> Paused -> Stopping -> Stopped
+//!                                        |          |                   Crashed
+//!                                        +-> Migrating <-+
+//! ```
+//!
+//! Transitions come from the manager's own actions (`start`, `stop`, ...) and
+//! from QMP events such as `STOP`, `RESUME` and `SHUTDOWN`. `Crashed` can be
+//! reached from any state where QEMU is running, when it goes away without
+//! being asked to. A guest powering off makes the VM `Stopping` until QEMU
+//! exits, which is only `Stopped` and not a crash.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VmState {
+    /// Known to the manager, never started
+    Defined,
+    /// QEMU is being spawned or reattached to
+    Starting,
+    /// QEMU waits in `--preconfig`; no guest code has run
+    Preconfig,
+    Running,
+    Paused,
+    Stopping,
+    Stopped,
+    /// QEMU or the guest died without being asked to
+    Crashed,
+    Migrating,
+}
+
+impl VmState {
+    /// Whether a QEMU process is expected to exist in this state
+    pub fn is_active(&self) -> bool {
+        !matches!(self, Self::Defined | Self::Stopped | Self::Crashed)
+    }
+
+    pub fn can_transition_to(&self, to: VmState) -> bool {
+        use VmState::*;
+        match (self, to) {
+            (Defined | Stopped | Crashed, Starting) => true,
+            (Starting, Preconfig | Running | Paused | Migrating | Stopping) => true,
+            (Preconfig, Running) => true,
+            (Running, Paused) | (Paused, Running) => true,
+            (Running | Paused, Migrating) => true,
+            (Migrating, Running | Paused) => true,
+            // A crashed guest may leave QEMU behind that still has to go, and
+            // so may an earlier manager for a VM this one never started
+            (Preconfig | Running | Paused | Migrating | Crashed, Stopping) => true,
+            (Defined | Stopped, Stopping) => true,
+            (Stopping, Stopped) => true,
+            (from, Crashed) => from.is_active(),
+            _ => false,
+        }
+    }
+
+    /// The state QEMU reports through `query-status`, used when reattaching to
+    /// a QEMU started by another manager
+    pub fn from_run_status(status: &str) -> Self {
+        match status {
+            "prelaunch" => Self::Preconfig,
+            "running" => Self::Running,
+            "inmigrate" | "postmigrate" | "finish-migrate" => Self::Migrating,
+            "shutdown" => Self::Stopping,
+            "guest-panicked" | "internal-error" | "io-error" => Self::Crashed,
+            // debug, paused, restore-vm, save-vm, suspended, watchdog, colo
+            _ => Self::Paused,
+        }
+    }
+
+    /// The state a QMP event moves a VM in, if it moves it at all
+    pub fn from_event(event: &str, data: Option<&serde_json::Value>) -> Option<Self> {
+        let field = |name: &str| data.and_then(|data| data.get(name)?.as_str());
+        match event {
+            "STOP" | "SUSPEND" | "SUSPEND_DISK" => Some(Self::Paused),
+            "RESUME" | "WAKEUP" => Some(Self::Running),
+            "SHUTDOWN" if field("reason") == Some("guest-panic") => Some(Self::Crashed),
+            "SHUTDOWN" => Some(Self::Stopping),
+            "GUEST_PANICKED" => Some(Self::Crashed),
+            "MIGRATION" => match field("status")? {
+                "setup" | "active" | "pre-switchover" | "device" => Some(Self::Migrating),
+                "completed" => Some(Self::Paused),
+                "failed" | "cancelled" => Some(Self::Running),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VmState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", state.as_str().unwrap_or_default())
+    }
+}
+
+/// A transition the lifecycle does not allow, such as starting a running VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: VmState,
+    pub to: VmState,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a {} VM can not become {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    pub from: VmState,
+    pub to: VmState,
+    /// What caused the change: the manager action, like `start`, or the QMP
+    /// event, like `STOP`
+    pub cause: String,
+}
+
+#[cfg(feature = "runtime")]
+pub(crate) use tracker::Lifecycle;
+
+#[cfg(feature = "runtime")]
+mod tracker {
+    use super::{InvalidTransition, StateChange, VmState};
+    use crate::qmp::qapi::Event;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::broadcast;
+
+    /// The current state of a VM, shared by the `VM` and the tasks following
+    /// its QMP events and its QEMU process
+    #[derive(Debug, Clone)]
+    pub(crate) struct Lifecycle {
+        state: Arc<Mutex<VmState>>,
+        changes: broadcast::Sender<StateChange>,
+    }
+
+    impl Lifecycle {
+        pub(crate) fn new() -> Self {
+            let (changes, _) = broadcast::channel(100);
+            Self {
+                state: Arc::new(Mutex::new(VmState::Defined)),
+                changes,
+            }
+        }
+
+        pub(crate) fn state(&self) -> VmState {
+            *self.state.lock().unwrap()
+        }
+
+        pub(crate) fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+            self.changes.subscribe()
+        }
+
+        /// Move to `to`. Staying in the current state is not a transition and
+        /// is always allowed.
+        pub(crate) fn transition(&self, to: VmState, cause: &str) -> Result<(), InvalidTransition> {
+            let mut state = self.state.lock().unwrap();
+            let from = *state;
+            if from == to {
+                return Ok(());
+            }
+            if !from.can_transition_to(to) {
+                return Err(InvalidTransition { from, to });
+            }
+            *state = to;
+            // Having no subscribers is not an error
+            let _ = self.changes.send(StateChange {
+                from,
+                to,
+                cause: cause.to_string(),
+            });
+            Ok(())
+        }
+
+        /// Follow a QMP event. Events that do not fit the current state, like
+        /// the `STOP` QEMU sends after a guest shut down, are ignored.
+        pub(crate) fn on_event(&self, event: &Event) {
+            if let Some(to) = VmState::from_event(&event.event, event.data.as_ref()) {
+                let _ = self.transition(to, &event.event);
+            }
+        }
+
+        /// QEMU exited. Unless it was being stopped, that is a crash.
+        pub(crate) fn on_exit(&self) {
+            let to = match self.state() {
+                VmState::Stopping => VmState::Stopped,
+                _ => VmState::Crashed,
+            };
+            let _ = self.transition(to, "exit");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_transitions() {
+        use VmState::*;
+        assert!(Defined.can_transition_to(Starting));
+        assert!(Running.can_transition_to(Paused));
+        assert!(Paused.can_transition_to(Running));
+        assert!(Running.can_transition_to(Crashed));
+        assert!(!Running.can_transition_to(Starting));
+        assert!(!Stopped.can_transition_to(Crashed));
+        assert!(!Stopping.can_transition_to(Paused));
+        assert!(Defined.can_transition_to(Stopping));
+        assert!(!Defined.can_transition_to(Running));
+    }
+
+    #[test]
+    fn test_from_event() {
+        assert_eq!(VmState::from_event("STOP", None), Some(VmState::Paused));
+        assert_eq!(VmState::from_event("WAKEUP", None), Some(VmState::Running));
+        let data = json!({ "guest": true, "reason": "guest-panic" });
+        assert_eq!(
+            VmState::from_event("SHUTDOWN", Some(&data)),
+            Some(VmState::Crashed)
+        );
+        let data = json!({ "guest": true, "reason": "guest-shutdown" });
+        assert_eq!(
+            VmState::from_event("SHUTDOWN", Some(&data)),
+            Some(VmState::Stopping)
+        );
+        let data = json!({ "status": "active" });
+        assert_eq!(
+            VmState::from_event("MIGRATION", Some(&data)),
+            Some(VmState::Migrating)
+        );
+        assert_eq!(VmState::from_event("POWERDOWN", None), None);
+    }
+
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn test_lifecycle() {
+        let lifecycle = Lifecycle::new();
+        let mut changes = lifecycle.subscribe();
+        lifecycle.transition(VmState::Starting, "start").unwrap();
+        lifecycle.transition(VmState::Preconfig, "start").unwrap();
+        lifecycle.transition(VmState::Running, "start").unwrap();
+        // QEMU confirms leaving preconfig with a `RESUME`
+        lifecycle.transition(VmState::Running, "RESUME").unwrap();
+        let err = lifecycle
+            .transition(VmState::Starting, "start")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a running VM can not become starting".to_string()
+        );
+
+        lifecycle.transition(VmState::Stopping, "stop").unwrap();
+        lifecycle.on_exit();
+        assert_eq!(lifecycle.state(), VmState::Stopped);
+        // `VM::stop` finishing after QEMU is gone changes nothing
+        lifecycle.transition(VmState::Stopped, "stop").unwrap();
+
+        let causes: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok())
+            .map(|change| (change.to, change.cause))
+            .collect();
+        assert_eq!(
+            causes,
+            vec![
+                (VmState::Starting, "start".to_string()),
+                (VmState::Preconfig, "start".to_string()),
+                (VmState::Running, "start".to_string()),
+                (VmState::Stopping, "stop".to_string()),
+                (VmState::Stopped, "exit".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(feature = "runtime")]
+    #[test]
+    fn test_guest_shutdown() {
+        let event = |name: &str, data: serde_json::Value| -> crate::qmp::qapi::Event {
+            serde_json::from_value(json!({
+                "event": name,
+                "data": data,
+                "timestamp": { "seconds": 0, "microseconds": 0 },
+            }))
+            .unwrap()
+        };
+        let lifecycle = Lifecycle::new();
+        lifecycle.transition(VmState::Starting, "start").unwrap();
+        lifecycle.transition(VmState::Running, "start").unwrap();
+
+        // The guest powers off; QEMU pauses in the `shutdown` run state and
+        // is then ended by the manager
+        let shutdown = json!({ "guest": true, "reason": "guest-shutdown" });
+        lifecycle.on_event(&event("SHUTDOWN", shutdown));
+        lifecycle.on_event(&event("STOP", json!({})));
+        assert_eq!(lifecycle.state(), VmState::Stopping);
+        let signal = json!({ "guest": false, "reason": "host-signal" });
+        lifecycle.on_event(&event("SHUTDOWN", signal));
+        lifecycle.on_exit();
+        assert_eq!(lifecycle.state(), VmState::Stopped);
+        lifecycle.transition(VmState::Starting, "start").unwrap();
+    }
+}
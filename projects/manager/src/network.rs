@@ -0,0 +1,308 @@
+//! Guest NICs and the host side of their networks
+//!
+//! Each NIC is a guest device on top of a QEMU network backend. The backends
+//! of the spec are added with `netdev_add` during preconfig and their devices
+//! are on the command line; a NIC hotplugged into a running guest gets both
+//! with `netdev_add` and `device_add`.
+//!
+//! QEMU runs without privileges, so it can not open tap devices itself. The
+//! manager opens them and hands QEMU the fd: inherited at spawn for the NICs
+//! of the spec, or sent along with `getfd` for a NIC hotplugged later.
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use crate::devices::DeviceAdd;
+use crate::qapi::{
+    Closefd, DeviceDel, Getfd, Netdev, NetdevAdd, NetdevBranch, NetdevDel, NetdevStreamOptions,
+    NetdevTapOptions, NetdevUserOptions, QapiString, SocketAddress, SocketAddressBranch,
+    UnixSocketAddress,
+};
+use crate::qmp::qapi::QMPCommand;
+use crate::spec::{NetBackend, NicModel, NicSpec, PortForward, PortProtocol, VmSpec};
+
+/// `_IOW('T', 202, int)` from `linux/if_tun.h`
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+
+/// The id of the network backend of the NIC `id`
+pub fn netdev_id(id: &str) -> String {
+    format!("net-{}", id)
+}
+
+/// The name the tap fd of the NIC `id` is passed to QEMU under by `getfd`
+pub fn tap_fd_name(id: &str) -> String {
+    format!("tap-{}", id)
+}
+
+/// Give every NIC without a MAC address one derived from the VM and NIC ids,
+/// skipping the addresses already taken. Once recorded in the spec the address
+/// stays, even if the hash would come out differently in another build.
+///
+/// Only the NICs of this VM are checked. The 24 bits of the hash can still
+/// collide with the address of another VM, which breaks both guests when
+/// they share a network. With a few thousand NICs on one network that gets
+/// likely, and those should get their MAC addresses set in the spec.
+pub fn assign_macs(vm: &str, nics: &mut [NicSpec]) {
+    let mut taken: HashSet<String> = nics
+        .iter()
+        .filter_map(|nic| nic.mac.as_ref().map(|mac| mac.to_ascii_lowercase()))
+        .collect();
+    for nic in nics.iter_mut().filter(|nic| nic.mac.is_none()) {
+        let mut hasher = DefaultHasher::new();
+        (vm, &nic.id).hash(&mut hasher);
+        let mut suffix = hasher.finish() as u32 & 0xff_ffff;
+        loop {
+            let [_, a, b, c] = suffix.to_be_bytes();
+            let mac = format!("52:54:00:{:02x}:{:02x}:{:02x}", a, b, c);
+            if taken.insert(mac.clone()) {
+                nic.mac = Some(mac);
+                break;
+            }
+            suffix = (suffix + 1) & 0xff_ffff;
+        }
+    }
+}
+
+/// Open the tap device `ifname`, creating it when it does not exist. Creating
+/// one takes `CAP_NET_ADMIN`, opening a persistent tap owned by the user of
+/// the manager does not. A tap created here is gone once the last fd to it
+/// is closed, which is when QEMU exits.
+pub fn open_tap(ifname: &str) -> Result<OwnedFd> {
+    let tun = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")?;
+    // SAFETY: `ifreq` is plain data, all zeroes is a valid value
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    // Validated to be shorter than `ifr_name`, so the name stays terminated
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(ifname.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    ifr.ifr_ifru.ifru_flags = (libc::IFF_TAP | libc::IFF_NO_PI | libc::IFF_VNET_HDR) as _;
+    // SAFETY: TUNSETIFF reads and writes an `ifreq`, which `ifr` is
+    let res = unsafe { libc::ioctl(tun.as_raw_fd(), TUNSETIFF, &mut ifr) };
+    if res < 0 {
+        let err = io::Error::last_os_error();
+        return Err(anyhow! {"failed to open tap device {}: {}", ifname, err});
+    }
+    Ok(tun.into())
+}
+
+/// Open the taps of every NIC of `spec` with a tap backend, by NIC id
+pub fn open_taps(spec: &VmSpec) -> Result<Vec<(String, OwnedFd)>> {
+    let mut taps = Vec::new();
+    for nic in &spec.nics {
+        if let NetBackend::Tap { ifname } = &nic.backend {
+            taps.push((nic.id.clone(), open_tap(ifname)?));
+        }
+    }
+    Ok(taps)
+}
+
+fn hostfwd(fwd: &PortForward) -> String {
+    let protocol = match fwd.protocol {
+        PortProtocol::Tcp => "tcp",
+        PortProtocol::Udp => "udp",
+    };
+    let host_addr = fwd.host_addr.map(|a| a.to_string()).unwrap_or_default();
+    format!(
+        "{}:{}:{}-:{}",
+        protocol, host_addr, fwd.host_port, fwd.guest_port
+    )
+}
+
+/// The network backend of `nic`. `tap_fd` is the fd of its tap device as
+/// QEMU knows it, a number or a `getfd` name; without one QEMU has to open
+/// the tap itself.
+pub fn netdev(nic: &NicSpec, tap_fd: Option<&str>) -> QMPCommand {
+    let netdev = match &nic.backend {
+        NetBackend::User { port_forwards } => {
+            let mut user = NetdevUserOptions::new();
+            if !port_forwards.is_empty() {
+                let hostfwd = port_forwards
+                    .iter()
+                    .map(|fwd| QapiString::new(hostfwd(fwd)))
+                    .collect();
+                user = user.hostfwd(hostfwd);
+            }
+            NetdevBranch::User(user)
+        }
+        NetBackend::Passt { socket } => {
+            let path = socket.to_string_lossy().into_owned();
+            let addr = SocketAddress::new(SocketAddressBranch::Unix(UnixSocketAddress::new(path)));
+            NetdevBranch::Stream(NetdevStreamOptions::new(addr).server(false))
+        }
+        NetBackend::Tap { ifname } => NetdevBranch::Tap(match tap_fd {
+            Some(fd) => NetdevTapOptions::new().fd(fd.to_string()),
+            None => NetdevTapOptions::new()
+                .ifname(ifname.clone())
+                .script("no".to_string())
+                .downscript("no".to_string()),
+        }),
+    };
+    QMPCommand::new(&NetdevAdd::new(Netdev::new(netdev_id(&nic.id), netdev)))
+}
+
+/// The guest device of `nic`
+pub fn device(nic: &NicSpec) -> DeviceAdd {
+    let model = match nic.model {
+        NicModel::VirtioNet => "virtio-net-pci",
+        NicModel::E1000e => "e1000e",
+    };
+    let mut device = json!({ "driver": model, "netdev": netdev_id(&nic.id), "id": nic.id });
+    if let Some(mac) = &nic.mac {
+        device["mac"] = json!(mac);
+    }
+    DeviceAdd::new(device)
+}
+
+/// The network backend and guest device of `nic`, in order
+pub fn attach(nic: &NicSpec, tap_fd: Option<&str>) -> Vec<QMPCommand> {
+    vec![netdev(nic, tap_fd), QMPCommand::new(&device(nic))]
+}
+
+/// Ask the guest to release the NIC `id`. QEMU sends `DEVICE_DELETED` once it
+/// did, then its backend can be removed with `release`.
+pub fn unplug(id: &str) -> QMPCommand {
+    QMPCommand::new(&DeviceDel::new(id.to_string()))
+}
+
+/// Remove the network backend of the NIC `id`
+pub fn release(id: &str) -> QMPCommand {
+    QMPCommand::new(&NetdevDel::new(netdev_id(id)))
+}
+
+/// Name the fd sent along with this command `name`
+pub fn getfd(name: &str) -> QMPCommand {
+    QMPCommand::new(&Getfd::new(name.to_string()))
+}
+
+/// Close the fd named `name` when no backend took it
+pub fn closefd(name: &str) -> QMPCommand {
+    QMPCommand::new(&Closefd::new(name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn to_json(commands: Vec<QMPCommand>) -> Vec<Value> {
+        commands
+            .iter()
+            .map(|cmd| serde_json::to_value(cmd).unwrap())
+            .collect()
+    }
+
+    fn nic(id: &str, backend: NetBackend) -> NicSpec {
+        NicSpec {
+            id: id.into(),
+            model: NicModel::E1000e,
+            mac: None,
+            backend,
+        }
+    }
+
+    #[test]
+    fn test_assign_macs() {
+        let mut nics = vec![
+            nic("eth0", NetBackend::default()),
+            nic("eth1", NetBackend::default()),
+        ];
+        nics[1].mac = Some("52:54:00:00:00:01".into());
+        assign_macs("vm:one", &mut nics);
+        let mac = nics[0].mac.clone().unwrap();
+        assert!(mac.starts_with("52:54:00:"));
+        assert_eq!(nics[1].mac.as_deref(), Some("52:54:00:00:00:01"));
+
+        // The same ids give the same address, other taken ones are skipped
+        let mut again = vec![nic("eth0", NetBackend::default())];
+        assign_macs("vm:one", &mut again);
+        assert_eq!(again[0].mac.as_ref(), Some(&mac));
+        let mut taken = vec![
+            nic("eth1", NetBackend::default()),
+            nic("eth0", NetBackend::default()),
+        ];
+        taken[0].mac = Some(mac.to_ascii_uppercase());
+        assign_macs("vm:one", &mut taken);
+        assert_ne!(taken[1].mac.as_ref(), Some(&mac));
+
+        let mut other = vec![nic("eth0", NetBackend::default())];
+        assign_macs("vm:two", &mut other);
+        assert_ne!(other[0].mac.as_ref(), Some(&mac));
+    }
+
+    #[test]
+    fn test_attach() {
+        let user = nic(
+            "eth0",
+            NetBackend::User {
+                port_forwards: vec![
+                    PortForward {
+                        protocol: PortProtocol::Tcp,
+                        host_addr: Some("127.0.0.1".parse().unwrap()),
+                        host_port: 2222,
+                        guest_port: 22,
+                    },
+                    PortForward {
+                        protocol: PortProtocol::Udp,
+                        host_addr: None,
+                        host_port: 5353,
+                        guest_port: 53,
+                    },
+                ],
+            },
+        );
+        assert_eq!(
+            to_json(attach(&user, None)),
+            vec![
+                json!({"execute": "netdev_add", "arguments": {
+                    "type": "user", "id": "net-eth0",
+                    "hostfwd": [{ "str": "tcp:127.0.0.1:2222-:22" }, { "str": "udp::5353-:53" }],
+                }}),
+                json!({"execute": "device_add", "arguments": {
+                    "driver": "e1000e", "netdev": "net-eth0", "id": "eth0",
+                }}),
+            ]
+        );
+
+        let passt = nic(
+            "eth1",
+            NetBackend::Passt {
+                socket: "/run/qrab/passt.sock".into(),
+            },
+        );
+        assert_eq!(
+            serde_json::to_value(netdev(&passt, None)).unwrap(),
+            json!({"execute": "netdev_add", "arguments": {
+                "type": "stream", "id": "net-eth1", "server": false,
+                "addr": { "type": "unix", "path": "/run/qrab/passt.sock" },
+            }})
+        );
+
+        let tap = nic(
+            "eth2",
+            NetBackend::Tap {
+                ifname: "tap0".into(),
+            },
+        );
+        assert_eq!(
+            serde_json::to_value(netdev(&tap, Some(&tap_fd_name("eth2")))).unwrap(),
+            json!({"execute": "netdev_add", "arguments": {
+                "type": "tap", "id": "net-eth2", "fd": "tap-eth2",
+            }})
+        );
+        assert_eq!(
+            to_json(vec![unplug("eth2"), release("eth2")]),
+            vec![
+                json!({"execute": "device_del", "arguments": { "id": "eth2" }}),
+                json!({"execute": "netdev_del", "arguments": { "id": "net-eth2" }}),
+            ]
+        );
+    }
+}
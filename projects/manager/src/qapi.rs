@@ -0,0 +1,7 @@
+//! Typed QMP commands and events, generated at build time from the QAPI
+//! schema in `qapi/`
+//!
+//! The schema holds the definitions the manager needs, copied from QEMU. To
+//! use another command, copy its definition and the types it depends on from
+//! QEMU's `qapi/` directory into the file of the same name here.
+include!(concat!(env!("OUT_DIR"), "/qapi.rs"));
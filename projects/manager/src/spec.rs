@@ -0,0 +1,1044 @@
+//! Declarative description of a virtual machine
+//!
+//! A `VmSpec` says what the guest should look like, not how QEMU is driven to
+//! get there. It is plain data so it can live in the `vm` database record and
+//! be handed to `manager::vm::VM::new`. The few things QEMU only takes on its
+//! command line come from `VmSpec::args`; memory and devices are built by
+//! `manager::devices`, mostly over QMP during preconfig.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmSpec {
+    pub emulator: EmulatorSpec,
+    pub machine: MachineSpec,
+    pub cpu: CpuSpec,
+    /// Guest RAM in MiB
+    pub memory_mib: u64,
+    /// Guest NUMA nodes. Empty means a single node holding all of the memory.
+    pub numa: Vec<NumaNodeSpec>,
+    pub firmware: Firmware,
+    pub disks: Vec<DiskSpec>,
+    pub nics: Vec<NicSpec>,
+    pub serials: Vec<SerialSpec>,
+    pub display: Display,
+    /// Additional QEMU options, given as option and value pairs. Only the
+    /// options in `EXTRA_ARGS_ALLOWED` are accepted.
+    pub extra_args: Vec<String>,
+}
+
+impl Default for VmSpec {
+    fn default() -> Self {
+        Self {
+            emulator: EmulatorSpec::default(),
+            machine: MachineSpec::default(),
+            cpu: CpuSpec::default(),
+            memory_mib: 1024,
+            numa: Vec::new(),
+            firmware: Firmware::default(),
+            disks: Vec::new(),
+            nics: Vec::new(),
+            serials: Vec::new(),
+            display: Display::default(),
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+/// Which QEMU runs the VM
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmulatorSpec {
+    /// The guest architecture
+    pub arch: Arch,
+    /// An absolute path to a specific QEMU build. Without it
+    /// `qemu-system-<arch>` is looked up in `PATH`.
+    pub binary: Option<PathBuf>,
+}
+
+impl EmulatorSpec {
+    /// The program to execute
+    pub fn binary(&self) -> PathBuf {
+        match &self.binary {
+            Some(binary) => binary.clone(),
+            None => PathBuf::from(format!("qemu-system-{}", self.arch.as_str())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Arch {
+    #[default]
+    #[serde(rename = "x86_64")]
+    X86_64,
+    #[serde(rename = "aarch64")]
+    Aarch64,
+    #[serde(rename = "riscv64")]
+    Riscv64,
+}
+
+impl Arch {
+    /// The name QEMU uses, both in its binary names and in `query-target`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "x86_64",
+            Self::Aarch64 => "aarch64",
+            Self::Riscv64 => "riscv64",
+        }
+    }
+
+    /// The machine type used when the spec does not name one
+    pub fn default_machine(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "q35",
+            Self::Aarch64 | Self::Riscv64 => "virt",
+        }
+    }
+
+    /// The device model of a serial console. Only x86 has ISA ports, the
+    /// `virt` boards get theirs on PCI.
+    fn serial_device(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "isa-serial",
+            Self::Aarch64 | Self::Riscv64 => "pci-serial",
+        }
+    }
+
+    /// The device model of the display
+    fn display_device(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "VGA",
+            Self::Aarch64 | Self::Riscv64 => "virtio-gpu-pci",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineSpec {
+    /// The QEMU machine type, such as `q35` or `pc-q35-8.2`. Without one the
+    /// default of the architecture is used, see `Arch::default_machine`.
+    pub r#type: Option<String>,
+    /// KVM, the default, needs a host of the guest architecture
+    pub accel: Accel,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Accel {
+    #[default]
+    Kvm,
+    Tcg,
+}
+
+impl Accel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kvm => "kvm",
+            Self::Tcg => "tcg",
+        }
+    }
+}
+
+/// The vCPU model and topology. The guest gets `sockets * cores * threads`
+/// vCPUs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CpuSpec {
+    /// The QEMU CPU model; `host` passes the host CPU through and needs KVM
+    pub model: String,
+    pub sockets: u32,
+    pub cores: u32,
+    pub threads: u32,
+}
+
+impl Default for CpuSpec {
+    fn default() -> Self {
+        Self {
+            model: "host".to_string(),
+            sockets: 1,
+            cores: 1,
+            threads: 1,
+        }
+    }
+}
+
+impl CpuSpec {
+    /// The number of vCPUs, or `None` when it does not fit a `u32`
+    pub fn checked_vcpus(&self) -> Option<u32> {
+        self.sockets
+            .checked_mul(self.cores)?
+            .checked_mul(self.threads)
+    }
+
+    /// The number of vCPUs of a validated spec
+    pub fn vcpus(&self) -> u32 {
+        self.checked_vcpus()
+            .expect("validated specs have a vCPU count that fits")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NumaNodeSpec {
+    /// RAM of this node in MiB. The nodes together must hold exactly
+    /// `VmSpec::memory_mib`.
+    pub memory_mib: u64,
+    /// The vCPU sockets, counted from 0, placed in this node
+    #[serde(default)]
+    pub sockets: Vec<u32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Firmware {
+    /// The machine's built in BIOS (SeaBIOS on x86)
+    #[default]
+    Bios,
+    /// UEFI from pflash images. `vars` should be a per-VM copy of the
+    /// variable store template, as the guest writes to it.
+    Uefi { code: PathBuf, vars: PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DiskSpec {
+    /// Unique among the disks of the VM; used as the QEMU device id
+    pub id: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub protocol: DiskProtocol,
+    #[serde(default)]
+    pub format: DiskFormat,
+    #[serde(default)]
+    pub bus: DiskBus,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Boot order among the bootable devices, lowest first
+    #[serde(default)]
+    pub boot_index: Option<u32>,
+    /// I/O limits of the disk. Unlimited when not set.
+    #[serde(default)]
+    pub throttle: Option<ThrottleSpec>,
+}
+
+/// How QEMU reaches the image at `DiskSpec::path`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskProtocol {
+    /// A regular file
+    #[default]
+    File,
+    /// A host block device, such as an LVM volume or a whole disk
+    HostDevice,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskFormat {
+    #[default]
+    Raw,
+    Qcow2,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskBus {
+    #[default]
+    Virtio,
+    /// An emulated SATA disk on the q35 AHCI controller, for guests without
+    /// virtio drivers. Only x86 machines have one.
+    Sata,
+    /// A disk on a virtio-scsi controller, which is shared by all of the SCSI
+    /// disks of the VM
+    Scsi,
+}
+
+/// Per disk I/O limits. A total limit can not be combined with the read or
+/// write limit of the same kind.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThrottleSpec {
+    pub iops_total: Option<u64>,
+    pub iops_read: Option<u64>,
+    pub iops_write: Option<u64>,
+    pub bps_total: Option<u64>,
+    pub bps_read: Option<u64>,
+    pub bps_write: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NicSpec {
+    /// Unique among the NICs of the VM; used as the QEMU device id
+    pub id: String,
+    #[serde(default)]
+    pub model: NicModel,
+    /// The guest MAC address, `52:54:00:xx:xx:xx` style. The manager assigns
+    /// one when it is not set and records it here, so the guest sees the same
+    /// address on every boot.
+    #[serde(default)]
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub backend: NetBackend,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NicModel {
+    #[default]
+    VirtioNet,
+    E1000e,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum NetBackend {
+    /// Unprivileged user mode networking (SLIRP). The guest reaches the host
+    /// and beyond through NAT; only `port_forwards` reach the guest.
+    User {
+        #[serde(default)]
+        port_forwards: Vec<PortForward>,
+    },
+    /// A passt instance listening on the unix socket `socket`. passt runs
+    /// unprivileged next to QEMU and is told about port forwards itself.
+    Passt { socket: PathBuf },
+    /// The tap device `ifname` on the host. The manager opens it, creating it
+    /// when it does not exist yet, and hands QEMU the open fd.
+    Tap { ifname: String },
+}
+
+impl Default for NetBackend {
+    fn default() -> Self {
+        Self::User {
+            port_forwards: Vec::new(),
+        }
+    }
+}
+
+/// A host port user mode networking forwards to the guest
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PortForward {
+    #[serde(default)]
+    pub protocol: PortProtocol,
+    /// The host address to listen on, all of them when not set
+    #[serde(default)]
+    pub host_addr: Option<Ipv4Addr>,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SerialSpec {
+    /// Unique among the serial consoles of the VM; used as the QEMU device id
+    pub id: String,
+    pub backend: CharBackend,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum CharBackend {
+    /// A unix socket QEMU listens on; the console can be attached to at any
+    /// time without blocking the guest
+    Socket { path: PathBuf },
+    /// A host pseudo terminal
+    Pty,
+    /// Discard all output
+    Null,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Display {
+    /// No graphics at all
+    #[default]
+    None,
+    /// A graphics device exported over VNC, VGA on x86 and virtio-gpu
+    /// elsewhere. `listen` is the display given to `-vnc`, for example
+    /// `unix:/run/qrab/vm.vnc` or `127.0.0.1:0`.
+    Vnc { listen: String },
+}
+
+/// The options `VmSpec::extra_args` may hold, each with the suboptions its
+/// value may set; an empty list takes a plain value without suboptions. These
+/// tune the guest without touching anything the manager sets up itself, like
+/// the QMP socket, the machine or the devices. Suboptions naming host files,
+/// like `-boot splash=` or `-smbios file=`, are left out so nothing reaches
+/// outside of the VM.
+pub const EXTRA_ARGS_ALLOWED: &[(&str, &[&str])] = &[
+    (
+        "-boot",
+        &[
+            "order",
+            "once",
+            "menu",
+            "splash-time",
+            "reboot-timeout",
+            "strict",
+        ],
+    ),
+    ("-rtc", &["base", "clock", "driftfix"]),
+    (
+        "-smbios",
+        &[
+            "type",
+            "vendor",
+            "version",
+            "date",
+            "release",
+            "uefi",
+            "manufacturer",
+            "product",
+            "serial",
+            "uuid",
+            "sku",
+            "family",
+            "asset",
+            "location",
+        ],
+    ),
+    ("-uuid", &[]),
+    ("-overcommit", &["mem-lock", "cpu-pm"]),
+    ("-msg", &["timestamp", "guest-name"]),
+];
+
+/// The id of the memory backend of a VM without NUMA nodes
+pub const MEMORY_BACKEND: &str = "ram0";
+
+/// The most vCPUs a VM may have, the limit of the largest QEMU machine types.
+/// The machine type may allow fewer.
+pub const MAX_VCPUS: u32 = 4096;
+
+/// The longest disk id. Block node names are built from the disk id and may
+/// be 31 characters at most.
+pub const MAX_DISK_ID_LEN: usize = 22;
+
+/// The longest name of a network interface on Linux
+pub const MAX_IFNAME_LEN: usize = 15;
+
+/// Whether `mac` is a unicast MAC address written as six pairs of hex digits
+/// separated by `:`
+fn is_valid_mac(mac: &str) -> bool {
+    let octets: Vec<&str> = mac.split(':').collect();
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()))
+        && u8::from_str_radix(octets[0], 16).is_ok_and(|first| first & 1 == 0)
+}
+
+/// The keys of the suboptions in the QEMU option string `value`. A suboption
+/// without `=` is a flag and its own key.
+fn suboption_keys(value: &str) -> Vec<String> {
+    // `,,` is an escaped comma inside of a suboption value
+    value
+        .replace(",,", "\0")
+        .split(',')
+        .map(|suboption| suboption.split('=').next().unwrap_or_default().to_string())
+        .collect()
+}
+
+/// Escape `value` for use in a QEMU option string, where `,` separates
+/// suboptions and `,,` stands for a literal comma
+fn escape(value: &str) -> String {
+    value.replace(',', ",,")
+}
+
+/// QEMU ids must start with a letter and only hold letters, digits, `-`, `.`
+/// and `_`
+fn is_valid_id(id: &str) -> bool {
+    id.starts_with(|c: char| c.is_ascii_alphabetic())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+}
+
+impl VmSpec {
+    /// The QEMU machine type to run
+    pub fn machine_type(&self) -> &str {
+        self.machine
+            .r#type
+            .as_deref()
+            .unwrap_or(self.emulator.arch.default_machine())
+    }
+
+    /// Check the spec for anything QEMU would refuse, before a process is
+    /// spawned for it
+    pub fn validate(&self) -> Result<()> {
+        if let Some(binary) = &self.emulator.binary {
+            if !binary.is_absolute() {
+                return Err(
+                    anyhow! {"emulator binary `{}` is not an absolute path", binary.display()},
+                );
+            }
+        }
+        if !self.extra_args.len().is_multiple_of(2) {
+            return Err(anyhow! {"extra args must be option and value pairs"});
+        }
+        for pair in self.extra_args.chunks(2) {
+            let (option, value) = (&pair[0], &pair[1]);
+            let Some((_, keys)) = EXTRA_ARGS_ALLOWED.iter().find(|(o, _)| o == option) else {
+                return Err(anyhow! {"extra arg `{}` is not allowed", option});
+            };
+            if keys.is_empty() {
+                if value.contains([',', '=']) {
+                    return Err(anyhow! {"extra arg `{}` takes no suboptions", option});
+                }
+                continue;
+            }
+            for key in suboption_keys(value) {
+                if !keys.contains(&key.as_str()) {
+                    return Err(
+                        anyhow! {"suboption `{}` of extra arg `{}` is not allowed", key, option},
+                    );
+                }
+            }
+        }
+        let cpu = &self.cpu;
+        if cpu.sockets == 0 || cpu.cores == 0 || cpu.threads == 0 {
+            return Err(anyhow! {"vCPU topology must have at least one socket, core and thread"});
+        }
+        match cpu.checked_vcpus() {
+            Some(vcpus) if vcpus <= MAX_VCPUS => {}
+            _ => return Err(anyhow! {"VM must not have more than {} vCPUs", MAX_VCPUS}),
+        }
+        if self.memory_mib == 0 {
+            return Err(anyhow! {"memory must not be zero"});
+        }
+        if !self.numa.is_empty() {
+            let numa_mib: u64 = self.numa.iter().map(|node| node.memory_mib).sum();
+            if numa_mib != self.memory_mib {
+                return Err(
+                    anyhow! {"NUMA nodes hold {} MiB but the VM has {} MiB", numa_mib, self.memory_mib},
+                );
+            }
+            let mut placed = HashSet::new();
+            for socket in self.numa.iter().flat_map(|node| &node.sockets) {
+                if *socket >= cpu.sockets {
+                    return Err(
+                        anyhow! {"NUMA node uses socket {} but the VM has {} socket(s)", socket, cpu.sockets},
+                    );
+                }
+                if !placed.insert(socket) {
+                    return Err(anyhow! {"socket {} is placed in more than one NUMA node", socket});
+                }
+            }
+        }
+        for disk in &self.disks {
+            if disk.id.len() > MAX_DISK_ID_LEN {
+                return Err(
+                    anyhow! {"disk id `{}` is longer than {} characters", disk.id, MAX_DISK_ID_LEN},
+                );
+            }
+            if disk.bus == DiskBus::Sata && self.emulator.arch != Arch::X86_64 {
+                return Err(
+                    anyhow! {"disk `{}` is on SATA, which {} machines do not have", disk.id, self.emulator.arch.as_str()},
+                );
+            }
+            if let Some(throttle) = &disk.throttle {
+                let iops = throttle.iops_read.or(throttle.iops_write);
+                let bps = throttle.bps_read.or(throttle.bps_write);
+                if throttle.iops_total.is_some() && iops.is_some()
+                    || throttle.bps_total.is_some() && bps.is_some()
+                {
+                    return Err(
+                        anyhow! {"disk `{}` combines a total limit with a read or write limit", disk.id},
+                    );
+                }
+            }
+        }
+        let mut macs = HashSet::new();
+        let mut forwards = HashSet::new();
+        for nic in &self.nics {
+            if let Some(mac) = &nic.mac {
+                if !is_valid_mac(mac) {
+                    return Err(anyhow! {"NIC `{}` has an invalid MAC address `{}`", nic.id, mac});
+                }
+                if !macs.insert(mac.to_ascii_lowercase()) {
+                    return Err(anyhow! {"MAC address `{}` is used more than once", mac});
+                }
+            }
+            match &nic.backend {
+                NetBackend::User { port_forwards } => {
+                    for fwd in port_forwards {
+                        if !forwards.insert((fwd.protocol, fwd.host_addr, fwd.host_port)) {
+                            return Err(
+                                anyhow! {"host port {} is forwarded more than once", fwd.host_port},
+                            );
+                        }
+                    }
+                }
+                NetBackend::Passt { socket } => {
+                    if !socket.is_absolute() {
+                        return Err(
+                            anyhow! {"passt socket `{}` is not an absolute path", socket.display()},
+                        );
+                    }
+                }
+                NetBackend::Tap { ifname } => {
+                    if ifname.is_empty()
+                        || ifname.len() > MAX_IFNAME_LEN
+                        || ifname.contains(|c: char| c == '/' || c == ':' || c.is_whitespace())
+                    {
+                        return Err(anyhow! {"`{}` is not a valid interface name", ifname});
+                    }
+                }
+            }
+        }
+        let ids = self
+            .disks
+            .iter()
+            .map(|d| &d.id)
+            .chain(self.nics.iter().map(|n| &n.id))
+            .chain(self.serials.iter().map(|s| &s.id));
+        let mut seen = HashSet::new();
+        for id in ids {
+            if !is_valid_id(id) {
+                return Err(anyhow! {"`{}` is not a valid device id", id});
+            }
+            if !seen.insert(id) {
+                return Err(anyhow! {"device id `{}` is used more than once", id});
+            }
+        }
+        Ok(())
+    }
+
+    /// The QEMU command line arguments for the parts of this spec that can not
+    /// be set over QMP: the machine, its firmware, the vCPUs and the size of
+    /// memory, as well as the consoles
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |option: &str, value: String| {
+            args.push(option.to_string());
+            args.push(value);
+        };
+
+        let mut machine = format!(
+            "{},accel={}",
+            escape(self.machine_type()),
+            self.machine.accel.as_str()
+        );
+        if self.numa.is_empty() {
+            // Created over QMP during preconfig; QEMU only looks it up once
+            // the board is built
+            machine.push_str(&format!(",memory-backend={}", MEMORY_BACKEND));
+        }
+        if let Firmware::Uefi { code, vars } = &self.firmware {
+            let code = json!({
+                "node-name": "firmware-code",
+                "driver": "file",
+                "filename": code,
+                "read-only": true,
+            });
+            let vars = json!({
+                "node-name": "firmware-vars",
+                "driver": "file",
+                "filename": vars,
+            });
+            push("-blockdev", code.to_string());
+            push("-blockdev", vars.to_string());
+            machine.push_str(",pflash0=firmware-code,pflash1=firmware-vars");
+        }
+        push("-machine", machine);
+
+        let cpu = &self.cpu;
+        push("-cpu", escape(&cpu.model));
+        push(
+            "-smp",
+            format!(
+                "{},sockets={},cores={},threads={}",
+                cpu.vcpus(),
+                cpu.sockets,
+                cpu.cores,
+                cpu.threads
+            ),
+        );
+        push("-m", format!("{}M", self.memory_mib));
+
+        let arch = self.emulator.arch;
+        for serial in &self.serials {
+            let chardev_id = format!("serial-{}", serial.id);
+            let chardev = match &serial.backend {
+                CharBackend::Socket { path } => format!(
+                    "socket,id={},path={},server=on,wait=off",
+                    chardev_id,
+                    escape(&path.to_string_lossy())
+                ),
+                CharBackend::Pty => format!("pty,id={}", chardev_id),
+                CharBackend::Null => format!("null,id={}", chardev_id),
+            };
+            push("-chardev", chardev);
+            push(
+                "-device",
+                format!(
+                    "{},chardev={},id={}",
+                    arch.serial_device(),
+                    chardev_id,
+                    serial.id
+                ),
+            );
+        }
+
+        push("-display", "none".to_string());
+        if let Display::Vnc { listen } = &self.display {
+            push("-device", format!("{},id=video0", arch.display_device()));
+            push("-vnc", escape(listen));
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_args() {
+        let spec = VmSpec::default();
+        spec.validate().unwrap();
+        assert_eq!(
+            spec.args(),
+            vec![
+                "-machine",
+                "q35,accel=kvm,memory-backend=ram0",
+                "-cpu",
+                "host",
+                "-smp",
+                "1,sockets=1,cores=1,threads=1",
+                "-m",
+                "1024M",
+                "-display",
+                "none",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_args_escape_commas() {
+        let spec = VmSpec {
+            serials: vec![SerialSpec {
+                id: "console".into(),
+                backend: CharBackend::Socket {
+                    path: "/tmp/a,logfile=/home/x/.bashrc".into(),
+                },
+            }],
+            display: Display::Vnc {
+                listen: "unix:/tmp/v,password=off".into(),
+            },
+            cpu: CpuSpec {
+                model: "host,-vmx".into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let args = spec.args();
+        let value = |option: &str| {
+            let i = args.iter().position(|a| a == option).unwrap();
+            args[i + 1].clone()
+        };
+        assert_eq!(
+            value("-chardev"),
+            "socket,id=serial-console,path=/tmp/a,,logfile=/home/x/.bashrc,server=on,wait=off"
+        );
+        assert_eq!(value("-vnc"), "unix:/tmp/v,,password=off");
+        assert_eq!(value("-cpu"), "host,,-vmx");
+    }
+
+    #[test]
+    fn test_console_args() {
+        let spec = VmSpec {
+            cpu: CpuSpec {
+                sockets: 1,
+                cores: 2,
+                threads: 2,
+                ..Default::default()
+            },
+            serials: vec![SerialSpec {
+                id: "console".into(),
+                backend: CharBackend::Socket {
+                    path: "/run/qrab/console.sock".into(),
+                },
+            }],
+            display: Display::Vnc {
+                listen: "127.0.0.1:0".into(),
+            },
+            ..Default::default()
+        };
+        spec.validate().unwrap();
+        let args = spec.args();
+        let values = |option: &str| -> Vec<&str> {
+            args.windows(2)
+                .filter(|w| w[0] == option)
+                .map(|w| w[1].as_str())
+                .collect()
+        };
+        assert_eq!(values("-smp"), vec!["4,sockets=1,cores=2,threads=2"]);
+        assert_eq!(
+            values("-device"),
+            vec![
+                "isa-serial,chardev=serial-console,id=console",
+                "VGA,id=video0"
+            ]
+        );
+        assert_eq!(
+            values("-chardev"),
+            vec!["socket,id=serial-console,path=/run/qrab/console.sock,server=on,wait=off"]
+        );
+        assert_eq!(values("-vnc"), vec!["127.0.0.1:0"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut spec = VmSpec::default();
+        spec.cpu.cores = 0;
+        assert!(spec.validate().is_err());
+        spec.cpu.cores = 64;
+        spec.cpu.sockets = 64;
+        spec.validate().unwrap();
+        spec.cpu.threads = 2;
+        assert!(spec.validate().is_err());
+        spec.cpu.threads = u32::MAX;
+        assert!(spec.validate().is_err());
+
+        let nic = |id: &str| NicSpec {
+            id: id.into(),
+            model: NicModel::default(),
+            mac: None,
+            backend: NetBackend::default(),
+        };
+        let spec = VmSpec {
+            nics: vec![nic("eth0"), nic("eth0")],
+            ..Default::default()
+        };
+        assert!(spec.validate().is_err());
+        let spec = VmSpec {
+            nics: vec![nic("0eth")],
+            ..Default::default()
+        };
+        assert!(spec.validate().is_err());
+
+        let node = |memory_mib, sockets: &[u32]| NumaNodeSpec {
+            memory_mib,
+            sockets: sockets.to_vec(),
+        };
+        let mut spec = VmSpec {
+            memory_mib: 2048,
+            cpu: CpuSpec {
+                sockets: 2,
+                ..Default::default()
+            },
+            numa: vec![node(1024, &[0]), node(1024, &[1])],
+            ..Default::default()
+        };
+        spec.validate().unwrap();
+        assert!(!spec.args()[1].contains("memory-backend"));
+        spec.numa = vec![node(1024, &[0]), node(512, &[1])];
+        assert!(spec.validate().is_err());
+        spec.numa = vec![node(1024, &[0]), node(1024, &[0])];
+        assert!(spec.validate().is_err());
+        spec.numa = vec![node(1024, &[0]), node(1024, &[2])];
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_disks() {
+        let disk: DiskSpec = serde_json::from_value(json!({
+            "id": "data",
+            "path": "/dev/vg0/data",
+            "protocol": "host_device",
+            "bus": "scsi",
+            "throttle": { "iops_total": 1000, "bps_read": 104857600 },
+        }))
+        .unwrap();
+        let mut spec = VmSpec {
+            disks: vec![disk],
+            ..Default::default()
+        };
+        spec.validate().unwrap();
+        spec.disks[0].throttle = Some(ThrottleSpec {
+            iops_total: Some(1000),
+            iops_write: Some(100),
+            ..Default::default()
+        });
+        assert!(spec.validate().is_err());
+        spec.disks[0].throttle = None;
+        spec.disks[0].id = "a".repeat(MAX_DISK_ID_LEN + 1);
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_nics() {
+        let nic: NicSpec = serde_json::from_value(json!({
+            "id": "eth0",
+            "mac": "52:54:00:AB:cd:01",
+            "backend": {
+                "type": "user",
+                "port_forwards": [{ "host_port": 2222, "guest_port": 22 }],
+            },
+        }))
+        .unwrap();
+        let mut spec = VmSpec {
+            nics: vec![nic.clone()],
+            ..Default::default()
+        };
+        spec.validate().unwrap();
+        spec.nics.push(NicSpec {
+            id: "eth1".into(),
+            ..nic
+        });
+        assert!(spec.validate().is_err());
+        spec.nics[1].mac = Some("52:54:00:ab:cd:02".into());
+        assert!(spec.validate().is_err());
+        spec.nics[1].backend = NetBackend::User {
+            port_forwards: vec![PortForward {
+                protocol: PortProtocol::Udp,
+                host_addr: None,
+                host_port: 2222,
+                guest_port: 53,
+            }],
+        };
+        spec.validate().unwrap();
+        spec.nics[1].mac = Some("53:54:00:ab:cd:02".into());
+        assert!(spec.validate().is_err());
+        spec.nics[1].mac = Some("52:54:00:ab:cd".into());
+        assert!(spec.validate().is_err());
+        spec.nics[1].mac = None;
+        spec.nics[1].backend = NetBackend::Tap {
+            ifname: "qrab-tap-too-long".into(),
+        };
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_emulator() {
+        let mut spec = VmSpec::default();
+        assert_eq!(spec.emulator.binary(), PathBuf::from("qemu-system-x86_64"));
+        spec.emulator.arch = Arch::Riscv64;
+        assert_eq!(spec.emulator.binary(), PathBuf::from("qemu-system-riscv64"));
+        spec.emulator.binary = Some("qemu-9.1/qemu-system-riscv64".into());
+        assert!(spec.validate().is_err());
+        spec.emulator.binary = Some("/opt/qemu-9.1/bin/qemu-system-riscv64".into());
+        spec.validate().unwrap();
+        assert_eq!(
+            spec.emulator.binary(),
+            PathBuf::from("/opt/qemu-9.1/bin/qemu-system-riscv64")
+        );
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let extra = |args: &[&str]| VmSpec {
+            extra_args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Default::default()
+        };
+        let spec = extra(&["-rtc", "base=utc,clock=host"]);
+        spec.validate().unwrap();
+        assert!(spec
+            .args()
+            .ends_with(&["-rtc".into(), "base=utc,clock=host".into()]));
+        assert!(extra(&["-rtc"]).validate().is_err());
+        assert!(extra(&["-qmp", "tcp:0.0.0.0:4444,server"])
+            .validate()
+            .is_err());
+        extra(&[
+            "-smbios",
+            "type=1,serial=a,,b=c",
+            "-uuid",
+            "00000000-0000-0000-0000-000000000001",
+        ])
+        .validate()
+        .unwrap();
+        assert!(extra(&["-smbios", "file=/etc/shadow"]).validate().is_err());
+        assert!(extra(&["-smbios", "type=1,serial=x,file=/etc/shadow"])
+            .validate()
+            .is_err());
+        assert!(extra(&["-boot", "menu=on,splash=/etc/shadow"])
+            .validate()
+            .is_err());
+        assert!(
+            extra(&["-global", "driver=cfi.pflash01,property=secure,value=on"])
+                .validate()
+                .is_err()
+        );
+        assert!(extra(&["-uuid", "x,file=/etc/shadow"]).validate().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_partial() {
+        let spec: VmSpec = serde_json::from_value(json!({
+            "memory_mib": 4096,
+            "emulator": { "arch": "aarch64" },
+            "firmware": { "type": "uefi", "code": "/usr/share/OVMF/OVMF_CODE.fd", "vars": "/var/lib/qrab/vars.fd" },
+            "nics": [{ "id": "eth0" }],
+        }))
+        .unwrap();
+        assert_eq!(spec.memory_mib, 4096);
+        assert_eq!(spec.machine, MachineSpec::default());
+        assert_eq!(spec.emulator.arch, Arch::Aarch64);
+        assert_eq!(spec.nics[0].backend, NetBackend::default());
+        spec.validate().unwrap();
+        let args = spec.args();
+        assert!(args[..6].contains(&"-blockdev".to_string()));
+        assert!(args.contains(
+            &"virt,accel=kvm,memory-backend=ram0,pflash0=firmware-code,pflash1=firmware-vars"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn test_non_x86_args() {
+        let mut spec = VmSpec {
+            emulator: EmulatorSpec {
+                arch: Arch::Aarch64,
+                binary: None,
+            },
+            serials: vec![SerialSpec {
+                id: "console".into(),
+                backend: CharBackend::Pty,
+            }],
+            display: Display::Vnc {
+                listen: "127.0.0.1:0".into(),
+            },
+            ..Default::default()
+        };
+        spec.validate().unwrap();
+        let args = spec.args();
+        let values = |option: &str| -> Vec<&str> {
+            args.windows(2)
+                .filter(|w| w[0] == option)
+                .map(|w| w[1].as_str())
+                .collect()
+        };
+        assert_eq!(
+            values("-machine"),
+            vec!["virt,accel=kvm,memory-backend=ram0"]
+        );
+        assert_eq!(
+            values("-device"),
+            vec![
+                "pci-serial,chardev=serial-console,id=console",
+                "virtio-gpu-pci,id=video0"
+            ]
+        );
+
+        let disk: DiskSpec = serde_json::from_value(json!({
+            "id": "root", "path": "/var/lib/qrab/root.qcow2", "format": "qcow2", "bus": "sata",
+        }))
+        .unwrap();
+        spec.disks.push(disk);
+        assert!(spec.validate().is_err());
+        spec.disks[0].bus = DiskBus::Virtio;
+        spec.validate().unwrap();
+    }
+}
@@ -0,0 +1,712 @@
+//! Disks of a VM, built as `blockdev-add` node graphs
+//!
+//! Every disk is a chain of block nodes with its guest device attached to the
+//! top one:
+//!
+//! ```text
+//! device -> throttle-<id> -> format-<id> -> file-<id>
+//! ```
+//!
+//! `file-<id>` opens the image as a `file` or `host_device`, `format-<id>`
+//! reads it as `qcow2` or `raw`, and `throttle-<id>` applies the limits of the
+//! `throttle-group` object of the same name. Disks without limits have no
+//! throttle node. The chain is added with a single `blockdev-add`.
+//!
+//! Live snapshots put qcow2 overlays on top of `format-<id>`, each added with
+//! its own `blockdev-add` as `overlay-<n>` over `file-overlay-<n>`. The guest
+//! writes to the newest one. `DiskChain` keeps track of them, so they can be
+//! merged back with `block-commit`, flattened with `block-stream` and deleted.
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::devices::DeviceAdd;
+use crate::qapi::{
+    BlockCommit, BlockDeviceInfo, BlockInfo, BlockResize, BlockStream, BlockdevAdd, BlockdevCreate,
+    BlockdevCreateOptions, BlockdevCreateOptionsBranch, BlockdevCreateOptionsFile,
+    BlockdevCreateOptionsQcow2, BlockdevDel, BlockdevDriver, BlockdevOptions,
+    BlockdevOptionsBranch, BlockdevOptionsFile, BlockdevOptionsGenericFormat, BlockdevOptionsQcow2,
+    BlockdevOptionsThrottle, BlockdevRef, BlockdevRefOrNull, BlockdevSnapshot, DeviceDel, Null,
+    ObjectAdd, ObjectDel, ObjectOptions, ObjectOptionsBranch, QueryBlock, QueryNamedBlockNodes,
+    ThrottleGroupProperties, ThrottleLimits,
+};
+use crate::qmp::qapi::QMPCommand;
+use crate::spec::{DiskBus, DiskFormat, DiskProtocol, DiskSpec, ThrottleSpec};
+
+/// The device id of the virtio-scsi controller all SCSI disks sit on
+pub const SCSI_CONTROLLER: &str = "scsi0";
+
+pub fn file_node(id: &str) -> String {
+    format!("file-{}", id)
+}
+
+/// The node reading the image of the spec
+pub fn format_node(id: &str) -> String {
+    format!("format-{}", id)
+}
+
+/// The name of both the throttle node and its throttle group
+pub fn throttle_node(id: &str) -> String {
+    format!("throttle-{}", id)
+}
+
+/// The node the guest device of `disk` is attached to
+pub fn top_node(disk: &DiskSpec) -> String {
+    match disk.throttle {
+        Some(_) => throttle_node(&disk.id),
+        None => format_node(&disk.id),
+    }
+}
+
+fn limits(throttle: &ThrottleSpec) -> ThrottleLimits {
+    let limit = |value: Option<u64>| value.map(|v| i64::try_from(v).unwrap_or(i64::MAX));
+    ThrottleLimits {
+        iops_total: limit(throttle.iops_total),
+        iops_read: limit(throttle.iops_read),
+        iops_write: limit(throttle.iops_write),
+        bps_total: limit(throttle.bps_total),
+        bps_read: limit(throttle.bps_read),
+        bps_write: limit(throttle.bps_write),
+    }
+}
+
+/// One image of the backing chain of a disk, as the format node reading it
+/// and the protocol node below that
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub node: String,
+    pub file: String,
+    pub path: PathBuf,
+    pub format: DiskFormat,
+    pub protocol: DiskProtocol,
+    /// Whether the node was added by a `blockdev-add` of its own and needs a
+    /// `blockdev-del` of its own. Nodes defined inside another one go away
+    /// with it.
+    owned: bool,
+}
+
+impl Layer {
+    /// The image the spec names for `disk`
+    fn image(disk: &DiskSpec) -> Self {
+        Self {
+            node: format_node(&disk.id),
+            file: file_node(&disk.id),
+            path: disk.path.clone(),
+            format: disk.format,
+            protocol: disk.protocol,
+            owned: disk.throttle.is_none(),
+        }
+    }
+
+    /// A qcow2 overlay named `node`, stored in the regular file `path`
+    pub fn overlay<S: ToString>(node: S, path: &Path) -> Self {
+        let node = node.to_string();
+        Self {
+            file: file_node(&node),
+            node,
+            path: path.to_path_buf(),
+            format: DiskFormat::Qcow2,
+            protocol: DiskProtocol::File,
+            owned: true,
+        }
+    }
+
+    fn blockdev(&self, read_only: bool, backing: Option<BlockdevRefOrNull>) -> BlockdevOptions {
+        let file = BlockdevOptionsFile::new(self.path.to_string_lossy().into_owned());
+        let protocol = match self.protocol {
+            DiskProtocol::File => BlockdevOptionsBranch::File(file),
+            DiskProtocol::HostDevice => BlockdevOptionsBranch::HostDevice(file),
+        };
+        let protocol = BlockdevOptions::new(protocol)
+            .node_name(self.file.clone())
+            .read_only(read_only);
+
+        let file = BlockdevRef::Definition(Box::new(protocol));
+        let format = match self.format {
+            DiskFormat::Raw => BlockdevOptionsBranch::Raw(BlockdevOptionsGenericFormat::new(file)),
+            DiskFormat::Qcow2 => {
+                let mut qcow2 = BlockdevOptionsQcow2::new(file);
+                qcow2.backing = backing;
+                BlockdevOptionsBranch::Qcow2(qcow2)
+            }
+        };
+        BlockdevOptions::new(format)
+            .node_name(self.node.clone())
+            .read_only(read_only)
+    }
+}
+
+/// The node graph of `disk`, from its top node down
+pub fn blockdev(disk: &DiskSpec) -> BlockdevOptions {
+    let format = Layer::image(disk).blockdev(disk.read_only, None);
+    if disk.throttle.is_none() {
+        return format;
+    }
+
+    let throttle = BlockdevOptionsThrottle::new(
+        throttle_node(&disk.id),
+        BlockdevRef::Definition(Box::new(format)),
+    );
+    BlockdevOptions::new(BlockdevOptionsBranch::Throttle(throttle))
+        .node_name(throttle_node(&disk.id))
+        .read_only(disk.read_only)
+}
+
+/// The guest device of `disk`
+pub fn device(disk: &DiskSpec) -> DeviceAdd {
+    let mut device = match disk.bus {
+        DiskBus::Virtio => json!({ "driver": "virtio-blk-pci" }),
+        DiskBus::Sata => json!({ "driver": "ide-hd", "bus": "ide.0" }),
+        DiskBus::Scsi => json!({ "driver": "scsi-hd", "bus": format!("{}.0", SCSI_CONTROLLER) }),
+    };
+    device["drive"] = json!(top_node(disk));
+    device["id"] = json!(disk.id);
+    if let Some(index) = disk.boot_index {
+        device["bootindex"] = json!(index);
+    }
+    DeviceAdd::new(device)
+}
+
+/// The virtio-scsi controller, which must exist before any SCSI disk is
+/// attached
+pub fn scsi_controller() -> DeviceAdd {
+    DeviceAdd::new(json!({ "driver": "virtio-scsi-pci", "id": SCSI_CONTROLLER }))
+}
+
+/// The throttle group and block nodes of a disk, in order
+pub fn backend(disk: &DiskSpec) -> Vec<QMPCommand> {
+    let mut commands = Vec::new();
+    if let Some(throttle) = &disk.throttle {
+        let group = ThrottleGroupProperties::new().limits(limits(throttle));
+        let object = ObjectOptions::new(
+            throttle_node(&disk.id),
+            ObjectOptionsBranch::ThrottleGroup(group),
+        );
+        commands.push(QMPCommand::new(&ObjectAdd::new(object)));
+    }
+    commands.push(QMPCommand::new(&BlockdevAdd::new(blockdev(disk))));
+    commands
+}
+
+/// The throttle group, block nodes and guest device of a disk, in order
+pub fn attach(disk: &DiskSpec) -> Vec<QMPCommand> {
+    let mut commands = backend(disk);
+    commands.push(QMPCommand::new(&device(disk)));
+    commands
+}
+
+/// Ask the guest to let go of the device of `disk`. It is only gone once QEMU
+/// sent `DEVICE_DELETED` for it; until then `release` fails.
+pub fn unplug(disk: &DiskSpec) -> QMPCommand {
+    QMPCommand::new(&DeviceDel::new(disk.id.clone()))
+}
+
+/// The backing chain of a disk, from its base image up to the active layer
+/// the guest writes to. A new chain holds only the image of the spec; after a
+/// restart QEMU opens the backing files of that image on its own, without
+/// node names, so overlays only show up here while the QEMU that made them
+/// keeps running. A manager attaching to that QEMU finds them again with
+/// `discover`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskChain {
+    id: String,
+    throttled: bool,
+    layers: Vec<Layer>,
+}
+
+impl DiskChain {
+    pub fn new(disk: &DiskSpec) -> Self {
+        Self {
+            id: disk.id.clone(),
+            throttled: disk.throttle.is_some(),
+            layers: vec![Layer::image(disk)],
+        }
+    }
+
+    /// The chain QEMU has for `disk`, out of the replies to `query_block`
+    /// and `query_nodes`. The layers are the named nodes whose images the
+    /// device reads, down to the image of the spec or the lowest one left
+    /// by a stream. A disk without a device gets a new chain.
+    pub fn discover(disk: &DiskSpec, blocks: &[BlockInfo], nodes: &[BlockDeviceInfo]) -> Self {
+        let mut chain = Self::new(disk);
+        let inserted = blocks
+            .iter()
+            .find(|block| attached(block, &disk.id))
+            .and_then(|block| block.inserted.as_ref());
+        let Some(inserted) = inserted else {
+            return chain;
+        };
+
+        // `query-block` names the images down the chain by file, starting
+        // with the throttle node of throttled disks, which reads the file of
+        // the active layer
+        let base = Layer::image(disk);
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut image = Some(&inserted.image);
+        while let Some(info) = image {
+            let Some(node) = layer_node(nodes, &info.filename, &base.node) else {
+                break;
+            };
+            if node == base.node {
+                layers.push(base);
+                break;
+            }
+            if layers.last().map(|layer| layer.node.as_str()) != Some(node) {
+                layers.push(Layer::overlay(node, Path::new(&info.filename)));
+            }
+            image = info.backing_image.as_deref();
+        }
+
+        if !layers.is_empty() {
+            layers.reverse();
+            chain.layers = layers;
+        }
+        chain
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn active(&self) -> &Layer {
+        self.layers.last().expect("a chain always has an image")
+    }
+
+    /// The node the guest device is attached to
+    pub fn root(&self) -> String {
+        match self.throttled {
+            true => throttle_node(&self.id),
+            false => self.active().node.clone(),
+        }
+    }
+
+    /// Delete the block nodes and throttle group of a disk without a device,
+    /// newest first
+    pub fn release(&self) -> Vec<QMPCommand> {
+        let mut nodes = Vec::new();
+        if self.throttled {
+            nodes.push(throttle_node(&self.id));
+        }
+        let owned = self.layers.iter().rev().filter(|layer| layer.owned);
+        nodes.extend(owned.map(|layer| layer.node.clone()));
+
+        let mut commands: Vec<_> = nodes.into_iter().map(delete_node).collect();
+        if self.throttled {
+            commands.push(QMPCommand::new(&ObjectDel::new(throttle_node(&self.id))));
+        }
+        commands
+    }
+
+    /// Resize the active layer to `size` bytes while the guest uses it
+    pub fn resize(&self, size: i64) -> QMPCommand {
+        QMPCommand::new(&BlockResize::new(size).node_name(self.active().node.clone()))
+    }
+
+    /// Put `overlay`, added with `add_image`, on top of the chain. The
+    /// layer below it is only read from from now on.
+    pub fn snapshot(&mut self, overlay: Layer) -> QMPCommand {
+        let snapshot = BlockdevSnapshot::new(self.active().node.clone(), overlay.node.clone());
+        self.layers.push(overlay);
+        QMPCommand::new(&snapshot)
+    }
+
+    /// Start the job `job_id` merging every overlay into the base image. The
+    /// job becomes ready once both hold the same data; completing it switches
+    /// the guest over to the base image and `committed` cleans up after it.
+    pub fn commit(&self, job_id: &str) -> Result<QMPCommand> {
+        let [base, .., active] = self.layers.as_slice() else {
+            return Err(anyhow! {"disk {} has no overlays to commit", self.id});
+        };
+        let commit = BlockCommit::new(self.root())
+            .job_id(job_id.to_string())
+            .base_node(base.node.clone())
+            .top_node(active.node.clone())
+            .auto_dismiss(false);
+        Ok(QMPCommand::new(&commit))
+    }
+
+    /// Drop the overlays a completed commit left unused
+    pub fn committed(&mut self) -> Vec<QMPCommand> {
+        let overlays = self.layers.split_off(1);
+        overlays
+            .iter()
+            .rev()
+            .map(|layer| delete_node(layer.node.clone()))
+            .collect()
+    }
+
+    /// Start the job `job_id` copying everything the active layer reads from
+    /// its backing chain into it, so it stands on its own. `streamed` cleans
+    /// up after it.
+    pub fn stream(&self, job_id: &str) -> Result<QMPCommand> {
+        if self.layers.len() < 2 {
+            return Err(anyhow! {"disk {} has no backing chain to stream", self.id});
+        }
+        let stream = BlockStream::new(self.active().node.clone())
+            .job_id(job_id.to_string())
+            .auto_dismiss(false);
+        Ok(QMPCommand::new(&stream))
+    }
+
+    /// Drop the layers below the active one after a completed stream
+    pub fn streamed(&mut self) -> Vec<QMPCommand> {
+        let active = self.layers.pop().expect("a chain always has an image");
+        let backing = std::mem::replace(&mut self.layers, vec![active]);
+        let owned = backing.iter().rev().filter(|layer| layer.owned);
+        owned.map(|layer| delete_node(layer.node.clone())).collect()
+    }
+}
+
+/// Whether `block` is the device of the disk `id`. QEMU reports a device by
+/// its id, or by the QOM path of the backend of a virtio device.
+fn attached(block: &BlockInfo, id: &str) -> bool {
+    let Some(qdev) = block.qdev.as_deref() else {
+        return false;
+    };
+    qdev == id || qdev.starts_with(&format!("/machine/peripheral/{}/", id))
+}
+
+/// The layer of a chain with the image `file`: either `base` or an overlay
+fn layer_node<'a>(nodes: &'a [BlockDeviceInfo], file: &str, base: &str) -> Option<&'a str> {
+    nodes
+        .iter()
+        .filter(|info| info.file == file)
+        .filter_map(|info| info.node_name.as_deref())
+        .find(|node| *node == base || overlay_number(node).is_some())
+}
+
+/// The `n` of the overlay node `overlay-<n>`
+pub fn overlay_number(node: &str) -> Option<u32> {
+    node.strip_prefix("overlay-")?.parse().ok()
+}
+
+pub fn delete_node<S: ToString>(node: S) -> QMPCommand {
+    QMPCommand::new(&BlockdevDel::new(node.to_string()))
+}
+
+/// Every named block node, to find out how large an image is
+pub fn query_nodes() -> QueryNamedBlockNodes {
+    QueryNamedBlockNodes::new().flat(true)
+}
+
+/// Every block device, with the chain of images it reads
+pub fn query_block() -> QueryBlock {
+    QueryBlock::new()
+}
+
+/// The size the guest sees of the image `node` reads, out of the reply to
+/// `query_nodes`
+pub fn virtual_size(nodes: &[BlockDeviceInfo], node: &str) -> Option<u64> {
+    let info = nodes
+        .iter()
+        .find(|info| info.node_name.as_deref() == Some(node))?;
+    u64::try_from(info.image.virtual_size).ok()
+}
+
+/// Start the job `job_id` creating the empty regular file `path`
+pub fn create_file(job_id: &str, path: &Path) -> QMPCommand {
+    let file = BlockdevCreateOptionsFile::new(path.to_string_lossy().into_owned(), 0);
+    let create = BlockdevCreate::new(
+        job_id.to_string(),
+        BlockdevCreateOptions::new(BlockdevCreateOptionsBranch::File(file)),
+    );
+    QMPCommand::new(&create)
+}
+
+/// Open the file of `image` on its own, for `create_qcow2` to write to. It has
+/// to be deleted again before the image itself is added.
+pub fn open_file(image: &Layer) -> QMPCommand {
+    let file = BlockdevOptionsFile::new(image.path.to_string_lossy().into_owned());
+    let file =
+        BlockdevOptions::new(BlockdevOptionsBranch::File(file)).node_name(image.file.clone());
+    QMPCommand::new(&BlockdevAdd::new(file))
+}
+
+/// Start the job `job_id` formatting the file of `image` as a qcow2 image of
+/// `size` bytes, backed by the image of `backing` if there is one
+pub fn create_qcow2(job_id: &str, image: &Layer, size: u64, backing: Option<&Layer>) -> QMPCommand {
+    let mut qcow2 =
+        BlockdevCreateOptionsQcow2::new(BlockdevRef::Reference(image.file.clone()), size);
+    if let Some(backing) = backing {
+        let format = match backing.format {
+            DiskFormat::Raw => BlockdevDriver::Raw,
+            DiskFormat::Qcow2 => BlockdevDriver::Qcow2,
+        };
+        qcow2 = qcow2
+            .backing_file(backing.path.to_string_lossy().into_owned())
+            .backing_fmt(format);
+    }
+    let create = BlockdevCreate::new(
+        job_id.to_string(),
+        BlockdevCreateOptions::new(BlockdevCreateOptionsBranch::Qcow2(qcow2)),
+    );
+    QMPCommand::new(&create)
+}
+
+/// Add the qcow2 `image` on top of the node of `backing`. Without one it is
+/// added without its backing file, which an overlay only gets from
+/// `DiskChain::snapshot`.
+pub fn add_image(image: &Layer, backing: Option<&Layer>) -> QMPCommand {
+    let backing = match backing {
+        Some(backing) => BlockdevRefOrNull::Reference(backing.node.clone()),
+        None => BlockdevRefOrNull::Null(Null),
+    };
+    let image = image.blockdev(false, Some(backing));
+    QMPCommand::new(&BlockdevAdd::new(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn to_json(commands: Vec<QMPCommand>) -> Vec<Value> {
+        commands
+            .iter()
+            .map(|cmd| serde_json::to_value(cmd).unwrap())
+            .collect()
+    }
+
+    fn disk() -> DiskSpec {
+        DiskSpec {
+            id: "data".into(),
+            path: "/dev/vg0/data".into(),
+            protocol: DiskProtocol::HostDevice,
+            format: DiskFormat::Qcow2,
+            bus: DiskBus::Scsi,
+            read_only: false,
+            boot_index: None,
+            throttle: Some(ThrottleSpec {
+                iops_total: Some(1000),
+                bps_read: Some(100 << 20),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_attach() {
+        assert_eq!(
+            to_json(attach(&disk())),
+            vec![
+                json!({"execute": "object-add", "arguments": {
+                    "qom-type": "throttle-group", "id": "throttle-data",
+                    "limits": { "iops-total": 1000, "bps-read": 104857600 },
+                }}),
+                json!({"execute": "blockdev-add", "arguments": {
+                    "driver": "throttle", "node-name": "throttle-data", "read-only": false,
+                    "throttle-group": "throttle-data",
+                    "file": {
+                        "driver": "qcow2", "node-name": "format-data", "read-only": false,
+                        "file": {
+                            "driver": "host_device", "node-name": "file-data",
+                            "read-only": false, "filename": "/dev/vg0/data",
+                        },
+                    },
+                }}),
+                json!({"execute": "device_add", "arguments": {
+                    "driver": "scsi-hd", "bus": "scsi0.0", "drive": "throttle-data",
+                    "id": "data",
+                }}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detach() {
+        let mut disk = disk();
+        assert_eq!(
+            to_json(vec![unplug(&disk)]),
+            vec![json!({"execute": "device_del", "arguments": { "id": "data" }})]
+        );
+        assert_eq!(
+            to_json(DiskChain::new(&disk).release()),
+            vec![
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "throttle-data" }}),
+                json!({"execute": "object-del", "arguments": { "id": "throttle-data" }}),
+            ]
+        );
+        disk.throttle = None;
+        assert_eq!(
+            to_json(DiskChain::new(&disk).release()),
+            vec![json!({"execute": "blockdev-del", "arguments": { "node-name": "format-data" }})]
+        );
+    }
+
+    #[test]
+    fn test_resize() {
+        assert_eq!(
+            serde_json::to_value(DiskChain::new(&disk()).resize(20 << 30)).unwrap(),
+            json!({"execute": "block_resize", "arguments": {
+                "node-name": "format-data", "size": 21474836480u64,
+            }})
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut chain = DiskChain::new(&disk());
+        let overlay = Layer::overlay("overlay-1", Path::new("/var/lib/qrab/data-1.qcow2"));
+        assert_eq!(
+            to_json(vec![
+                open_file(&overlay),
+                create_qcow2("create-overlay-1", &overlay, 10 << 30, Some(chain.active())),
+                add_image(&overlay, None),
+                chain.snapshot(overlay),
+            ]),
+            vec![
+                json!({"execute": "blockdev-add", "arguments": {
+                    "driver": "file", "node-name": "file-overlay-1",
+                    "filename": "/var/lib/qrab/data-1.qcow2",
+                }}),
+                json!({"execute": "blockdev-create", "arguments": {
+                    "job-id": "create-overlay-1",
+                    "options": {
+                        "driver": "qcow2", "file": "file-overlay-1", "size": 10737418240u64,
+                        "backing-file": "/dev/vg0/data", "backing-fmt": "qcow2",
+                    },
+                }}),
+                json!({"execute": "blockdev-add", "arguments": {
+                    "driver": "qcow2", "node-name": "overlay-1", "read-only": false,
+                    "backing": null,
+                    "file": {
+                        "driver": "file", "node-name": "file-overlay-1", "read-only": false,
+                        "filename": "/var/lib/qrab/data-1.qcow2",
+                    },
+                }}),
+                json!({"execute": "blockdev-snapshot", "arguments": {
+                    "node": "format-data", "overlay": "overlay-1",
+                }}),
+            ]
+        );
+        assert_eq!(chain.active().node, "overlay-1");
+        // The throttle node stays on top of the chain
+        assert_eq!(chain.root(), "throttle-data");
+        assert_eq!(
+            to_json(chain.release()),
+            vec![
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "throttle-data" }}),
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "overlay-1" }}),
+                json!({"execute": "object-del", "arguments": { "id": "throttle-data" }}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_commit_stream() {
+        let mut disk = disk();
+        disk.throttle = None;
+        let mut chain = DiskChain::new(&disk);
+        assert!(chain.commit("commit-data").is_err());
+        assert!(chain.stream("stream-data").is_err());
+        for n in 1..=2 {
+            let path = PathBuf::from(format!("/var/lib/qrab/data-{}.qcow2", n));
+            chain.snapshot(Layer::overlay(format!("overlay-{}", n), &path));
+        }
+
+        let mut committed = chain.clone();
+        assert_eq!(
+            serde_json::to_value(committed.commit("commit-data").unwrap()).unwrap(),
+            json!({"execute": "block-commit", "arguments": {
+                "job-id": "commit-data", "device": "overlay-2", "base-node": "format-data",
+                "top-node": "overlay-2", "auto-dismiss": false,
+            }})
+        );
+        assert_eq!(
+            to_json(committed.committed()),
+            vec![
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "overlay-2" }}),
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "overlay-1" }}),
+            ]
+        );
+        assert_eq!(committed.active().node, "format-data");
+
+        assert_eq!(
+            serde_json::to_value(chain.stream("stream-data").unwrap()).unwrap(),
+            json!({"execute": "block-stream", "arguments": {
+                "job-id": "stream-data", "device": "overlay-2", "auto-dismiss": false,
+            }})
+        );
+        assert_eq!(
+            to_json(chain.streamed()),
+            vec![
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "overlay-1" }}),
+                json!({"execute": "blockdev-del", "arguments": { "node-name": "format-data" }}),
+            ]
+        );
+        assert_eq!(chain.layers().len(), 1);
+        assert_eq!(chain.root(), "overlay-2");
+    }
+    fn node(name: &str, file: &str, drv: &str) -> Value {
+        json!({
+            "node-name": name, "file": file, "ro": false, "drv": drv,
+            "image": { "filename": file, "format": drv, "virtual-size": 10737418240u64 },
+        })
+    }
+
+    #[test]
+    fn test_discover() {
+        let data = "/dev/vg0/data";
+        let (one, two) = ("/var/lib/qrab/data-1.qcow2", "/var/lib/qrab/data-2.qcow2");
+        let nodes: Vec<BlockDeviceInfo> = serde_json::from_value(json!([
+            node("throttle-data", two, "throttle"),
+            node("overlay-2", two, "qcow2"),
+            node("file-overlay-2", two, "file"),
+            node("overlay-1", one, "qcow2"),
+            node("file-overlay-1", one, "file"),
+            node("format-data", data, "qcow2"),
+            node("file-data", data, "host_device"),
+            node("overlay-7", "/var/lib/qrab/root-7.qcow2", "qcow2"),
+        ]))
+        .unwrap();
+        let image = |filename: &str, format: &str, backing: Value| {
+            let mut image = json!({
+                "filename": filename, "format": format, "virtual-size": 10737418240u64,
+            });
+            if !backing.is_null() {
+                image["backing-image"] = backing;
+            }
+            image
+        };
+        let blocks: Vec<BlockInfo> = serde_json::from_value(json!([{
+            "device": "", "qdev": "data", "type": "unknown",
+            "removable": false, "locked": false,
+            "inserted": {
+                "node-name": "throttle-data", "file": two, "ro": false, "drv": "throttle",
+                "image": image(two, "throttle", image(two, "qcow2",
+                    image(one, "qcow2", image(data, "qcow2", Value::Null)))),
+            },
+        }]))
+        .unwrap();
+
+        let mut expected = DiskChain::new(&disk());
+        expected.snapshot(Layer::overlay("overlay-1", Path::new(one)));
+        expected.snapshot(Layer::overlay("overlay-2", Path::new(two)));
+        assert_eq!(DiskChain::discover(&disk(), &blocks, &nodes), expected);
+
+        // A virtio disk that streamed its chain into the active layer
+        let mut root = disk();
+        root.id = "root".into();
+        root.throttle = None;
+        let blocks: Vec<BlockInfo> = serde_json::from_value(json!([{
+            "device": "", "qdev": "/machine/peripheral/root/virtio-backend",
+            "type": "unknown", "removable": false, "locked": false,
+            "inserted": node("overlay-7", "/var/lib/qrab/root-7.qcow2", "qcow2"),
+        }]))
+        .unwrap();
+        let chain = DiskChain::discover(&root, &blocks, &nodes);
+        assert_eq!(chain.layers().len(), 1);
+        assert_eq!(chain.root(), "overlay-7");
+
+        // Without a device the disk keeps the image of its spec
+        assert_eq!(
+            DiskChain::discover(&disk(), &[], &nodes),
+            DiskChain::new(&disk())
+        );
+
+        let highest = nodes
+            .iter()
+            .filter_map(|info| overlay_number(info.node_name.as_deref()?));
+        assert_eq!(highest.max(), Some(7));
+    }
+}
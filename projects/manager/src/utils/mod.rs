@@ -1,14 +1,18 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use futures::future::try_join_all;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::sched::{unshare, CloneFlags};
+use nix::sys::signal::Signal;
 use nix::unistd::{getpid, setgid, setsid, setuid, Gid, Pid, Uid};
 use std::collections::{HashMap, HashSet};
-use std::io::{Read, Write};
-use std::os::fd::IntoRawFd;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::os::unix::io::FromRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncBufReadExt, BufReader, Interest};
 use tokio::net::unix::pipe;
 use tokio::process::{Child, Command};
 use tokio::time::Duration;
@@ -113,54 +117,127 @@ pub(crate) async fn build_cgroup_process_trees(cgroup_name: String) -> Result<Ve
     Ok(trees)
 }
 
-pub(crate) async fn wait_for_pids(pids: Vec<Pid>) -> Result<()> {
-    // I gave up on this function...
-    // TODO something other than sleep, reference previous attempt
-    eprintln!("TODO: check for pids to die instead of sleep -- {:?}", pids);
-    tokio::time::sleep(Duration::from_millis(1_500)).await;
-
-    //let mut known_pids = HashSet::new();
-    //for pid in pids {
-    //    known_pids.insert(pid);
-    //}
-    //let mut interval = tokio::time::interval(Duration::from_millis(100));
-    //tokio::time::timeout(Duration::from_millis(60_000), async {
-    //    loop {
-    //        if known_pids.is_empty() {
-    //            break;
-    //        }
-    //        let mut pids_to_remove = HashSet::new();
-    //        for &pid in &known_pids {
-    //            match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
-    //                Ok(status) => match status {
-    //                    WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) => {}
-    //                    _ => {
-    //                        dbg!(status);
-    //                    }
-    //                },
-    //                Err(nix::errno::Errno::ECHILD) => {
-    //                    pids_to_remove.insert(pid);
-    //                }
-    //                Err(e) => {
-    //                    eprintln!("Untracked error: {:?}", e);
-    //                    pids_to_remove.insert(pid);
-    //                }
-    //            }
-    //        }
-    //        for pid in pids_to_remove.iter() {
-    //            known_pids.remove(pid);
-    //        }
-    //        interval.tick().await;
-    //    }
-    //}).await.unwrap();
-    Ok(())
+/// A process we can wait on and signal without being its parent, and without
+/// racing against its pid being reused. The fd turns readable once the
+/// process has exited.
+struct PidFd(AsyncFd<OwnedFd>);
+
+impl PidFd {
+    /// Returns `None` when the process is already gone
+    fn open(pid: Pid) -> Result<Option<Self>> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+        if fd < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ESRCH) {
+                return Ok(None);
+            }
+            return Err(err.into());
+        }
+        // SAFETY: the syscall succeeded, so `fd` is a new fd owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        Ok(Some(Self(AsyncFd::with_interest(fd, Interest::READABLE)?)))
+    }
+
+    fn signal(&self, signal: Signal) -> Result<()> {
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.0.as_raw_fd(),
+                signal as libc::c_int,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            // Exited between opening the pidfd and now, which is fine
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn exited(&self) -> Result<()> {
+        // The readiness is never cleared, an exited process stays exited
+        let _ready = self.0.readable().await?;
+        Ok(())
+    }
+}
+
+/// How `ProcessTreeFds::terminate` ended the processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// They exited after `SIGTERM` to the root process
+    Terminated,
+    /// They were still around when the timeout ran out and got `SIGKILL`
+    Killed,
+}
+
+/// pidfds for every process of a `ProcessTree`, all opened up front so none
+/// of the pids can be reused by an unrelated process while we wait on them
+pub(crate) struct ProcessTreeFds {
+    root: Option<PidFd>,
+    children: Vec<PidFd>,
+}
+
+impl ProcessTreeFds {
+    pub(crate) fn open(tree: &ProcessTree) -> Result<Self> {
+        let root = PidFd::open(tree.root_pid())?;
+        let mut children = Vec::new();
+        // `all_pids` starts with the root
+        for pid in tree.all_pids().into_iter().skip(1) {
+            children.extend(PidFd::open(pid)?);
+        }
+        Ok(Self { root, children })
+    }
+
+    fn all(&self) -> impl Iterator<Item = &PidFd> {
+        self.root.iter().chain(&self.children)
+    }
+
+    /// Wait for every process to exit, however long that takes
+    pub(crate) async fn exited(&self) -> Result<()> {
+        try_join_all(self.all().map(|pidfd| pidfd.exited())).await?;
+        Ok(())
+    }
+
+    /// Wait for every process to exit, up to `timeout`. Returns whether they
+    /// did.
+    pub(crate) async fn wait(&self, timeout: Duration) -> Result<bool> {
+        match tokio::time::timeout(timeout, self.exited()).await {
+            Ok(res) => res.map(|_| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Send `SIGTERM` to the root and wait up to `timeout` for the whole tree
+    /// to exit. Anything left after that gets `SIGKILL`, and is given the same
+    /// `timeout` again before giving up.
+    pub(crate) async fn terminate(&self, timeout: Duration) -> Result<Termination> {
+        if let Some(root) = &self.root {
+            root.signal(Signal::SIGTERM)?;
+        }
+        if self.wait(timeout).await? {
+            return Ok(Termination::Terminated);
+        }
+
+        for pidfd in self.all() {
+            pidfd.signal(Signal::SIGKILL)?;
+        }
+        if self.wait(timeout).await? {
+            return Ok(Termination::Killed);
+        }
+        Err(anyhow! {"processes survived SIGKILL for {:?}", timeout})
+    }
 }
 
 pub async fn spawn_namespaced_process(
     reader_pipe: pipe::Receiver,
     writer_pipe: pipe::Sender,
-    cmd: String,
+    cmd: PathBuf,
     args: Vec<String>,
+    inherit_fds: Vec<RawFd>,
 ) -> Result<Child> {
     // Convert our async pipes into raw file descriptors for use in the preexec
     // stage which is not async.
@@ -233,6 +310,13 @@ pub async fn spawn_namespaced_process(
             setgid(Gid::from_raw(0)).unwrap();
             // 😎 We are now root.... less! 😎
 
+            // Everything the parent opens is close-on-exec. Clear that flag on
+            // the fds the target process is meant to receive, which it knows
+            // by number from its arguments.
+            for fd in &inherit_fds {
+                fcntl(*fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            }
+
             Ok(())
         });
     }
@@ -248,3 +332,38 @@ pub async fn spawn_namespaced_process(
 
     Ok(child)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_tree(script: &str) -> (std::process::Child, ProcessTree) {
+        let child = std::process::Command::new("sh")
+            .args(["-c", script])
+            .spawn()
+            .unwrap();
+        let tree = ProcessTree {
+            pid: Pid::from_raw(child.id() as i32),
+            children: Vec::new(),
+        };
+        (child, tree)
+    }
+
+    #[tokio::test]
+    async fn test_terminate() {
+        let (mut child, tree) = spawn_tree("exec sleep 60");
+        let fds = ProcessTreeFds::open(&tree).unwrap();
+        assert!(!fds.wait(Duration::from_millis(100)).await.unwrap());
+        let termination = fds.terminate(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(termination, Termination::Terminated);
+        child.wait().unwrap();
+
+        let (mut child, tree) = spawn_tree("trap '' TERM; while true; do sleep 1; done");
+        // Give the shell time to install the trap
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let fds = ProcessTreeFds::open(&tree).unwrap();
+        let termination = fds.terminate(Duration::from_millis(300)).await.unwrap();
+        assert_eq!(termination, Termination::Killed);
+        child.wait().unwrap();
+    }
+}
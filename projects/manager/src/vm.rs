@@ -1,103 +1,914 @@
 use anyhow::{anyhow, Result};
-use nix::sys::signal::{self, Signal};
+use chrono::Utc;
 use nix::unistd::{getgid, getuid, Pid};
-use std::io::Write;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::{write, File};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::unix::pipe;
 use tokio::spawn;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, Duration};
+use tokio::time::{timeout, Duration, Instant};
 
-use crate::qmp::qapi::{Event, QMPCommand};
+use crate::backup::{self, Backup, BackupKind, BackupTarget};
+use crate::devices;
+use crate::jobs::{self, JobInfo, JobStatus};
+use crate::lifecycle::{InvalidTransition, Lifecycle, StateChange, VmState};
+use crate::network;
+use crate::qapi::{self, Event as _};
+use crate::qmp::qapi::{Event, QMPCommand, QOMListArgs};
 use crate::qmp::QMP;
+use crate::spec::{DiskBus, DiskSpec, NetBackend, NicSpec, VmSpec};
+use crate::storage::{self, DiskChain, Layer};
 
 use crate::utils::build_cgroup_process_trees;
 use crate::utils::spawn_namespaced_process;
-use crate::utils::wait_for_pids;
 use crate::utils::ProcessTree;
+use crate::utils::ProcessTreeFds;
+use crate::utils::Termination;
+
+/// What actually runs a VM, as found once QEMU is up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    /// The executable of the QEMU process
+    pub binary: PathBuf,
+    /// The QEMU version, like `9.1.0` or `9.1.0 (Debian 1:9.1.0+ds-1)`
+    pub version: String,
+}
 
 #[derive(Debug)]
 pub struct VM {
     id: String,
-    running: bool,
+    spec: VmSpec,
+    lifecycle: Lifecycle,
+    /// Tasks feeding QMP events and the exit of QEMU into `lifecycle`
+    followers: Vec<JoinHandle<()>>,
     manager: Option<QMP>,
+    runtime: Option<RuntimeInfo>,
+    stop_policy: StopPolicy,
+    /// The backing chain of every disk, by disk id
+    chains: HashMap<String, DiskChain>,
+    /// The block jobs the manager started, by job id, with the disk they work
+    /// on
+    block_jobs: HashMap<String, BlockJob>,
+    /// The highest `n` of an `overlay-<n>` node, to name the next one
+    overlays: u32,
+    /// The disks exported for a backup, by disk id
+    exports: HashMap<String, Export>,
+}
+
+/// A disk exported by `VM::export_disk`
+#[derive(Debug, Clone)]
+struct Export {
+    kind: BackupKind,
+    /// The layer the bitmaps of the export are on
+    layer: Layer,
+}
+
+/// A block job changing the backing chain of a disk, which the chain only
+/// follows once the job succeeded
+#[derive(Debug, Clone)]
+enum BlockJob {
+    Commit(String),
+    Stream(String),
+}
+
+/// How `VM::stop` brings a VM down. Each stage is only tried when the ones
+/// before it did not end the VM: the guest is asked to power off through
+/// ACPI, then QEMU is asked to `quit`, then QEMU gets `SIGTERM` and finally
+/// `SIGKILL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopPolicy {
+    /// How long the guest gets to shut down after `system_powerdown`. Zero
+    /// skips asking the guest.
+    pub grace_period: Duration,
+    /// How long QEMU gets to exit after `quit`
+    pub quit_timeout: Duration,
+    /// How long QEMU gets to exit after `SIGTERM`, and again after `SIGKILL`
+    pub kill_timeout: Duration,
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        Self {
+            grace_period: Duration::from_secs(60),
+            quit_timeout: Duration::from_secs(10),
+            kill_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The stage of a `StopPolicy` that ended the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopStage {
+    /// The guest shut itself down after `system_powerdown`
+    Powerdown,
+    /// QEMU exited on `quit`
+    Quit,
+    /// QEMU exited on `SIGTERM`
+    Terminated,
+    /// QEMU had to be killed
+    Killed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StopOutcome {
+    pub stage: StopStage,
+    /// The `reason` of the `SHUTDOWN` event, such as `guest-shutdown` or
+    /// `host-qmp-quit`, when QEMU sent one before going away
+    pub reason: Option<String>,
 }
 
+/// The reason of a `SHUTDOWN` event
+fn shutdown_reason(event: &Event) -> Option<String> {
+    if event.event != "SHUTDOWN" {
+        return None;
+    }
+    let reason = event.data.as_ref()?.get("reason")?.as_str()?;
+    Some(reason.to_string())
+}
+
+/// Wait up to `deadline` for a `SHUTDOWN` event and return its reason
+async fn wait_for_shutdown(
+    events: &mut broadcast::Receiver<Event>,
+    deadline: Duration,
+) -> Option<String> {
+    let event = wait_for_event(events, "SHUTDOWN", deadline).await?;
+    shutdown_reason(&event)
+}
+
+/// Wait up to `deadline` for an event named `name`
+async fn wait_for_event(
+    events: &mut broadcast::Receiver<Event>,
+    name: &str,
+    deadline: Duration,
+) -> Option<Event> {
+    wait_for_matching(events, deadline, |event| event.event == name).await
+}
+
+/// Whether `event` tells the device `id` was unplugged
+fn device_deleted(event: &Event, id: &str) -> bool {
+    if event.event != qapi::DeviceDeletedEvent::NAME {
+        return false;
+    }
+    let Some(data) = event.data.clone() else {
+        return false;
+    };
+    match serde_json::from_value::<qapi::DeviceDeletedEvent>(data) {
+        Ok(deleted) => deleted.device.as_deref() == Some(id),
+        Err(_) => false,
+    }
+}
+
+/// The `return` value of a QMP reply, or its `error` as an `Err`
+fn checked_reply(description: &str, reply: &str) -> Result<serde_json::Value> {
+    if reply.is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    let mut reply: serde_json::Value = serde_json::from_str(reply)?;
+    if let Some(error) = reply.get("error") {
+        return Err(anyhow! {"QMP command {} failed: {}", description, error["desc"]});
+    }
+    Ok(reply["return"].take())
+}
+
+/// The size of the image `layer` reads, going by the reply to
+/// `storage::query_nodes`
+fn node_size(nodes: &[qapi::BlockDeviceInfo], layer: &Layer) -> Result<u64> {
+    storage::virtual_size(nodes, &layer.node)
+        .ok_or_else(|| anyhow! {"QEMU has no block node {}", layer.node})
+}
+
+/// Wait up to `deadline` for an event `matches` accepts
+async fn wait_for_matching(
+    events: &mut broadcast::Receiver<Event>,
+    deadline: Duration,
+    matches: impl Fn(&Event) -> bool,
+) -> Option<Event> {
+    let deadline = Instant::now() + deadline;
+    loop {
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Ok(event)) => {
+                if matches(&event) {
+                    return Some(event);
+                }
+            }
+            // Missed some events, the one we want may still come
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return None,
+        }
+    }
+}
+
+/// How long the lifecycle operations wait for QEMU to confirm them with an
+/// event
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a guest gets to release a device being unplugged. Guests act on
+/// the request in their own time; PCIe hotplug alone waits 5 seconds for the
+/// request to be cancelled.
+pub const UNPLUG_TIMEOUT: Duration = Duration::from_secs(30);
+
 impl VM {
-    pub async fn new(id: String) -> Self {
+    pub async fn new(id: String, spec: VmSpec) -> Self {
         Self {
             id,
+            spec,
+            lifecycle: Lifecycle::new(),
+            followers: Vec::new(),
             manager: None,
-            running: false,
+            runtime: None,
+            stop_policy: StopPolicy::default(),
+            chains: HashMap::new(),
+            block_jobs: HashMap::new(),
+            overlays: 0,
+            exports: HashMap::new(),
         }
     }
 
     pub async fn start(&mut self) -> Result<mpsc::Receiver<Event>> {
+        self.lifecycle.transition(VmState::Starting, "start")?;
+        let events = self.boot().await;
+        if events.is_err() {
+            let _ = self.lifecycle.transition(VmState::Crashed, "start");
+        }
+        events
+    }
+
+    async fn boot(&mut self) -> Result<mpsc::Receiver<Event>> {
+        self.chains = self
+            .spec
+            .disks
+            .iter()
+            .map(|disk| (disk.id.clone(), DiskChain::new(disk)))
+            .collect();
+        self.block_jobs.clear();
+        self.exports.clear();
+
         // Try to reattach to a running instance if it exists
-        if self.running {
-            todo! {"called start on a running vm"};
+        if let Some(events) = self.reattach().await? {
+            return Ok(events);
         }
 
-        match self.reattach().await {
-            Ok(events) => return Ok(events),
-            Err(_) => {}
+        // A fresh guest; NICs without a MAC address get one now, which the
+        // caller persists with the spec so the guest keeps it
+        network::assign_macs(&self.id, &mut self.spec.nics);
+
+        // A QEMU that fails to come up would wait in preconfig forever
+        match self.launch().await {
+            Ok(events) => Ok(events),
+            Err(e) => {
+                if let Err(e) = self.discard().await {
+                    eprintln!("failed to clean up QEMU of {} -- {}", self.id, e);
+                }
+                Err(e)
+            }
         }
+    }
 
+    /// Spawn QEMU and build the guest from the spec while it waits in
+    /// preconfig, then let the guest run
+    async fn launch(&mut self) -> Result<mpsc::Receiver<Event>> {
         // There is no qemu process running. We can now start qemu, but the vcpu
         // threads do not start running until after we exit the preconfig stage.
-        self.prestart().await.unwrap();
+        let tap_fds = self.prestart().await?;
 
         // The qemu process should be running and waiting for us to attach to
         // the socket. When we do, we are returned a events receiver object that
         // gets async generated events from qmp.
         let events = self.attach_manager().await?;
+        self.lifecycle.transition(VmState::Preconfig, "start")?;
+        self.check_emulator().await?;
 
         // At this point, the qemu process is running, but no untrusted code has
         // executed in the VM yet. The qemu process is waiting for us to send
         // any additional configuration settings. Limited `QMPCommands` are
-        // available in this mode.
-        //let cmd = QMPCommand::QOMList{ arguments: QOMListArgs {path: "/machine".to_string()}};
-        //let cmd = QMPCommand::QOMListTypes{ arguments: QOMListTypesArgs::default()};
-        //let reply = self.qmp(cmd).await?;
-        //eprintln!("{reply}");
-        //dbg!(reply);
+        // available in this mode, but enough to build what the guest devices
+        // on the command line need: memory, NUMA nodes and the backends of the
+        // disks and NICs.
+        for cmd in devices::preconfig(&self.spec, &tap_fds) {
+            self.qmp_checked(cmd).await?;
+        }
 
         // Send `x-exit-preconfig` which tells qemu to continue booting. As soon
         // as this command is sent, untrusted user code may be running inside of
         // the vm.
-        let _reply = self.qmp(QMPCommand::XExitPreconfig).await?;
-        //dbg!(_reply);
-
-        self.running = true;
+        self.qmp_checked(QMPCommand::XExitPreconfig).await?;
+        self.lifecycle.transition(VmState::Running, "start")?;
         Ok(events)
     }
 
-    pub async fn stop(&mut self) -> Result<()> {
-        sleep(Duration::from_millis(100)).await; // TODO -- sleeping to drain events
+    /// Kill the QEMU of this VM without asking anything of the guest, for one
+    /// without a guest worth keeping
+    async fn discard(&mut self) -> Result<()> {
+        for follower in self.followers.drain(..) {
+            follower.abort();
+        }
+        if let Some(mut manager) = self.manager.take() {
+            manager.shutdown().await;
+        }
+        self.runtime = None;
         if let Some(tree) = self.build_pid_tree().await? {
-            let status = self.qmp(QMPCommand::QueryStatus).await?;
-            eprintln!("unused poweroff status -- {:?}", status);
+            let processes = ProcessTreeFds::open(&tree)?;
+            processes.terminate(self.stop_policy.kill_timeout).await?;
+        }
+        Ok(())
+    }
+
+    /// Bring the VM down following its `StopPolicy`. Returns `None` when no
+    /// QEMU process was running.
+    pub async fn stop(&mut self) -> Result<Option<StopOutcome>> {
+        let Some(tree) = self.build_pid_tree().await? else {
+            return Ok(None);
+        };
+        self.lifecycle.transition(VmState::Stopping, "stop")?;
+        let processes = ProcessTreeFds::open(&tree)?;
+        let policy = self.stop_policy;
+        let mut stage = None;
+        let mut reason = None;
+        let mut exited = false;
+
+        if let Some(mut events) = self.manager.as_ref().map(QMP::subscribe) {
+            if !policy.grace_period.is_zero() {
+                match self.qmp_checked(QMPCommand::SystemPowerdown).await {
+                    Ok(_) => {
+                        reason = wait_for_shutdown(&mut events, policy.grace_period).await;
+                        if reason.is_some() {
+                            stage = Some(StopStage::Powerdown);
+                        }
+                    }
+                    Err(e) => eprintln!("system_powerdown failed for {} -- {}", self.id, e),
+                }
+            }
 
-            if let Some(ref mut manager) = self.manager {
-                manager.shutdown().await;
-                self.manager = None;
+            // With `-no-shutdown` QEMU stays around after the guest powered
+            // off, so it is asked to quit either way
+            let quit = timeout(policy.quit_timeout, self.qmp_checked(QMPCommand::Quit)).await;
+            if let Ok(Err(e)) = quit {
+                eprintln!("quit failed for {} -- {}", self.id, e);
+            }
+            exited = processes.wait(policy.quit_timeout).await?;
+            if exited && reason.is_none() {
+                // QEMU announces `quit` with a `SHUTDOWN` of its own
+                reason = wait_for_shutdown(&mut events, Duration::ZERO).await;
+            }
+        }
+
+        if let Some(mut manager) = self.manager.take() {
+            manager.shutdown().await;
+        }
+
+        let stopped_by = if exited {
+            StopStage::Quit
+        } else {
+            match processes.terminate(policy.kill_timeout).await? {
+                Termination::Terminated => StopStage::Terminated,
+                Termination::Killed => StopStage::Killed,
+            }
+        };
+
+        // Nothing that happens to the old QEMU from here on may leak into the
+        // state of a VM started again later
+        for follower in self.followers.drain(..) {
+            follower.abort();
+        }
+        self.runtime = None;
+        self.lifecycle.transition(VmState::Stopped, "stop")?;
+        Ok(Some(StopOutcome {
+            stage: stage.unwrap_or(stopped_by),
+            reason,
+        }))
+    }
+
+    /// Pause the vCPUs, confirmed by the `STOP` event
+    pub async fn pause(&mut self) -> Result<()> {
+        self.expect_transition(VmState::Paused)?;
+        self.qmp_confirmed(QMPCommand::Stop, "STOP").await?;
+        Ok(())
+    }
+
+    /// Resume the vCPUs of a paused VM, confirmed by the `RESUME` event
+    pub async fn resume(&mut self) -> Result<()> {
+        self.expect_transition(VmState::Running)?;
+        self.qmp_confirmed(QMPCommand::Cont, "RESUME").await?;
+        Ok(())
+    }
+
+    /// Reset the guest like a press of the reset button, without restarting
+    /// QEMU. Confirmed by the `RESET` event.
+    pub async fn reset(&mut self) -> Result<()> {
+        self.expect_guest("reset")?;
+        self.qmp_confirmed(QMPCommand::SystemReset, "RESET").await?;
+        Ok(())
+    }
+
+    /// Wake a guest up from suspend to RAM, confirmed by the `WAKEUP` event
+    pub async fn wakeup(&mut self) -> Result<()> {
+        self.expect_guest("wake up")?;
+        self.qmp_confirmed(QMPCommand::SystemWakeup, "WAKEUP")
+            .await?;
+        Ok(())
+    }
+
+    /// Inject an NMI into every vCPU, to get a hung guest to dump its state.
+    /// QEMU has no event for this, the reply is all the confirmation there is.
+    pub async fn inject_nmi(&mut self) -> Result<()> {
+        self.expect_guest("inject an NMI into")?;
+        self.qmp_checked(QMPCommand::InjectNmi).await?;
+        Ok(())
+    }
+
+    /// Hotplug `disk` into the VM and add it to the spec
+    pub async fn add_disk(&mut self, disk: DiskSpec) -> Result<()> {
+        self.expect_guest("add a disk to")?;
+        let mut spec = self.spec.clone();
+        spec.disks.push(disk.clone());
+        spec.validate()?;
+
+        if disk.bus == DiskBus::Scsi && !self.has_device(storage::SCSI_CONTROLLER).await {
+            let controller = storage::scsi_controller();
+            self.manager()?.execute_command(&controller).await?;
+        }
+        for cmd in storage::attach(&disk) {
+            if let Err(e) = self.qmp_checked(cmd).await {
+                // Whatever was added before the failure must not linger and
+                // block adding the disk again. Deleting the parts that never
+                // got added fails, which is fine.
+                for cmd in DiskChain::new(&disk).release() {
+                    let _ = self.qmp_checked(cmd).await;
+                }
+                return Err(e);
+            }
+        }
+        self.chains.insert(disk.id.clone(), DiskChain::new(&disk));
+        self.spec = spec;
+        Ok(())
+    }
+
+    /// Unplug the disk `id` from the VM, then free its block nodes and drop
+    /// it from the spec. The guest has `UNPLUG_TIMEOUT` to release the device;
+    /// if it does not, the disk stays in the spec and removal can be retried.
+    pub async fn remove_disk(&mut self, id: &str) -> Result<()> {
+        self.expect_guest("remove a disk from")?;
+        let index = self.disk_index(id)?;
+        let disk = self.spec.disks[index].clone();
+        // Subscribed before sending so the event can not be missed
+        let mut events = self.manager()?.subscribe();
+        self.qmp_checked(storage::unplug(&disk)).await?;
+        let deleted =
+            wait_for_matching(&mut events, UNPLUG_TIMEOUT, |e| device_deleted(e, id)).await;
+        if deleted.is_none() {
+            return Err(anyhow! {"guest did not release disk {} within {:?}", id, UNPLUG_TIMEOUT});
+        }
+        for cmd in self.chain(id)?.release() {
+            self.qmp_checked(cmd).await?;
+        }
+        self.chains.remove(id);
+        self.spec.disks.remove(index);
+        Ok(())
+    }
+
+    /// Hotplug `nic` into the VM and add it to the spec. A NIC without a MAC
+    /// address gets one assigned, which the spec records.
+    pub async fn add_nic(&mut self, nic: NicSpec) -> Result<()> {
+        self.expect_guest("add a NIC to")?;
+        let mut spec = self.spec.clone();
+        spec.nics.push(nic);
+        network::assign_macs(&self.id, &mut spec.nics);
+        spec.validate()?;
+        let nic = spec.nics.last().unwrap().clone();
+
+        let tap_fd = match &nic.backend {
+            NetBackend::Tap { ifname } => {
+                let name = network::tap_fd_name(&nic.id);
+                let fd = network::open_tap(ifname)?;
+                self.qmp_checked_with_fd(network::getfd(&name), fd).await?;
+                Some(name)
+            }
+            _ => None,
+        };
+        for cmd in network::attach(&nic, tap_fd.as_deref()) {
+            if let Err(e) = self.qmp_checked(cmd).await {
+                // Same as for disks, nothing may linger and block adding the
+                // NIC again. Whatever was never added fails to go, which is
+                // fine; a backend took its fd along.
+                let _ = self.qmp_checked(network::release(&nic.id)).await;
+                if let Some(name) = &tap_fd {
+                    let _ = self.qmp_checked(network::closefd(name)).await;
+                }
+                return Err(e);
+            }
+        }
+        self.spec = spec;
+        Ok(())
+    }
+
+    /// Unplug the NIC `id` from the VM, then remove its backend and drop it
+    /// from the spec. The guest has `UNPLUG_TIMEOUT` to release the device.
+    pub async fn remove_nic(&mut self, id: &str) -> Result<()> {
+        self.expect_guest("remove a NIC from")?;
+        let index = self
+            .spec
+            .nics
+            .iter()
+            .position(|nic| nic.id == id)
+            .ok_or_else(|| anyhow! {"VM {} has no NIC {}", self.id, id})?;
+        // Subscribed before sending so the event can not be missed
+        let mut events = self.manager()?.subscribe();
+        self.qmp_checked(network::unplug(id)).await?;
+        let deleted =
+            wait_for_matching(&mut events, UNPLUG_TIMEOUT, |e| device_deleted(e, id)).await;
+        if deleted.is_none() {
+            return Err(anyhow! {"guest did not release NIC {} within {:?}", id, UNPLUG_TIMEOUT});
+        }
+        self.qmp_checked(network::release(id)).await?;
+        self.spec.nics.remove(index);
+        Ok(())
+    }
+
+    /// Resize the image of the disk `id` to `size` bytes while the guest runs.
+    /// Growing is always safe; shrinking loses whatever the guest stored past
+    /// the new end.
+    pub async fn resize_disk(&mut self, id: &str, size: u64) -> Result<()> {
+        self.expect_guest("resize a disk of")?;
+        let size = i64::try_from(size)?;
+        let cmd = self.chain(id)?.resize(size);
+        self.qmp_checked(cmd).await?;
+        Ok(())
+    }
+
+    /// Take a live external snapshot of the disk `id`: its image is only read
+    /// from now on and the guest writes to a new qcow2 overlay in the regular
+    /// file `path`, which must not exist yet. The overlay takes the place of
+    /// the image in the spec.
+    pub async fn snapshot_disk(&mut self, id: &str, path: &Path) -> Result<()> {
+        self.expect_guest("snapshot a disk of")?;
+        let index = self.disk_index(id)?;
+        if self.spec.disks[index].read_only {
+            return Err(anyhow! {"disk {} is read only, there is nothing to snapshot", id});
+        }
+        let backing = self.chain(id)?.active().clone();
+        let nodes = self.block_nodes().await?;
+        let size = node_size(&nodes, &backing)?;
+
+        self.overlays += 1;
+        let overlay = Layer::overlay(format!("overlay-{}", self.overlays), path);
+        self.create_qcow2(&overlay, size, Some(&backing)).await?;
+        self.qmp_checked(storage::add_image(&overlay, None)).await?;
+        let mut chain = self.chain(id)?.clone();
+        if let Err(e) = self.qmp_checked(chain.snapshot(overlay.clone())).await {
+            let _ = self.qmp_checked(storage::delete_node(&overlay.node)).await;
+            return Err(e);
+        }
+        self.chains.insert(id.to_string(), chain);
+        self.set_image(index, &overlay);
+        Ok(())
+    }
+
+    /// Back up the disk `id` into a new qcow2 image at `path`. Without
+    /// `parent` the backup is a full one. With it, it only holds what changed
+    /// since `parent`, the last backup of the disk, and the image is backed by
+    /// the one of `parent` if there is one.
+    pub async fn backup_disk(
+        &mut self,
+        id: &str,
+        path: &Path,
+        parent: Option<&Backup>,
+    ) -> Result<Backup> {
+        self.expect_guest("back up a disk of")?;
+        let layer = self.chain(id)?.active().clone();
+        let nodes = self.block_nodes().await?;
+        let size = node_size(&nodes, &layer)?;
+        let has_bitmap = backup::has_bitmap(&nodes, &layer.node);
+        let kind = backup::kind(id, parent, has_bitmap)?;
+        // Only the file and format of the backing image go into the target
+        let backing = match parent.map(|parent| &parent.target) {
+            Some(BackupTarget::Image(path)) => Some(Layer::overlay(&layer.node, path)),
+            _ => None,
+        };
+
+        let target = Layer::overlay(backup::target_node(id), path);
+        self.create_qcow2(&target, size, backing.as_ref()).await?;
+        self.qmp_checked(storage::add_image(&target, None)).await?;
+        let job = target.node.clone();
+        let started_at = Utc::now();
+        let cmd = backup::push(&job, &layer, &target.node, kind, has_bitmap);
+        let pushed = self.run_job(&job, cmd).await;
+        if pushed.is_err() && !has_bitmap {
+            // The bitmap the backup added holds the changes since a backup
+            // that never happened
+            let _ = self.qmp_checked(backup::remove_bitmap(&layer)).await;
+        }
+        let deleted = self.qmp_checked(storage::delete_node(&target.node)).await;
+        pushed?;
+        deleted?;
+        Ok(Backup {
+            disk: id.to_string(),
+            kind,
+            target: BackupTarget::Image(path.to_path_buf()),
+            started_at,
+        })
+    }
+
+    /// Export the disk `id` as it is now over the NBD server of the VM, for a
+    /// backup tool to pull. Without `parent` the export is a full backup,
+    /// with it the export also tells what changed since `parent`, the last
+    /// backup of the disk. Until `end_export`, whatever the guest overwrites
+    /// is first copied into a qcow2 image at `scratch`, which is left behind.
+    pub async fn export_disk(
+        &mut self,
+        id: &str,
+        scratch: &Path,
+        parent: Option<&Backup>,
+    ) -> Result<Backup> {
+        self.expect_guest("export a disk of")?;
+        if self.exports.contains_key(id) {
+            return Err(anyhow! {"disk {} is already exported", id});
+        }
+        let layer = self.chain(id)?.active().clone();
+        let nodes = self.block_nodes().await?;
+        let size = node_size(&nodes, &layer)?;
+        let has_bitmap = backup::has_bitmap(&nodes, &layer.node);
+        let kind = backup::kind(id, parent, has_bitmap)?;
+
+        // Fails when an earlier export started the server already; exporting
+        // fails on its own if there is none
+        let socket = self.nbd_socket();
+        let _ = self.qmp_checked(backup::start_nbd(&socket)).await;
+
+        let fleecing = Layer::overlay(backup::fleecing_node(id), scratch);
+        self.create_qcow2(&fleecing, size, None).await?;
+        self.qmp_checked(storage::add_image(&fleecing, Some(&layer)))
+            .await?;
+        let job = fleecing.node.clone();
+        let started_at = Utc::now();
+        let cmd = backup::fleece(&job, &layer, &fleecing.node, kind, has_bitmap);
+        if let Err(e) = self.qmp_checked(cmd).await {
+            let _ = self.qmp_checked(storage::delete_node(&fleecing.node)).await;
+            return Err(e);
+        }
+        let export = Export { kind, layer };
+        self.exports.insert(id.to_string(), export.clone());
+        let cmd = backup::export(id, &export.layer, &fleecing.node, kind);
+        if let Err(e) = self.qmp_checked(cmd).await {
+            let _ = self.release_export(id, false).await;
+            return Err(e);
+        }
+        Ok(Backup {
+            disk: id.to_string(),
+            kind,
+            target: BackupTarget::Nbd {
+                socket,
+                export: backup::export_id(id),
+            },
+            started_at,
+        })
+    }
+
+    /// Take down the export of the disk `id`. Unless the backup tool `pulled`
+    /// all of it, the changes it held are left for the next backup. Fails
+    /// while clients are still connected.
+    pub async fn end_export(&mut self, id: &str, pulled: bool) -> Result<()> {
+        if !self.exports.contains_key(id) {
+            return Err(anyhow! {"disk {} is not exported", id});
+        }
+        // Subscribed before sending so the event can not be missed
+        let mut events = self.manager()?.subscribe();
+        self.qmp_checked(backup::unexport(id)).await?;
+        let export = backup::export_id(id);
+        let deleted = wait_for_matching(&mut events, CONFIRM_TIMEOUT, |e| {
+            backup::export_deleted(e, &export)
+        })
+        .await;
+        if deleted.is_none() {
+            return Err(anyhow! {"export {} was not removed within {:?}", export, CONFIRM_TIMEOUT});
+        }
+        self.release_export(id, pulled).await
+    }
+
+    /// Stop keeping the point in time view of the disk `id` and settle its
+    /// bitmaps
+    async fn release_export(&mut self, id: &str, pulled: bool) -> Result<()> {
+        let Some(export) = self.exports.remove(id) else {
+            return Ok(());
+        };
+        // The job copying into the scratch image runs until it is cancelled,
+        // after which it fails by design
+        let job = backup::fleecing_node(id);
+        self.qmp_checked(jobs::cancel(&job)).await?;
+        let _ = self.wait_job(&job).await;
+        self.qmp_checked(storage::delete_node(&job)).await?;
+        if let Some(cmd) = backup::unfreeze(&export.layer, export.kind, pulled) {
+            self.qmp_checked(cmd).await?;
+        }
+        Ok(())
+    }
+
+    /// Start merging the overlays of the disk `id` back into its base image.
+    /// Returns the id of the job; once `wait_job` saw it succeed, the guest
+    /// uses the base image again, which also takes the place of the overlays
+    /// in the spec. The overlay files are left on disk.
+    pub async fn commit_disk(&mut self, id: &str) -> Result<String> {
+        self.expect_guest("commit a disk of")?;
+        let job = format!("commit-{}", id);
+        let cmd = self.chain(id)?.commit(&job)?;
+        self.qmp_checked(cmd).await?;
+        self.block_jobs
+            .insert(job.clone(), BlockJob::Commit(id.to_string()));
+        Ok(job)
+    }
+
+    /// Start copying the backing chain of the disk `id` into its active layer.
+    /// Returns the id of the job; once `wait_job` saw it succeed, the image in
+    /// the spec no longer needs its backing files.
+    pub async fn stream_disk(&mut self, id: &str) -> Result<String> {
+        self.expect_guest("stream a disk of")?;
+        let job = format!("stream-{}", id);
+        let cmd = self.chain(id)?.stream(&job)?;
+        self.qmp_checked(cmd).await?;
+        self.block_jobs
+            .insert(job.clone(), BlockJob::Stream(id.to_string()));
+        Ok(job)
+    }
+
+    /// The jobs QEMU runs, with their progress
+    pub async fn jobs(&mut self) -> Result<Vec<JobInfo>> {
+        self.manager()?.execute_command(&jobs::query()).await
+    }
+
+    pub async fn job(&mut self, id: &str) -> Result<JobInfo> {
+        let jobs = self.jobs().await?;
+        jobs.into_iter()
+            .find(|job| job.id == id)
+            .ok_or_else(|| anyhow! {"QEMU has no job {}", id})
+    }
+
+    /// Ask QEMU to abort the job `id`; `wait_job` then fails
+    pub async fn cancel_job(&mut self, id: &str) -> Result<()> {
+        self.qmp_checked(jobs::cancel(id)).await?;
+        Ok(())
+    }
+
+    /// Follow the job `id` until it concludes, completing it once it is
+    /// ready, then dismiss it. Fails with the error of the job if it failed or
+    /// was cancelled. Jobs may take hours, so there is no timeout.
+    pub async fn wait_job(&mut self, id: &str) -> Result<()> {
+        // Subscribed before asking so no change can be missed
+        let mut events = self.manager()?.subscribe();
+        let mut status = self.job(id).await?.status;
+        let mut completed = false;
+        while status != JobStatus::Concluded {
+            if status == JobStatus::Ready && !completed {
+                self.qmp_checked(jobs::complete(id)).await?;
+                completed = true;
+            }
+            status = match events.recv().await {
+                Ok(event) => match jobs::status_change(&event, id) {
+                    Some(status) => status,
+                    None => continue,
+                },
+                // The change we wait for may be among the missed events
+                Err(broadcast::error::RecvError::Lagged(_)) => self.job(id).await?.status,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(anyhow! {"QMP connection closed while waiting for job {}", id});
+                }
             };
+        }
 
-            signal::kill(tree.root_pid(), Signal::SIGTERM).unwrap();
-            wait_for_pids(tree.all_pids()).await.unwrap();
+        let error = self.job(id).await?.error;
+        self.qmp_checked(jobs::dismiss(id)).await?;
+        let block_job = self.block_jobs.remove(id);
+        if let Some(error) = error {
+            return Err(anyhow! {"job {} failed: {}", id, error});
+        }
+        if let Some(block_job) = block_job {
+            self.finish_block_job(block_job).await?;
+        }
+        Ok(())
+    }
+
+    /// Create a qcow2 image of `size` bytes in the new regular file of
+    /// `image`, backed by the image of `backing` if there is one
+    async fn create_qcow2(
+        &mut self,
+        image: &Layer,
+        size: u64,
+        backing: Option<&Layer>,
+    ) -> Result<()> {
+        let job = format!("create-{}", image.file);
+        self.run_job(&job, storage::create_file(&job, &image.path))
+            .await?;
+        self.qmp_checked(storage::open_file(image)).await?;
+        let job = format!("create-{}", image.node);
+        let created = self
+            .run_job(&job, storage::create_qcow2(&job, image, size, backing))
+            .await;
+        self.qmp_checked(storage::delete_node(&image.file)).await?;
+        created
+    }
+
+    async fn block_nodes(&mut self) -> Result<Vec<qapi::BlockDeviceInfo>> {
+        self.manager()?
+            .execute_command(&storage::query_nodes())
+            .await
+    }
+
+    /// Start the job `id` with `cmd` and wait for it
+    async fn run_job(&mut self, id: &str, cmd: QMPCommand) -> Result<()> {
+        self.qmp_checked(cmd).await?;
+        self.wait_job(id).await
+    }
+
+    /// Follow a block job that succeeded in the chain of its disk and drop
+    /// the nodes it left unused
+    async fn finish_block_job(&mut self, block_job: BlockJob) -> Result<()> {
+        let (BlockJob::Commit(id) | BlockJob::Stream(id)) = &block_job;
+        let index = self.disk_index(id)?;
+        let Some(chain) = self.chains.get_mut(id) else {
+            return Err(anyhow! {"VM {} has no disk {}", self.id, id});
+        };
+        let unused = match block_job {
+            BlockJob::Commit(_) => chain.committed(),
+            BlockJob::Stream(_) => chain.streamed(),
+        };
+        let active = chain.active().clone();
+        self.set_image(index, &active);
+        for cmd in unused {
+            self.qmp_checked(cmd).await?;
+        }
+        Ok(())
+    }
+
+    /// Point the disk at `index` in the spec at the image of `layer`
+    fn set_image(&mut self, index: usize, layer: &Layer) {
+        let disk = &mut self.spec.disks[index];
+        disk.path = layer.path.clone();
+        disk.format = layer.format;
+        disk.protocol = layer.protocol;
+    }
+
+    /// The socket the NBD server of the backup exports listens on. It is in
+    /// the runtime directory of the user, so no other user can put anything
+    /// at the path first or connect to it.
+    fn nbd_socket(&self) -> PathBuf {
+        let runtime_dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(format!("/run/user/{}", getuid())),
+        };
+        runtime_dir.join(format!("qrab-{}-nbd.sock", self.id))
+    }
+
+    fn chain(&self, id: &str) -> Result<&DiskChain> {
+        self.chains
+            .get(id)
+            .ok_or_else(|| anyhow! {"VM {} has no disk {}", self.id, id})
+    }
+
+    fn disk_index(&self, id: &str) -> Result<usize> {
+        self.spec
+            .disks
+            .iter()
+            .position(|disk| disk.id == id)
+            .ok_or_else(|| anyhow! {"VM {} has no disk {}", self.id, id})
+    }
+
+    /// Whether QEMU has a device with the id `id`
+    async fn has_device(&mut self, id: &str) -> bool {
+        let path = format!("/machine/peripheral/{}", id);
+        let cmd = QMPCommand::QOMList {
+            arguments: QOMListArgs { path },
+        };
+        self.qmp_checked(cmd).await.is_ok()
+    }
+
+    fn expect_transition(&self, to: VmState) -> Result<(), InvalidTransition> {
+        let from = self.lifecycle.state();
+        if !from.can_transition_to(to) {
+            return Err(InvalidTransition { from, to });
+        }
+        Ok(())
+    }
 
-            self.running = false;
+    /// Fail unless there is a guest to act on
+    fn expect_guest(&self, action: &str) -> Result<()> {
+        let state = self.lifecycle.state();
+        if !matches!(state, VmState::Running | VmState::Paused) {
+            return Err(anyhow! {"can not {} a {} VM", action, state});
         }
         Ok(())
     }
 
     pub async fn restart(&mut self) -> Result<mpsc::Receiver<Event>> {
-        self.stop().await.unwrap();
+        self.stop().await?;
         if let Some(_) = self.build_pid_tree().await? {
-            return Err(anyhow! {"VM processes are still running; this is a race condition"});
+            return Err(anyhow! {"VM processes are still running after being stopped"});
         }
         let events = self.start().await?;
         Ok(events)
@@ -130,32 +941,156 @@ impl VM {
     }
 
     async fn attach_manager(&mut self) -> Result<mpsc::Receiver<Event>> {
-        sleep(Duration::from_millis(1_000)).await; // TODO WAIT FOR SOCKET TO EXIST in a better way
+        // No waiting for the socket is needed; `prestart` binds it before QEMU
+        // is even spawned and it stays in place for as long as QEMU runs.
 
         // Attempt to reattach to the QMP socket
         let socket_path_str = self.qmp_socket();
         let socket_path = Path::new(&socket_path_str);
-        let (manager, events) = QMP::new(socket_path).await?;
+        let (mut manager, events) = QMP::new(socket_path).await?;
+        manager.set_no_success_response(qapi::NO_SUCCESS_RESPONSE);
+        self.follow(&manager).await?;
         self.manager = Some(manager);
         // We successfully reattached to this socket
+        self.runtime = Some(self.query_runtime().await?);
 
         Ok(events)
     }
 
-    async fn reattach(&mut self) -> Result<mpsc::Receiver<Event>> {
-        if let Some(_) = self.build_pid_tree().await? {
-            // Our internal state says we are not running, but there is a
-            // process running in the cgroup.
-            if let Some(_) = &self.manager {
-                todo! {r#"This should never happen. Our internal"
-                        "self.running state is `false`, but the manager"
-                        "is saying it is already init"#};
+    /// Keep `lifecycle` up to date with the QMP events and the exit of QEMU.
+    /// With `-no-shutdown` QEMU stays around after the guest powered off on
+    /// its own, so it is terminated then and the VM ends up stopped.
+    async fn follow(&mut self, manager: &QMP) -> Result<()> {
+        let processes = match self.build_pid_tree().await? {
+            Some(tree) => Some(Arc::new(ProcessTreeFds::open(&tree)?)),
+            None => None,
+        };
+
+        let lifecycle = self.lifecycle.clone();
+        let mut events = manager.subscribe();
+        let id = self.id.clone();
+        let kill_timeout = self.stop_policy.kill_timeout;
+        let guest_processes = processes.clone();
+        self.followers.push(spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                // `VM::stop` takes care of QEMU when it is the one stopping
+                let stopping = lifecycle.state() == VmState::Stopping;
+                lifecycle.on_event(&event);
+                if stopping || lifecycle.state() != VmState::Stopping {
+                    continue;
+                }
+                if let Some(processes) = &guest_processes {
+                    if let Err(e) = processes.terminate(kill_timeout).await {
+                        eprintln!("failed to end QEMU of {} after shutdown -- {}", id, e);
+                    }
+                }
             }
-            let events = self.attach_manager().await?;
-            self.running = true;
-            return Ok(events);
+        }));
+
+        if let Some(processes) = processes {
+            let lifecycle = self.lifecycle.clone();
+            self.followers.push(spawn(async move {
+                if processes.exited().await.is_ok() {
+                    lifecycle.on_exit();
+                }
+            }));
+        }
+        Ok(())
+    }
+
+    async fn query_runtime(&mut self) -> Result<RuntimeInfo> {
+        let Some(tree) = self.build_pid_tree().await? else {
+            return Err(anyhow! {"no QEMU process found for the QMP socket"});
+        };
+        let binary = tokio::fs::read_link(format!("/proc/{}/exe", tree.root_pid())).await?;
+
+        let reply = self.qmp_checked(QMPCommand::QueryVersion).await?;
+        let qemu = &reply["qemu"];
+        let mut version = format!("{}.{}.{}", qemu["major"], qemu["minor"], qemu["micro"]);
+        if let Some(package) = reply["package"].as_str().filter(|p| !p.is_empty()) {
+            version.push_str(&format!(" ({})", package));
+        }
+        Ok(RuntimeInfo { binary, version })
+    }
+
+    /// Make sure the QEMU we started is the one the spec asks for. A binary
+    /// pinned by path may well be built for another target, and QEMU accepts
+    /// some machine types on more than one.
+    async fn check_emulator(&mut self) -> Result<()> {
+        let arch = self.spec.emulator.arch.as_str();
+        let target = self.qmp_checked(QMPCommand::QueryTarget).await?;
+        if target["arch"] != arch {
+            return Err(anyhow! {
+                "{} emulates {} but the VM is {}",
+                self.spec.emulator.binary().display(),
+                target["arch"],
+                arch
+            });
+        }
+
+        let machines = self.qmp_checked(QMPCommand::QueryMachines).await?;
+        let machine = self.spec.machine_type();
+        let supported = machines
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|m| m["name"] == machine || m["alias"] == machine);
+        if !supported {
+            return Err(anyhow! {
+                "{} does not support machine type {}",
+                self.spec.emulator.binary().display(),
+                machine
+            });
+        }
+        Ok(())
+    }
+
+    /// Attach to a QEMU left running by an earlier manager. Returns `None`
+    /// when there is none, or when it had no guest worth keeping: one still
+    /// in preconfig was left half built by a manager that went away during
+    /// `start`, and one whose guest shut down would never run again. Those
+    /// are killed so a fresh QEMU can take their place.
+    async fn reattach(&mut self) -> Result<Option<mpsc::Receiver<Event>>> {
+        if self.build_pid_tree().await?.is_none() {
+            return Ok(None);
         }
-        Err(anyhow! {"no running process found to reattach to"})
+        let events = self.attach_manager().await?;
+        let status = self.qmp_checked(QMPCommand::QueryStatus).await?;
+        let state = VmState::from_run_status(status["status"].as_str().unwrap_or_default());
+        if matches!(state, VmState::Preconfig | VmState::Stopping) {
+            self.discard().await?;
+            return Ok(None);
+        }
+        self.lifecycle.transition(state, "reattach")?;
+        self.discover_chains().await?;
+        Ok(Some(events))
+    }
+
+    /// Pick up the overlays the running QEMU has on top of each disk, and
+    /// keep naming new ones after the highest it has
+    async fn discover_chains(&mut self) -> Result<()> {
+        let blocks = self
+            .manager()?
+            .execute_command(&storage::query_block())
+            .await?;
+        let nodes = self.block_nodes().await?;
+        self.chains = self
+            .spec
+            .disks
+            .iter()
+            .map(|disk| (disk.id.clone(), DiskChain::discover(disk, &blocks, &nodes)))
+            .collect();
+        self.overlays = nodes
+            .iter()
+            .filter_map(|info| storage::overlay_number(info.node_name.as_deref()?))
+            .max()
+            .unwrap_or(0);
+        Ok(())
     }
 
     /// Help a forked process elevate to root in its user namespace
@@ -218,31 +1153,81 @@ impl VM {
         Ok(handle)
     }
 
-    async fn prestart(&mut self) -> Result<()> {
+    pub fn spec(&self) -> &VmSpec {
+        &self.spec
+    }
+
+    pub fn state(&self) -> VmState {
+        self.lifecycle.state()
+    }
+
+    /// Every state change from now on, for persisting or displaying them
+    pub fn subscribe_state(&self) -> broadcast::Receiver<StateChange> {
+        self.lifecycle.subscribe()
+    }
+
+    pub fn set_stop_policy(&mut self, policy: StopPolicy) {
+        self.stop_policy = policy;
+    }
+
+    /// The QEMU binary and version running this VM, known once it is attached
+    pub fn runtime(&self) -> Option<&RuntimeInfo> {
+        self.runtime.as_ref()
+    }
+
+    fn bind_qmp_socket(&self) -> Result<UnixListener> {
+        let path = self.qmp_socket();
+        // Only reached when no QEMU is running for this VM, so anything at the
+        // path was left behind by one that exited
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(UnixListener::bind(&path)?)
+    }
+
+    /// Spawn QEMU waiting in preconfig. Returns the fds of the tap devices
+    /// QEMU inherited, by NIC id.
+    async fn prestart(&mut self) -> Result<HashMap<String, String>> {
+        self.spec.validate()?;
+
         // Default args. This disables defaults and config loading across the
-        // board. Maybe `-mem-prealloc` doesn't belong here long term?
-        let qmp_unix = format!("unix:{},server,wait=on", self.qmp_socket());
-        let cmd = "qemu-system-x86_64".to_string();
-        let args = vec![
+        // board. Memory preallocation is set on the memory backends instead.
+        // There is no `-no-reboot`, which would turn every reset, including
+        // `VM::reset`, into a shutdown.
+        //
+        // The QMP socket is created and put in the listening state here, then
+        // handed to QEMU as an open fd. Connections made before QEMU gets
+        // around to accepting them simply wait in the backlog, so there is no
+        // window where the socket does not exist yet.
+        let qmp_listener = self.bind_qmp_socket()?;
+        let qmp_fd = qmp_listener.as_raw_fd();
+        // Taps are opened here, where the manager still has the privileges to
+        // do so, and inherited by QEMU like the QMP socket
+        let taps = network::open_taps(&self.spec)?;
+        let mut inherit_fds = vec![qmp_fd];
+        inherit_fds.extend(taps.iter().map(|(_, fd)| fd.as_raw_fd()));
+        let cmd = self.spec.emulator.binary();
+        let mut args = vec![
             "--preconfig".to_string(),
             "-run-with".to_string(),
             "async-teardown=on".to_string(),
-            "-nographic".to_string(),
             "-no-user-config".to_string(),
             "-nodefaults".to_string(),
-            "-no-reboot".to_string(),
             "-no-shutdown".to_string(),
-            "-mem-prealloc".to_string(),
-            "-machine".to_string(),
-            "none".to_string(),
-            "-qmp".to_string(),
-            qmp_unix,
+            "-chardev".to_string(),
+            format!("socket,id=qmp,fd={},server=on,wait=off", qmp_fd),
+            "-mon".to_string(),
+            "chardev=qmp,mode=control".to_string(),
             "-name".to_string(),
             self.id.clone(),
-            //"-machine", "pc-q35-8.2",
-            //"-smp",     "1,sockets=1,cores=1,threads=1",
-            //"-m",       "1G",
         ];
+        // Machine, CPU and memory size come from the spec, as do the guest
+        // devices, which QEMU only creates once it leaves preconfig; their
+        // backends are added over QMP while QEMU is waiting in preconfig
+        args.extend(self.spec.args());
+        args.extend(devices::args(&self.spec));
 
         let (inside_write_half, outside_read_half) = pipe::pipe()?;
         let (outside_write_half, inside_read_half) = pipe::pipe()?;
@@ -257,24 +1242,123 @@ impl VM {
             inside_write_half,
             cmd,
             args,
+            inherit_fds,
         ));
         let (child_res, nshelper_res) = tokio::join!(child_handle, nshelper_handle);
         let child = child_res??;
         nshelper_res.unwrap();
+        // QEMU holds its own copy of the listening socket and taps now, under
+        // the same fd numbers
+        drop(qmp_listener);
+        let tap_fds = taps
+            .into_iter()
+            .map(|(id, fd)| (id, fd.as_raw_fd().to_string()))
+            .collect();
 
         if let Some(tree) = self.build_pid_tree().await? {
             if Pid::from_raw(child.id().unwrap() as i32) != tree.root_pid() {
                 return Err(anyhow! {"child_pid does not match cgroup list"});
             }
         }
-        Ok(())
+        Ok(tap_fds)
     }
 
     pub async fn qmp(&mut self, cmd: QMPCommand) -> Result<String> {
-        if let Some(ref mut manager) = self.manager {
-            Ok(manager.execute(cmd).await?)
-        } else {
-            Err(anyhow! {"QMP manager not set up"})
+        self.manager()?.execute(cmd).await
+    }
+
+    /// The QMP connection to QEMU, once attached
+    fn manager(&mut self) -> Result<&mut QMP> {
+        self.manager
+            .as_mut()
+            .ok_or_else(|| anyhow! {"QMP manager not set up"})
+    }
+
+    /// Send `cmd` and wait up to `CONFIRM_TIMEOUT` for the `event` QEMU sends
+    /// once the command took effect
+    async fn qmp_confirmed(&mut self, cmd: QMPCommand, event: &str) -> Result<Event> {
+        // Subscribed before sending so the event can not be missed
+        let mut events = self.manager()?.subscribe();
+        self.qmp_checked(cmd).await?;
+        match wait_for_event(&mut events, event, CONFIRM_TIMEOUT).await {
+            Some(event) => {
+                // The follower task sees the same event, but maybe only after
+                // we return; the state must already be right by then
+                self.lifecycle.on_event(&event);
+                Ok(event)
+            }
+            None => Err(anyhow! {"no {} event within {:?}", event, CONFIRM_TIMEOUT}),
         }
     }
+
+    /// Like `qmp`, but a reply holding an `error` is turned into an `Err` and
+    /// otherwise the `return` value is parsed out
+    async fn qmp_checked(&mut self, cmd: QMPCommand) -> Result<serde_json::Value> {
+        let description = format!("{:?}", cmd);
+        let reply = self.qmp(cmd).await?;
+        checked_reply(&description, &reply)
+    }
+
+    /// Like `qmp_checked`, passing `fd` to QEMU along with `cmd`
+    async fn qmp_checked_with_fd(
+        &mut self,
+        cmd: QMPCommand,
+        fd: OwnedFd,
+    ) -> Result<serde_json::Value> {
+        let description = format!("{:?}", cmd);
+        let reply = self.manager()?.execute_with_fd(cmd, fd).await?;
+        checked_reply(&description, &reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, data: Option<serde_json::Value>) -> Event {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": { "seconds": 0, "microseconds": 0 },
+            "event": name,
+            "data": data,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_shutdown() {
+        let (tx, mut rx) = broadcast::channel(8);
+        tx.send(event("POWERDOWN", None)).unwrap();
+        tx.send(event(
+            "SHUTDOWN",
+            Some(serde_json::json!({ "guest": true, "reason": "guest-shutdown" })),
+        ))
+        .unwrap();
+        let reason = wait_for_shutdown(&mut rx, Duration::from_secs(1)).await;
+        assert_eq!(reason.as_deref(), Some("guest-shutdown"));
+
+        tx.send(event("RESUME", None)).unwrap();
+        assert_eq!(wait_for_shutdown(&mut rx, Duration::ZERO).await, None);
+    }
+
+    #[test]
+    fn test_device_deleted() {
+        let deleted = |device: &str| {
+            event(
+                "DEVICE_DELETED",
+                Some(serde_json::json!({
+                    "device": device,
+                    "path": format!("/machine/peripheral/{}", device),
+                })),
+            )
+        };
+        assert!(device_deleted(&deleted("data"), "data"));
+        assert!(!device_deleted(&deleted("root"), "data"));
+        // The backend of a virtio device goes first, without an id
+        let backend = event(
+            "DEVICE_DELETED",
+            Some(serde_json::json!({ "path": "/machine/peripheral/data/virtio-backend" })),
+        );
+        assert!(!device_deleted(&backend, "data"));
+        assert!(!device_deleted(&event("RESET", None), "data"));
+    }
 }
@@ -0,0 +1,28 @@
+use manager::backup::{BackupKind, BackupTarget};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::{Datetime, Thing};
+
+/// A backup of a disk of a VM. Incremental backups are related to the backup
+/// they build on with `based_on`, down to a full backup.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Backup {
+    pub id: Thing,
+    pub created_at: Datetime,
+    pub disk: String,
+    pub kind: BackupKind,
+    pub target: BackupTarget,
+    /// The point in time the backup holds the disk at
+    pub started_at: Datetime,
+}
+
+/// The backup as the manager knows it, to build the next one on
+impl From<Backup> for manager::backup::Backup {
+    fn from(backup: Backup) -> Self {
+        Self {
+            disk: backup.disk,
+            kind: backup.kind,
+            target: backup.target,
+            started_at: backup.started_at.0,
+        }
+    }
+}
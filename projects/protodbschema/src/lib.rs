@@ -6,3 +6,6 @@ pub use hypervisor::{Hostname, Hypervisor};
 
 mod event;
 pub use event::Event;
+
+mod backup;
+pub use backup::Backup;
@@ -1,3 +1,5 @@
+use manager::lifecycle::VmState;
+use manager::spec::VmSpec;
 use serde::{Deserialize, Serialize};
 use surrealdb::sql::{Datetime, Thing};
 
@@ -6,4 +8,10 @@ pub struct VM {
     pub id: Thing,
     pub created_at: Datetime,
     pub name: String,
+    /// Records written before specs existed get the default spec
+    #[serde(default)]
+    pub spec: VmSpec,
+    /// The last lifecycle state the hypervisor running the VM reported
+    #[serde(default)]
+    pub state: Option<VmState>,
 }
@@ -1,14 +1,18 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 //use chrono::DateTime;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "event")]
 pub struct Event {
     pub timestamp: Timestamp,
     pub event: String,
+    /// The event specific payload, for the events that have one
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Timestamp {
     pub seconds: i64,
     pub microseconds: u32,
@@ -36,6 +40,22 @@ pub struct QOMListTypesArgs {
     pub r#abstract: Option<bool>,
 }
 
+/// The arguments of a command, as the JSON object sent in `arguments`
+pub type Arguments = serde_json::Map<String, serde_json::Value>;
+
+/// A QMP command, sent as `{ "execute": NAME, "arguments": ... }` with the
+/// command as its arguments. The commands generated from the QAPI schema
+/// implement it, and `QMP::execute_command` executes any of them.
+pub trait Command: Serialize {
+    const NAME: &'static str;
+    /// Whether QEMU replies when the command succeeds. Without a success
+    /// response only failures are answered, so nothing should wait for a
+    /// reply.
+    const SUCCESS_RESPONSE: bool = true;
+    /// What QEMU replies with when the command succeeds
+    type Returns: DeserializeOwned;
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "execute")]
 pub enum QMPCommand {
@@ -45,18 +65,61 @@ pub enum QMPCommand {
     QueryCpusFast,
     #[serde(rename = "system_reset")]
     SystemReset,
+    #[serde(rename = "system_wakeup")]
+    SystemWakeup,
+    #[serde(rename = "inject-nmi")]
+    InjectNmi,
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "cont")]
+    Cont,
     #[serde(rename = "system_powerdown")]
     SystemPowerdown,
+    #[serde(rename = "quit")]
+    Quit,
     #[serde(rename = "query-machines")]
     QueryMachines,
     #[serde(rename = "query-status")]
     QueryStatus,
     #[serde(rename = "query-current-machine")]
     QueryCurrentMachine,
+    #[serde(rename = "query-target")]
+    QueryTarget,
+    #[serde(rename = "query-version")]
+    QueryVersion,
     #[serde(rename = "x-exit-preconfig")]
     XExitPreconfig,
     #[serde(rename = "qom-list")]
     QOMList { arguments: QOMListArgs },
     #[serde(rename = "qom-list-types")]
     QOMListTypes { arguments: QOMListTypesArgs },
+    #[serde(rename = "query-jobs")]
+    QueryJobs,
+    #[serde(rename = "nbd-server-stop")]
+    NbdServerStop,
+    /// Any `Command`, under its `NAME`; see `QMPCommand::new`
+    #[serde(untagged)]
+    Typed {
+        execute: String,
+        arguments: Arguments,
+    },
+}
+
+impl QMPCommand {
+    /// A `Command`, to be sent along with commands of other types
+    pub fn new<C: Command>(command: &C) -> Self {
+        match serde_json::to_value(command).expect("commands always serialize") {
+            serde_json::Value::Object(arguments) => Self::Typed {
+                execute: C::NAME.to_string(),
+                arguments,
+            },
+            _ => unreachable!("QMP arguments are always an object"),
+        }
+    }
+
+    /// The name QEMU knows the command by, as sent in `execute`
+    pub fn name(&self) -> String {
+        let command = serde_json::to_value(self).expect("commands always serialize");
+        command["execute"].as_str().unwrap_or_default().to_string()
+    }
 }
@@ -4,6 +4,10 @@ use serde::Serialize;
 pub trait Command: Serialize {
     fn name() -> &'static str;
 
+    /// False for commands declared with `'success-response': false`, which
+    /// QEMU only answers when they fail
+    fn success_response() -> bool { true }
+
     fn skip_serializing_arguments(&self) -> bool;
 
     fn execute<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
@@ -0,0 +1,1838 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+/// A QMP command, sent as `{ "execute": NAME, "arguments": ... }`
+/// with the command as its arguments
+pub trait Command: Serialize {
+    const NAME: &'static str;
+    /// Whether QEMU replies when the command succeeds. Without a
+    /// success response only failures are answered, so nothing
+    /// should wait for a reply.
+    const SUCCESS_RESPONSE: bool = true;
+    /// What QEMU replies with when the command succeeds
+    type Returns: DeserializeOwned;
+}
+/// A QMP event, received as `{ "event": NAME, "data": ... }`
+pub trait Event: DeserializeOwned {
+    const NAME: &'static str;
+}
+/// The QAPI builtin `null` type, which only ever holds JSON `null`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Null;
+/// The empty object QEMU replies with when a command returns nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Empty {}
+// path begin:	qapi/pragma.json
+// path end:	qapi/pragma.json
+// path begin:	qapi/error.json
+/// QEMU error classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QapiErrorClass {
+    /// this is used for errors that don't require a specific
+    /// error class.  This should be the default case for most errors
+    GenericError,
+    /// the requested command has not been found
+    CommandNotFound,
+    /// a device has failed to be become active
+    DeviceNotActive,
+    /// the requested device has not been found
+    DeviceNotFound,
+    /// the requested operation can't be fulfilled because a
+    /// required KVM capability is missing
+    #[serde(rename = "KVMMissingCap")]
+    KvmMissingCap,
+}
+// path end:	qapi/error.json
+// path begin:	qapi/common.json
+/// An enumeration of the I/O operation types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IoOperationType {
+    /// read operation
+    #[serde(rename = "read")]
+    Read,
+    /// write operation
+    #[serde(rename = "write")]
+    Write,
+}
+/// An enumeration of three options: on, off, and auto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OnOffAuto {
+    /// QEMU selects the value between on and off
+    #[serde(rename = "auto")]
+    Auto,
+    /// Enabled
+    #[serde(rename = "on")]
+    On,
+    /// Disabled
+    #[serde(rename = "off")]
+    Off,
+}
+/// This is a string value or the explicit lack of a string (null
+/// pointer in C).  Intended for cases when 'optional absent' already
+/// has a different meaning.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StrOrNull {
+    /// the string value
+    S(String),
+    /// no string value
+    N(Null),
+}
+/// An enumeration of options for specifying a PCI BAR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OffAutoPcibar {
+    /// The specified feature is disabled
+    #[serde(rename = "off")]
+    Off,
+    /// The PCI BAR for the feature is automatically selected
+    #[serde(rename = "auto")]
+    Auto,
+    /// PCI BAR0 is used for the feature
+    #[serde(rename = "bar0")]
+    Bar0,
+    /// PCI BAR1 is used for the feature
+    #[serde(rename = "bar1")]
+    Bar1,
+}
+// path end:	qapi/common.json
+// path begin:	qapi/sockets.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InetSocketAddressBase {
+    /// host part of the address
+    pub host: String,
+    /// port part of the address
+    pub port: String,
+}
+impl InetSocketAddressBase {
+    pub fn new(host: String, port: String) -> Self {
+        Self { host, port }
+    }
+}
+/// Captures a socket address or address range in the Internet
+/// namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InetSocketAddress {
+    /// host part of the address
+    pub host: String,
+    /// port part of the address
+    pub port: String,
+    /// true if the host/port are guaranteed to be numeric, false
+    /// if name resolution should be attempted.  Defaults to false.
+    /// (Since 2.9)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<bool>,
+    /// If present, this is range of possible addresses, with port
+    /// between @port and @to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<u16>,
+    /// whether to accept IPv4 addresses, default try both IPv4 and
+    /// IPv6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<bool>,
+    /// whether to accept IPv6 addresses, default try both IPv4 and
+    /// IPv6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<bool>,
+    /// enable keep-alive when connecting to this socket.  Not
+    /// supported for passive sockets.  (Since 4.2)
+    #[serde(rename = "keep-alive")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<bool>,
+}
+impl InetSocketAddress {
+    pub fn new(host: String, port: String) -> Self {
+        Self {
+            host,
+            port,
+            numeric: None,
+            to: None,
+            ipv4: None,
+            ipv6: None,
+            keep_alive: None,
+        }
+    }
+    /// true if the host/port are guaranteed to be numeric, false
+    /// if name resolution should be attempted.  Defaults to false.
+    /// (Since 2.9)
+    pub fn numeric(mut self, value: bool) -> Self {
+        self.numeric = Some(value);
+        self
+    }
+    /// If present, this is range of possible addresses, with port
+    /// between @port and @to.
+    pub fn to(mut self, value: u16) -> Self {
+        self.to = Some(value);
+        self
+    }
+    /// whether to accept IPv4 addresses, default try both IPv4 and
+    /// IPv6
+    pub fn ipv4(mut self, value: bool) -> Self {
+        self.ipv4 = Some(value);
+        self
+    }
+    /// whether to accept IPv6 addresses, default try both IPv4 and
+    /// IPv6
+    pub fn ipv6(mut self, value: bool) -> Self {
+        self.ipv6 = Some(value);
+        self
+    }
+    /// enable keep-alive when connecting to this socket.  Not
+    /// supported for passive sockets.  (Since 4.2)
+    pub fn keep_alive(mut self, value: bool) -> Self {
+        self.keep_alive = Some(value);
+        self
+    }
+}
+/// Captures a socket address in the local ("Unix socket") namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnixSocketAddress {
+    /// filesystem path to use
+    pub path: String,
+    /// if true, this is a Linux abstract socket address.  @path
+    /// will be prefixed by a null byte, and optionally padded with null
+    /// bytes.  Defaults to false.  (Since 5.1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#abstract: Option<bool>,
+}
+impl UnixSocketAddress {
+    pub fn new(path: String) -> Self {
+        Self { path, r#abstract: None }
+    }
+    /// if true, this is a Linux abstract socket address.  @path
+    /// will be prefixed by a null byte, and optionally padded with null
+    /// bytes.  Defaults to false.  (Since 5.1)
+    pub fn r#abstract(mut self, value: bool) -> Self {
+        self.r#abstract = Some(value);
+        self
+    }
+}
+/// A fat type wrapping 'str', to be embedded in lists.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QapiString {
+    /// the string
+    pub str: String,
+}
+impl QapiString {
+    pub fn new(str: String) -> Self {
+        Self { str }
+    }
+}
+/// Available SocketAddress types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SocketAddressType {
+    /// Internet address
+    #[serde(rename = "inet")]
+    Inet,
+    /// Unix domain socket
+    #[serde(rename = "unix")]
+    Unix,
+    /// Decimal file descriptor number.
+    #[serde(rename = "fd")]
+    Fd,
+}
+/// Transport type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SocketAddressBranch {
+    /// Internet address
+    #[serde(rename = "inet")]
+    Inet(InetSocketAddress),
+    /// Unix domain socket
+    #[serde(rename = "unix")]
+    Unix(UnixSocketAddress),
+    /// Decimal file descriptor number.
+    #[serde(rename = "fd")]
+    Fd(QapiString),
+}
+/// Captures the address of a socket, which could also be a socket file
+/// descriptor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SocketAddress {
+    #[serde(flatten)]
+    pub u: SocketAddressBranch,
+}
+impl SocketAddress {
+    pub fn new(u: SocketAddressBranch) -> Self {
+        Self { u }
+    }
+}
+// path end:	qapi/sockets.json
+// path begin:	qapi/run-state.json
+/// An enumeration of VM run states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RunState {
+    /// QEMU is running on a debugger
+    #[serde(rename = "debug")]
+    Debug,
+    /// guest is paused waiting for an incoming migration.  Note
+    /// that this state does not tell whether the machine will start at
+    /// the end of the migration.  This depends on the command-line -S
+    /// option and any invocation of 'stop' or 'cont' that has happened
+    /// since QEMU was started.
+    #[serde(rename = "inmigrate")]
+    Inmigrate,
+    /// guest is paused to finish the migration process
+    #[serde(rename = "finish-migrate")]
+    FinishMigrate,
+    /// guest has been paused via the 'stop' command
+    #[serde(rename = "paused")]
+    Paused,
+    /// QEMU was started with -S and guest has not started
+    #[serde(rename = "prelaunch")]
+    Prelaunch,
+    /// guest is actively running
+    #[serde(rename = "running")]
+    Running,
+    /// guest is shut down (and -no-shutdown is in use)
+    #[serde(rename = "shutdown")]
+    Shutdown,
+    /// guest is suspended (ACPI S3)
+    #[serde(rename = "suspended")]
+    Suspended,
+    /// the watchdog action is configured to pause and has been
+    /// triggered
+    #[serde(rename = "watchdog")]
+    Watchdog,
+}
+/// An enumeration of reasons for a Shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShutdownCause {
+    /// No shutdown request pending
+    #[serde(rename = "none")]
+    None,
+    /// An error prevents further use of guest
+    #[serde(rename = "host-error")]
+    HostError,
+    /// Reaction to the QMP command 'quit'
+    #[serde(rename = "host-qmp-quit")]
+    HostQmpQuit,
+    /// Reaction to a signal, such as SIGINT
+    #[serde(rename = "host-signal")]
+    HostSignal,
+    /// Guest shutdown/suspend request, via ACPI or other
+    /// hardware-specific means
+    #[serde(rename = "guest-shutdown")]
+    GuestShutdown,
+    /// Guest reset request, and command line turns that into
+    /// a shutdown
+    #[serde(rename = "guest-reset")]
+    GuestReset,
+}
+/// Information about VM run state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusInfo {
+    /// true if all VCPUs are runnable, false if not runnable
+    pub running: bool,
+    /// the virtual machine @RunState
+    pub status: RunState,
+}
+impl StatusInfo {
+    pub fn new(running: bool, status: RunState) -> Self {
+        Self { running, status }
+    }
+}
+/// Query the run status of the VM
+///
+/// # Returns
+///
+/// @StatusInfo reflecting the VM
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "query-status" }"#).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": { "running": true,
+/// "status": "running" } }"#).unwrap();
+/// let _: StatusInfo = serde_json::from_value(message["return"].clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryStatus {}
+impl QueryStatus {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for QueryStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for QueryStatus {
+    const NAME: &'static str = "query-status";
+    type Returns = StatusInfo;
+}
+/// Emitted when the virtual machine has shut down, indicating that qemu
+/// is about to exit.
+///
+/// # Note
+///
+/// If the command-line option "-no-shutdown" has been
+/// specified, qemu will not exit, and a STOP event will eventually
+/// follow the SHUTDOWN event.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "event": "SHUTDOWN",
+/// "data": { "guest": true, "reason": "guest-shutdown" },
+/// "timestamp": { "seconds": 1267040730, "microseconds": 682951 } }"#).unwrap();
+/// let _: ShutdownEvent = serde_json::from_value(message["data"].clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownEvent {
+    /// If true, the shutdown was triggered by a guest request (such
+    /// as a guest-initiated ACPI shutdown request or other
+    /// hardware-specific action) rather than a host request (such as
+    /// sending qemu a SIGINT).  (since 2.10)
+    pub guest: bool,
+    /// The @ShutdownCause which resulted in the SHUTDOWN.
+    /// (since 4.0)
+    pub reason: ShutdownCause,
+}
+impl ShutdownEvent {
+    pub fn new(guest: bool, reason: ShutdownCause) -> Self {
+        Self { guest, reason }
+    }
+}
+impl Event for ShutdownEvent {
+    const NAME: &'static str = "SHUTDOWN";
+}
+/// Emitted when the virtual machine is stopped
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "STOP",
+/// "timestamp": { "seconds": 1267041730, "microseconds": 281295 } }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StopEvent {}
+impl StopEvent {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for StopEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Event for StopEvent {
+    const NAME: &'static str = "STOP";
+}
+/// Emitted when the virtual machine resumes execution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResumeEvent {}
+impl ResumeEvent {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for ResumeEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Event for ResumeEvent {
+    const NAME: &'static str = "RESUME";
+}
+/// Stop guest VM execution.
+///
+/// # Note
+///
+/// This function will succeed even if the guest is already in
+/// the stopped state. In "inmigrate" state, it will ensure that the
+/// guest remains paused once migration finishes, as if the -S option
+/// was passed on the command line.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "stop" }
+/// <- { "return": {} }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stop {}
+impl Stop {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for Stop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for Stop {
+    const NAME: &'static str = "stop";
+    type Returns = Empty;
+}
+/// Resume guest VM execution.
+///
+/// # Returns
+///
+/// If successful, nothing
+///
+/// # Note
+///
+/// This command will succeed if the guest is currently
+/// running. It will also succeed if the guest is in the "inmigrate"
+/// state; in this case, the effect of the command is to make sure the
+/// guest starts once migration finishes, removing the effect of the
+/// -S command line option if it was passed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cont {}
+impl Cont {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for Cont {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for Cont {
+    const NAME: &'static str = "cont";
+    type Returns = Empty;
+}
+/// An enumeration of the actions taken when the watchdog device's timer
+/// is expired
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WatchdogAction {
+    /// system resets
+    #[serde(rename = "reset")]
+    Reset,
+    /// system shutdown, note that it is similar to @powerdown,
+    /// which tries to set to system status and notify guest
+    #[serde(rename = "shutdown")]
+    Shutdown,
+    /// system pauses
+    #[serde(rename = "pause")]
+    Pause,
+    /// a non-maskable interrupt is injected into the first
+    /// VCPU (all VCPUS on x86) (since 2.4)
+    #[serde(rename = "inject-nmi")]
+    InjectNmi,
+}
+// path end:	qapi/run-state.json
+// path begin:	qapi/block-core.json
+/// Drivers that are supported in block device operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BlockdevDriver {
+    /// Since 2.0
+    #[serde(rename = "file")]
+    File,
+    /// Since 5.0
+    #[serde(rename = "io_uring")]
+    IoUring,
+    /// Since 2.9
+    #[serde(rename = "nbd")]
+    Nbd,
+    /// Since 2.9
+    #[serde(rename = "null-co")]
+    NullCo,
+    /// Since 2.9
+    #[serde(rename = "qcow2")]
+    Qcow2,
+    /// Since 2.9
+    #[serde(rename = "raw")]
+    Raw,
+}
+/// Determines how to handle discard requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BlockdevDiscardOptions {
+    /// Ignore the request
+    #[serde(rename = "ignore")]
+    Ignore,
+    /// Forward as an unmap request
+    #[serde(rename = "unmap")]
+    Unmap,
+}
+/// Includes cache-related options for block devices
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevCacheOptions {
+    /// enables use of O_DIRECT (bypass the host page cache;
+    /// default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct: Option<bool>,
+    /// ignore any flush requests for the device (default: false)
+    #[serde(rename = "no-flush")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_flush: Option<bool>,
+}
+impl BlockdevCacheOptions {
+    pub fn new() -> Self {
+        Self {
+            direct: None,
+            no_flush: None,
+        }
+    }
+    /// enables use of O_DIRECT (bypass the host page cache;
+    /// default: false)
+    pub fn direct(mut self, value: bool) -> Self {
+        self.direct = Some(value);
+        self
+    }
+    /// ignore any flush requests for the device (default: false)
+    pub fn no_flush(mut self, value: bool) -> Self {
+        self.no_flush = Some(value);
+        self
+    }
+}
+impl Default for BlockdevCacheOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// Driver specific block device options for the file backend.
+///
+/// # Features
+///
+/// - `dynamic-auto-read-only`: If present, enabled auto-read-only means
+///   that the driver will open the image read-only at first,
+///   dynamically reopen the image file read-write when the first
+///   writer is attached to the node and reopen read-only when the
+///   last writer is detached.  This allows giving QEMU write
+///   permissions only on demand when an operation actually needs
+///   write access.
+/// - `unstable`: Member x-check-cache-dropped is meant for debugging.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptionsFile {
+    /// path to the image file
+    pub filename: String,
+    /// the id for the object that will handle persistent
+    /// reservations for this device (default: none, forward the
+    /// commands via SG_IO; since 2.11)
+    #[serde(rename = "pr-manager")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pr_manager: Option<String>,
+    /// maximum number of requests to batch together into a
+    /// single submission in the AIO backend.  The smallest value
+    /// between this and the aio-max-batch value of the IOThread object
+    /// is chosen.  0 means that the AIO backend will handle it
+    /// automatically.  (default: 0, since 6.2)
+    #[serde(rename = "aio-max-batch")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aio_max_batch: Option<i64>,
+    /// invalidate page cache during live migration.  This
+    /// prevents stale data on the migration destination with
+    /// cache.direct=off.  Currently only supported on Linux hosts.
+    /// (default: on, since: 4.0)
+    #[serde(rename = "drop-cache")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_cache: Option<bool>,
+    /// whether to check that page cache was dropped
+    /// on live migration.  May cause noticeable delays if the image
+    /// file is large, do not use in production.  (default: off)
+    /// (since: 3.0)
+    #[serde(rename = "x-check-cache-dropped")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_check_cache_dropped: Option<bool>,
+}
+impl BlockdevOptionsFile {
+    pub fn new(filename: String) -> Self {
+        Self {
+            filename,
+            pr_manager: None,
+            aio_max_batch: None,
+            drop_cache: None,
+            x_check_cache_dropped: None,
+        }
+    }
+    /// the id for the object that will handle persistent
+    /// reservations for this device (default: none, forward the
+    /// commands via SG_IO; since 2.11)
+    pub fn pr_manager(mut self, value: String) -> Self {
+        self.pr_manager = Some(value);
+        self
+    }
+    /// maximum number of requests to batch together into a
+    /// single submission in the AIO backend.  The smallest value
+    /// between this and the aio-max-batch value of the IOThread object
+    /// is chosen.  0 means that the AIO backend will handle it
+    /// automatically.  (default: 0, since 6.2)
+    pub fn aio_max_batch(mut self, value: i64) -> Self {
+        self.aio_max_batch = Some(value);
+        self
+    }
+    /// invalidate page cache during live migration.  This
+    /// prevents stale data on the migration destination with
+    /// cache.direct=off.  Currently only supported on Linux hosts.
+    /// (default: on, since: 4.0)
+    pub fn drop_cache(mut self, value: bool) -> Self {
+        self.drop_cache = Some(value);
+        self
+    }
+    /// whether to check that page cache was dropped
+    /// on live migration.  May cause noticeable delays if the image
+    /// file is large, do not use in production.  (default: off)
+    /// (since: 3.0)
+    pub fn x_check_cache_dropped(mut self, value: bool) -> Self {
+        self.x_check_cache_dropped = Some(value);
+        self
+    }
+}
+/// Driver specific block device options for the null backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptionsNull {
+    /// size of the device in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    /// emulated latency (in nanoseconds) in processing
+    /// requests.  Default to zero which completes requests immediately.
+    /// (Since 2.4)
+    #[serde(rename = "latency-ns")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ns: Option<u64>,
+    /// if true, reads from the device produce zeroes; if
+    /// false, the buffer is left unchanged.
+    /// (default: false; since: 4.1)
+    #[serde(rename = "read-zeroes")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_zeroes: Option<bool>,
+}
+impl BlockdevOptionsNull {
+    pub fn new() -> Self {
+        Self {
+            size: None,
+            latency_ns: None,
+            read_zeroes: None,
+        }
+    }
+    /// size of the device in bytes.
+    pub fn size(mut self, value: i64) -> Self {
+        self.size = Some(value);
+        self
+    }
+    /// emulated latency (in nanoseconds) in processing
+    /// requests.  Default to zero which completes requests immediately.
+    /// (Since 2.4)
+    pub fn latency_ns(mut self, value: u64) -> Self {
+        self.latency_ns = Some(value);
+        self
+    }
+    /// if true, reads from the device produce zeroes; if
+    /// false, the buffer is left unchanged.
+    /// (default: false; since: 4.1)
+    pub fn read_zeroes(mut self, value: bool) -> Self {
+        self.read_zeroes = Some(value);
+        self
+    }
+}
+impl Default for BlockdevOptionsNull {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// Driver specific block device options for NBD.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptionsNbd {
+    /// NBD server address
+    pub server: SocketAddress,
+    /// export name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export: Option<String>,
+    /// TLS credentials ID
+    #[serde(rename = "tls-creds")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_creds: Option<String>,
+    /// On an unexpected disconnect, the nbd client tries
+    /// to connect again until succeeding or encountering a serious
+    /// error.  During the first @reconnect-delay seconds, all requests
+    /// are paused and will be rerun on a successful reconnect.  After
+    /// that time, any delayed requests and all future requests before a
+    /// successful reconnect will immediately fail.  Default 0 (Since
+    /// 4.2)
+    #[serde(rename = "reconnect-delay")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconnect_delay: Option<u32>,
+}
+impl BlockdevOptionsNbd {
+    pub fn new(server: SocketAddress) -> Self {
+        Self {
+            server,
+            export: None,
+            tls_creds: None,
+            reconnect_delay: None,
+        }
+    }
+    /// export name
+    pub fn export(mut self, value: String) -> Self {
+        self.export = Some(value);
+        self
+    }
+    /// TLS credentials ID
+    pub fn tls_creds(mut self, value: String) -> Self {
+        self.tls_creds = Some(value);
+        self
+    }
+    /// On an unexpected disconnect, the nbd client tries
+    /// to connect again until succeeding or encountering a serious
+    /// error.  During the first @reconnect-delay seconds, all requests
+    /// are paused and will be rerun on a successful reconnect.  After
+    /// that time, any delayed requests and all future requests before a
+    /// successful reconnect will immediately fail.  Default 0 (Since
+    /// 4.2)
+    pub fn reconnect_delay(mut self, value: u32) -> Self {
+        self.reconnect_delay = Some(value);
+        self
+    }
+}
+/// Driver specific block device options for image format that have no
+/// option besides their data source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptionsGenericFormat {
+    /// reference to or definition of the data source block device
+    pub file: BlockdevRef,
+}
+impl BlockdevOptionsGenericFormat {
+    pub fn new(file: BlockdevRef) -> Self {
+        Self { file }
+    }
+}
+/// Driver specific block device options for qcow2.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptionsQcow2 {
+    /// reference to or definition of the data source block device
+    pub file: BlockdevRef,
+    /// whether to enable the lazy refcounts feature
+    /// (default is taken from the image file)
+    #[serde(rename = "lazy-refcounts")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lazy_refcounts: Option<bool>,
+    /// whether discard requests to the qcow2 device
+    /// should be forwarded to the data source
+    #[serde(rename = "pass-discard-request")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pass_discard_request: Option<bool>,
+    /// reference to or definition of the external data file.
+    /// This may only be specified for images that require an external
+    /// data file.  If it is not specified for such an image, the data
+    /// file name is loaded from the image file.  (since 4.0)
+    #[serde(rename = "data-file")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_file: Option<BlockdevRef>,
+}
+impl BlockdevOptionsQcow2 {
+    pub fn new(file: BlockdevRef) -> Self {
+        Self {
+            file,
+            lazy_refcounts: None,
+            pass_discard_request: None,
+            data_file: None,
+        }
+    }
+    /// whether to enable the lazy refcounts feature
+    /// (default is taken from the image file)
+    pub fn lazy_refcounts(mut self, value: bool) -> Self {
+        self.lazy_refcounts = Some(value);
+        self
+    }
+    /// whether discard requests to the qcow2 device
+    /// should be forwarded to the data source
+    pub fn pass_discard_request(mut self, value: bool) -> Self {
+        self.pass_discard_request = Some(value);
+        self
+    }
+    /// reference to or definition of the external data file.
+    /// This may only be specified for images that require an external
+    /// data file.  If it is not specified for such an image, the data
+    /// file name is loaded from the image file.  (since 4.0)
+    pub fn data_file(mut self, value: BlockdevRef) -> Self {
+        self.data_file = Some(value);
+        self
+    }
+}
+/// block driver name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "driver")]
+pub enum BlockdevOptionsBranch {
+    /// Since 2.0
+    #[serde(rename = "file")]
+    File(BlockdevOptionsFile),
+    /// Since 5.0
+    #[serde(rename = "io_uring")]
+    IoUring(BlockdevOptionsFile),
+    /// Since 2.9
+    #[serde(rename = "nbd")]
+    Nbd(BlockdevOptionsNbd),
+    /// Since 2.9
+    #[serde(rename = "null-co")]
+    NullCo(BlockdevOptionsNull),
+    /// Since 2.9
+    #[serde(rename = "qcow2")]
+    Qcow2(BlockdevOptionsQcow2),
+    /// Since 2.9
+    #[serde(rename = "raw")]
+    Raw(BlockdevOptionsGenericFormat),
+}
+/// Options for creating a block device.  Many options are available for
+/// all block devices, independent of the block driver:
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevOptions {
+    /// the node name of the new node.  This option is required
+    /// on the top level of blockdev-add.  Valid node names start with
+    /// an alphabetic character and may contain only alphanumeric
+    /// characters, '-', '.' and '_'.  Their maximum length is 31
+    /// characters.
+    #[serde(rename = "node-name")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    /// discard-related options (default: ignore)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discard: Option<BlockdevDiscardOptions>,
+    /// cache-related options
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<BlockdevCacheOptions>,
+    /// whether the block device should be read-only (default:
+    /// false).
+    #[serde(rename = "read-only")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(flatten)]
+    pub u: BlockdevOptionsBranch,
+}
+impl BlockdevOptions {
+    pub fn new(u: BlockdevOptionsBranch) -> Self {
+        Self {
+            node_name: None,
+            discard: None,
+            cache: None,
+            read_only: None,
+            u,
+        }
+    }
+    /// the node name of the new node.  This option is required
+    /// on the top level of blockdev-add.  Valid node names start with
+    /// an alphabetic character and may contain only alphanumeric
+    /// characters, '-', '.' and '_'.  Their maximum length is 31
+    /// characters.
+    pub fn node_name(mut self, value: String) -> Self {
+        self.node_name = Some(value);
+        self
+    }
+    /// discard-related options (default: ignore)
+    pub fn discard(mut self, value: BlockdevDiscardOptions) -> Self {
+        self.discard = Some(value);
+        self
+    }
+    /// cache-related options
+    pub fn cache(mut self, value: BlockdevCacheOptions) -> Self {
+        self.cache = Some(value);
+        self
+    }
+    /// whether the block device should be read-only (default:
+    /// false).
+    pub fn read_only(mut self, value: bool) -> Self {
+        self.read_only = Some(value);
+        self
+    }
+}
+/// Reference to a block device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BlockdevRef {
+    /// defines a new block device inline
+    Definition(Box<BlockdevOptions>),
+    /// references the ID of an existing block device
+    Reference(String),
+}
+/// Creates a new block device.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "blockdev-add",
+///   "arguments": {
+///        "driver": "qcow2",
+///        "node-name": "test1",
+///        "file": {
+///            "driver": "file",
+///            "filename": "test.qcow2"
+///         }
+///   }
+/// }"#).unwrap();
+/// let _: BlockdevAdd = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevAdd {
+    #[serde(flatten)]
+    pub data: Box<BlockdevOptions>,
+}
+impl BlockdevAdd {
+    pub fn new(data: BlockdevOptions) -> Self {
+        Self { data: Box::new(data) }
+    }
+}
+impl Command for BlockdevAdd {
+    const NAME: &'static str = "blockdev-add";
+    type Returns = Empty;
+}
+/// Deletes a block device that has been added using blockdev-add.  The
+/// command will fail if the node is attached to a device or is
+/// otherwise being used.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "blockdev-del",
+///   "arguments": { "node-name": "node0" }
+/// }"#).unwrap();
+/// let _: BlockdevDel = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockdevDel {
+    /// Name of the graph node to delete.
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+}
+impl BlockdevDel {
+    pub fn new(node_name: String) -> Self {
+        Self { node_name }
+    }
+}
+impl Command for BlockdevDel {
+    const NAME: &'static str = "blockdev-del";
+    type Returns = Empty;
+}
+/// Resize a block image while a guest is running.
+///
+/// Either @device or @node-name must be set but not both.
+///
+/// # Errors
+///
+/// - If @device is not a valid block device, DeviceNotFound
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "block_resize",
+/// "arguments": { "device": "scratch", "size": 1073741824 } }"#).unwrap();
+/// let _: BlockResize = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockResize {
+    /// the name of the device to get the image resized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// graph node name to get the image resized (Since 2.0)
+    #[serde(rename = "node-name")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    /// new image size in bytes
+    pub size: i64,
+}
+impl BlockResize {
+    pub fn new(size: i64) -> Self {
+        Self {
+            device: None,
+            node_name: None,
+            size,
+        }
+    }
+    /// the name of the device to get the image resized
+    pub fn device(mut self, value: String) -> Self {
+        self.device = Some(value);
+        self
+    }
+    /// graph node name to get the image resized (Since 2.0)
+    pub fn node_name(mut self, value: String) -> Self {
+        self.node_name = Some(value);
+        self
+    }
+}
+impl Command for BlockResize {
+    const NAME: &'static str = "block_resize";
+    type Returns = Empty;
+}
+/// Type of a background job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum JobType {
+    /// block commit job type, see "block-commit"
+    #[serde(rename = "commit")]
+    Commit,
+    /// block stream job type, see "block-stream"
+    #[serde(rename = "stream")]
+    Stream,
+    /// drive mirror job type, see "drive-mirror"
+    #[serde(rename = "mirror")]
+    Mirror,
+    /// drive backup job type, see "drive-backup"
+    #[serde(rename = "backup")]
+    Backup,
+}
+/// Information about a long-running block device operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockJobInfo {
+    /// the job type ('stream' for image streaming)
+    pub r#type: JobType,
+    /// The job identifier.  Originally the device name but other
+    /// values are allowed since QEMU 2.7
+    pub device: String,
+    /// Estimated @offset value at the completion of the job.  This
+    /// value can arbitrarily change while the job is running, in both
+    /// directions.
+    pub len: i64,
+    /// Progress made until now.  The unit is arbitrary and the
+    /// value can only meaningfully be used for the ratio of @offset to
+    /// @len.  The value is monotonically increasing.
+    pub offset: i64,
+    /// false if the job is known to be in a quiescent state, with no
+    /// pending I/O.  (Since 1.3)
+    pub busy: bool,
+    /// whether the job is paused or, if @busy is true, will pause
+    /// itself as soon as possible.  (Since 1.3)
+    pub paused: bool,
+    /// the rate limit, bytes per second
+    pub speed: i64,
+    /// the status of the job (since 1.3)
+    #[serde(rename = "io-status")]
+    pub io_status: String,
+    /// true if the job may be completed (since 2.2)
+    pub ready: bool,
+}
+impl BlockJobInfo {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        r#type: JobType,
+        device: String,
+        len: i64,
+        offset: i64,
+        busy: bool,
+        paused: bool,
+        speed: i64,
+        io_status: String,
+        ready: bool,
+    ) -> Self {
+        Self {
+            r#type,
+            device,
+            len,
+            offset,
+            busy,
+            paused,
+            speed,
+            io_status,
+            ready,
+        }
+    }
+}
+/// Return information about long-running block device operations.
+///
+/// # Returns
+///
+/// a list of @BlockJobInfo for each active block job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryBlockJobs {}
+impl QueryBlockJobs {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for QueryBlockJobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for QueryBlockJobs {
+    const NAME: &'static str = "query-block-jobs";
+    type Returns = Vec<BlockJobInfo>;
+}
+/// Emitted when a block job is ready to complete
+///
+/// # Note
+///
+/// The "ready to complete" status is always reset by a
+/// @BLOCK_JOB_ERROR event.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "event": "BLOCK_JOB_READY",
+/// "data": { "device": "drive0", "type": "mirror", "speed": 0,
+///           "len": 2097152, "offset": 2097152 },
+/// "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }"#).unwrap();
+/// let _: BlockJobReadyEvent = serde_json::from_value(message["data"].clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockJobReadyEvent {
+    /// job type
+    pub r#type: JobType,
+    /// The job identifier.  Originally the device name but other
+    /// values are allowed since QEMU 2.7
+    pub device: String,
+    /// maximum progress value
+    pub len: i64,
+    /// current progress value.  On success this is equal to len.
+    /// On failure this is less than len
+    pub offset: i64,
+    /// rate limit, bytes per second
+    pub speed: i64,
+}
+impl BlockJobReadyEvent {
+    pub fn new(
+        r#type: JobType,
+        device: String,
+        len: i64,
+        offset: i64,
+        speed: i64,
+    ) -> Self {
+        Self {
+            r#type,
+            device,
+            len,
+            offset,
+            speed,
+        }
+    }
+}
+impl Event for BlockJobReadyEvent {
+    const NAME: &'static str = "BLOCK_JOB_READY";
+}
+// path end:	qapi/block-core.json
+// path begin:	qapi/machine.json
+/// The comprehensive enumeration of QEMU system emulation ("softmmu")
+/// targets.  Run "./configure --help" in the project root directory,
+/// and look for the \*-softmmu targets near the "--target-list" option.
+/// The individual target constants are not documented here, for the
+/// time being.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SysEmuTarget {
+    /// since 1.2
+    #[serde(rename = "aarch64")]
+    Aarch64,
+    /// since 2.12
+    #[serde(rename = "riscv64")]
+    Riscv64,
+    /// since 1.2
+    #[serde(rename = "x86_64")]
+    X8664,
+}
+/// Information about a virtual CPU
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuInfoFast {
+    /// index of the virtual CPU
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: i64,
+    /// path to the CPU object in the QOM tree
+    #[serde(rename = "qom-path")]
+    pub qom_path: String,
+    /// ID of the underlying host thread
+    #[serde(rename = "thread-id")]
+    pub thread_id: i64,
+    /// the QEMU system emulation target, which determines which
+    /// additional fields will be listed (since 3.0)
+    pub target: SysEmuTarget,
+}
+impl CpuInfoFast {
+    pub fn new(
+        cpu_index: i64,
+        qom_path: String,
+        thread_id: i64,
+        target: SysEmuTarget,
+    ) -> Self {
+        Self {
+            cpu_index,
+            qom_path,
+            thread_id,
+            target,
+        }
+    }
+}
+/// Returns information about all virtual CPUs.
+///
+/// # Returns
+///
+/// list of @CpuInfoFast
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "query-cpus-fast" }"#).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": [
+///         {
+///             "thread-id": 25627,
+///             "target": "x86_64",
+///             "qom-path": "/machine/unattached/device[0]",
+///             "cpu-index": 0
+///         },
+///         {
+///             "thread-id": 25628,
+///             "target": "x86_64",
+///             "qom-path": "/machine/unattached/device[2]",
+///             "cpu-index": 1
+///         }
+///     ]
+/// }"#).unwrap();
+/// let _: Vec<CpuInfoFast> = serde_json::from_value(message["return"].clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryCpusFast {}
+impl QueryCpusFast {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for QueryCpusFast {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for QueryCpusFast {
+    const NAME: &'static str = "query-cpus-fast";
+    type Returns = Vec<CpuInfoFast>;
+}
+/// Performs a hard reset of a guest.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "system_reset" }
+/// <- { "return": {} }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemReset {}
+impl SystemReset {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for SystemReset {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for SystemReset {
+    const NAME: &'static str = "system_reset";
+    type Returns = Empty;
+}
+/// Requests that a guest perform a powerdown operation.
+///
+/// # Note
+///
+/// A guest may or may not respond to this command. This
+/// command returning does not indicate that a guest has accepted the
+/// request or that it has shut down. Many guests will respond to
+/// this command by prompting the user in some way.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "system_powerdown" }
+/// <- { "return": {} }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemPowerdown {}
+impl SystemPowerdown {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for SystemPowerdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for SystemPowerdown {
+    const NAME: &'static str = "system_powerdown";
+    type Returns = Empty;
+}
+/// Injects a Non-Maskable Interrupt into the default CPU (x86/s390) or
+/// all CPUs (ppc64).  The command fails when the guest doesn't support
+/// injecting.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "inject-nmi" }
+/// <- { "return": {} }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InjectNmi {}
+impl InjectNmi {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for InjectNmi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for InjectNmi {
+    const NAME: &'static str = "inject-nmi";
+    type Returns = Empty;
+}
+// path end:	qapi/machine.json
+// path begin:	qapi/qom.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectPropertyInfo {
+    /// the name of the property
+    pub name: String,
+    /// the type of the property.  This will typically come in one of
+    /// four forms:
+    ///
+    /// 1) A primitive type such as 'u8', 'u16', 'bool', 'str', or
+    /// 'double'.  These types are mapped to the appropriate JSON
+    /// type.
+    ///
+    /// 2) A child type in the form 'child<subtype>' where subtype is a
+    /// qdev device type name.  Child properties create the
+    /// composition tree.
+    pub r#type: String,
+    /// if specified, the description of the property.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// the default value, if any (since 5.0)
+    #[serde(rename = "default-value")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_value: Option<serde_json::Value>,
+}
+impl ObjectPropertyInfo {
+    pub fn new(name: String, r#type: String) -> Self {
+        Self {
+            name,
+            r#type,
+            description: None,
+            default_value: None,
+        }
+    }
+    /// if specified, the description of the property.
+    pub fn description(mut self, value: String) -> Self {
+        self.description = Some(value);
+        self
+    }
+    /// the default value, if any (since 5.0)
+    pub fn default_value(mut self, value: serde_json::Value) -> Self {
+        self.default_value = Some(value);
+        self
+    }
+}
+/// This command will list any properties of a object given a path in
+/// the object model.
+///
+/// # Returns
+///
+/// a list of @ObjectPropertyInfo that describe the properties
+/// of the object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QomList {
+    /// the path within the object model.  See @qom-get for a
+    /// description of this parameter.
+    pub path: String,
+}
+impl QomList {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+impl Command for QomList {
+    const NAME: &'static str = "qom-list";
+    type Returns = Vec<ObjectPropertyInfo>;
+}
+/// This command will get a property from a object model path and return
+/// the value.
+///
+/// # Returns
+///
+/// The property value. The type depends on the property type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QomGet {
+    /// The path within the object model.
+    pub path: String,
+    /// The property name to read
+    pub property: String,
+}
+impl QomGet {
+    pub fn new(path: String, property: String) -> Self {
+        Self { path, property }
+    }
+}
+impl Command for QomGet {
+    const NAME: &'static str = "qom-get";
+    type Returns = serde_json::Value;
+}
+/// Properties for objects of classes derived from memory-backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryBackendProperties {
+    /// if true, mark the memory as mergeable (default depends on
+    /// the machine type)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<bool>,
+    /// if true, preallocate memory (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prealloc: Option<bool>,
+    /// size of the memory region in bytes
+    pub size: u64,
+}
+impl MemoryBackendProperties {
+    pub fn new(size: u64) -> Self {
+        Self {
+            merge: None,
+            prealloc: None,
+            size,
+        }
+    }
+    /// if true, mark the memory as mergeable (default depends on
+    /// the machine type)
+    pub fn merge(mut self, value: bool) -> Self {
+        self.merge = Some(value);
+        self
+    }
+    /// if true, preallocate memory (default: false)
+    pub fn prealloc(mut self, value: bool) -> Self {
+        self.prealloc = Some(value);
+        self
+    }
+}
+/// Properties for memory-backend-file objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryBackendFileProperties {
+    /// if true, mark the memory as mergeable (default depends on
+    /// the machine type)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<bool>,
+    /// if true, preallocate memory (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prealloc: Option<bool>,
+    /// size of the memory region in bytes
+    pub size: u64,
+    /// the path to either a shared memory or huge page
+    /// filesystem mount
+    #[serde(rename = "mem-path")]
+    pub mem_path: String,
+    /// if true, the backing file is opened read-only (default:
+    /// false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readonly: Option<bool>,
+}
+impl MemoryBackendFileProperties {
+    pub fn new(size: u64, mem_path: String) -> Self {
+        Self {
+            merge: None,
+            prealloc: None,
+            size,
+            mem_path,
+            readonly: None,
+        }
+    }
+    /// if true, mark the memory as mergeable (default depends on
+    /// the machine type)
+    pub fn merge(mut self, value: bool) -> Self {
+        self.merge = Some(value);
+        self
+    }
+    /// if true, preallocate memory (default: false)
+    pub fn prealloc(mut self, value: bool) -> Self {
+        self.prealloc = Some(value);
+        self
+    }
+    /// if true, the backing file is opened read-only (default:
+    /// false)
+    pub fn readonly(mut self, value: bool) -> Self {
+        self.readonly = Some(value);
+        self
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ObjectType {
+    /// memory backed by a file
+    #[serde(rename = "memory-backend-file")]
+    MemoryBackendFile,
+    /// memory backed by an anonymous memfd
+    #[serde(rename = "memory-backend-memfd")]
+    MemoryBackendMemfd,
+    /// memory backed by anonymous RAM
+    #[serde(rename = "memory-backend-ram")]
+    MemoryBackendRam,
+}
+/// the class name for the object to be created
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "qom-type")]
+pub enum ObjectOptionsBranch {
+    /// memory backed by a file
+    #[serde(rename = "memory-backend-file")]
+    MemoryBackendFile(MemoryBackendFileProperties),
+    /// memory backed by an anonymous memfd
+    #[serde(rename = "memory-backend-memfd")]
+    MemoryBackendMemfd(MemoryBackendProperties),
+    /// memory backed by anonymous RAM
+    #[serde(rename = "memory-backend-ram")]
+    MemoryBackendRam(MemoryBackendProperties),
+}
+/// Describes the options of a user creatable QOM object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectOptions {
+    /// the name of the new object
+    pub id: String,
+    #[serde(flatten)]
+    pub u: ObjectOptionsBranch,
+}
+impl ObjectOptions {
+    pub fn new(id: String, u: ObjectOptionsBranch) -> Self {
+        Self { id, u }
+    }
+}
+/// Create a QOM object.
+///
+/// # Errors
+///
+/// - Error if @qom-type is not a valid class name
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "object-add",
+/// "arguments": { "qom-type": "memory-backend-ram", "id": "ram0",
+///                "size": 1073741824 } }"#).unwrap();
+/// let _: ObjectAdd = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectAdd {
+    #[serde(flatten)]
+    pub data: Box<ObjectOptions>,
+}
+impl ObjectAdd {
+    pub fn new(data: ObjectOptions) -> Self {
+        Self { data: Box::new(data) }
+    }
+}
+impl Command for ObjectAdd {
+    const NAME: &'static str = "object-add";
+    type Returns = Empty;
+}
+/// Remove a QOM object.
+///
+/// # Errors
+///
+/// - Error if @id is not a valid id for a QOM object
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "object-del", "arguments": { "id": "rng1" } }"#).unwrap();
+/// let _: ObjectDel = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectDel {
+    /// the name of the QOM object to remove
+    pub id: String,
+}
+impl ObjectDel {
+    pub fn new(id: String) -> Self {
+        Self { id }
+    }
+}
+impl Command for ObjectDel {
+    const NAME: &'static str = "object-del";
+    type Returns = Empty;
+}
+// path end:	qapi/qom.json
+// path begin:	qapi/control.json
+/// Enable QMP capabilities.
+///
+/// # Note
+///
+/// This command is valid exactly when first connecting: it
+/// must be issued before any other command will be accepted, and
+/// will fail once the monitor is accepting other commands. (see
+/// qemu docs/interop/qmp-spec.rst)
+///
+/// # Note
+///
+/// The QMP client needs to explicitly enable QMP
+/// capabilities, otherwise all the QMP capabilities will be turned
+/// off by default.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "qmp_capabilities",
+/// "arguments": { "enable": [ "oob" ] } }"#).unwrap();
+/// let _: QmpCapabilities = serde_json::from_value(message["arguments"].clone()).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "return": {} }"#).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QmpCapabilities {
+    /// An optional list of QMPCapability values to enable.  The
+    /// client must not enable any capability that is not mentioned in
+    /// the QMP greeting message.  If the field is not provided, it
+    /// means no QMP capabilities will be enabled.  (since 2.12)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable: Option<Vec<QmpCapability>>,
+}
+impl QmpCapabilities {
+    pub fn new() -> Self {
+        Self { enable: None }
+    }
+    /// An optional list of QMPCapability values to enable.  The
+    /// client must not enable any capability that is not mentioned in
+    /// the QMP greeting message.  If the field is not provided, it
+    /// means no QMP capabilities will be enabled.  (since 2.12)
+    pub fn enable(mut self, value: Vec<QmpCapability>) -> Self {
+        self.enable = Some(value);
+        self
+    }
+}
+impl Default for QmpCapabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for QmpCapabilities {
+    const NAME: &'static str = "qmp_capabilities";
+    type Returns = Empty;
+}
+/// Enumeration of capabilities to be advertised during initial client
+/// connection, used for agreeing on particular QMP extension
+/// behaviors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QmpCapability {
+    /// QMP ability to support out-of-band requests.  (Please refer to
+    /// qmp-spec.rst for more information on OOB)
+    #[serde(rename = "oob")]
+    Oob,
+}
+/// A three-part version number.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionTriple {
+    /// The major version number.
+    pub major: i64,
+    /// The minor version number.
+    pub minor: i64,
+    /// The micro version number.
+    pub micro: i64,
+}
+impl VersionTriple {
+    pub fn new(major: i64, minor: i64, micro: i64) -> Self {
+        Self { major, minor, micro }
+    }
+}
+/// A description of QEMU's version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// The version of QEMU.  By current convention, a micro version
+    /// of 50 signifies a development branch.  A micro version greater
+    /// than or equal to 90 signifies a release candidate for the next
+    /// minor version.  A micro version of less than 50 signifies a
+    /// stable release.
+    pub qemu: VersionTriple,
+    /// QEMU will always set this field to an empty string.
+    /// Downstream versions of QEMU should set this to a non-empty
+    /// string.  The exact format depends on the downstream however it
+    /// highly recommended that a unique name is used.
+    pub package: String,
+}
+impl VersionInfo {
+    pub fn new(qemu: VersionTriple, package: String) -> Self {
+        Self { qemu, package }
+    }
+}
+/// Returns the current version of QEMU.
+///
+/// # Returns
+///
+/// A @VersionInfo object describing the current version of
+/// QEMU.
+///
+/// # Examples
+///
+/// ```
+/// # use qapi::*;
+/// // -> sent to QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "query-version" }"#).unwrap();
+/// // <- received from QEMU
+/// let message: serde_json::Value = serde_json::from_str(r#"{
+///    "return":{
+///       "qemu":{
+///          "major":0,
+///          "minor":11,
+///          "micro":5
+///       },
+///       "package":""
+///    }
+/// }"#).unwrap();
+/// let _: VersionInfo = serde_json::from_value(message["return"].clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryVersion {}
+impl QueryVersion {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for QueryVersion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for QueryVersion {
+    const NAME: &'static str = "query-version";
+    type Returns = VersionInfo;
+}
+/// This command will cause the QEMU process to exit gracefully.  While
+/// every attempt is made to send the QMP response before terminating,
+/// this is not guaranteed.  When using this interface, a premature EOF
+/// would not be unexpected.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "quit" }
+/// <- { "return": {} }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quit {}
+impl Quit {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+impl Default for Quit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Command for Quit {
+    const NAME: &'static str = "quit";
+    type Returns = Empty;
+}
+// path end:	qapi/control.json
+// path begin:	qapi/qapi-schema.json
+// path end:	qapi/qapi-schema.json
+/// The commands that are only answered when they fail, see
+/// `Command::SUCCESS_RESPONSE`
+pub const NO_SUCCESS_RESPONSE: &[&str] = &[];
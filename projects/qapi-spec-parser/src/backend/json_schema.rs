@@ -0,0 +1,484 @@
+/// JSON Schema and OpenAPI output
+///
+/// Every type, command and event becomes one schema, keyed by its QAPI name.
+/// Types describe the JSON value itself. Commands describe the whole QMP
+/// message (`{ "execute": ..., "arguments": ... }`), with the value they
+/// return under `x-returns`, or `"x-success-response": false` when QEMU does
+/// not reply on success; events likewise describe their `{ "event": ... }`
+/// message. A JSON Schema document accepts any command message, so a server
+/// can validate requests against it directly.
+///
+/// Objects are left open to unknown members: a struct can also be the branch
+/// of a union or the arguments of a command, where the members of the union
+/// base or the message sit next to its own. Conditional definitions and
+/// members are all included, since the build configuration is not known here.
+use super::Backend;
+use crate::qapi_ir::{Attribute, Enum, EnumVariantKind, Metadata, Struct, StructField, StructKind};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Collects the schema of every definition into one document
+#[derive(Debug)]
+pub struct SchemaDocument {
+    /// Prefix turning a definition name into a reference to its schema
+    refs: &'static str,
+    schemas: Map<String, Value>,
+    commands: Vec<String>,
+}
+
+impl SchemaDocument {
+    /// A JSON Schema document with every definition under `$defs`
+    pub fn json_schema() -> Self {
+        Self {
+            refs: "#/$defs/",
+            schemas: Map::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// An OpenAPI document with every definition under
+    /// `components/schemas`. OpenAPI 3.1 schemas are JSON Schema, so both
+    /// documents hold the same schemas.
+    pub fn openapi() -> Self {
+        Self {
+            refs: "#/components/schemas/",
+            ..Self::json_schema()
+        }
+    }
+
+    fn add_struct_schema(&mut self, s: &Struct) {
+        let schema = match &s.kind {
+            StructKind::Type => self.object(&s.fields),
+            StructKind::Event => {
+                let mut properties = Map::new();
+                properties.insert("event".into(), json!({ "const": s.name }));
+                properties.insert("timestamp".into(), timestamp());
+                let mut required = vec!["event", "timestamp"];
+                if !s.fields.is_empty() {
+                    properties.insert("data".into(), self.object(&s.fields));
+                    required.push("data");
+                }
+                json!({ "type": "object", "properties": properties, "required": required })
+            }
+            StructKind::Command(returns) => {
+                let mut properties = Map::new();
+                properties.insert("execute".into(), json!({ "const": s.name }));
+                properties.insert("id".into(), json!({}));
+                let mut required = vec!["execute"];
+                if !s.fields.is_empty() {
+                    properties.insert("arguments".into(), self.object(&s.fields));
+                    if s.fields.iter().any(|f| !f.optional) {
+                        required.push("arguments");
+                    }
+                }
+                let returns = match returns {
+                    Some(returns) => self.field(returns),
+                    // QEMU replies with an empty object
+                    None => json!({ "type": "object", "maxProperties": 0 }),
+                };
+                self.commands.push(s.name.clone());
+                let mut schema = json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                    "x-returns": returns,
+                });
+                if s.meta.has_attribute("no_success_response") {
+                    schema["x-success-response"] = json!(false);
+                }
+                schema
+            }
+        };
+        self.insert(&s.name, &s.meta, schema);
+    }
+
+    fn add_enum_schema(&mut self, e: &Enum) {
+        let schema = if e.meta.has_attribute("alternate") {
+            let alternatives: Vec<Value> = e
+                .variants
+                .iter()
+                .map(|v| match &v.kind {
+                    EnumVariantKind::Tuple(r#type) => self.type_ref(r#type, v.array),
+                    _ => unreachable! {"alternatives always hold a type"},
+                })
+                .collect();
+            json!({ "oneOf": alternatives })
+        } else if let Some(discriminator) = e.meta.attribute("discriminator") {
+            // The tag selecting the branch, next to the members of the branch
+            let branches: Vec<Value> = e
+                .variants
+                .iter()
+                .map(|v| {
+                    let mut branch = json!({
+                        "properties": { discriminator: { "const": v.name } },
+                    });
+                    if let EnumVariantKind::Tuple(r#type) = &v.kind {
+                        branch["$ref"] = json!(format!("{}{}", self.refs, r#type));
+                    }
+                    branch
+                })
+                .collect();
+            let mut tag = json!({ "type": "string" });
+            if let Some(doc) = &e.meta.doc {
+                tag["description"] = json!(doc.trim());
+            }
+            json!({
+                "type": "object",
+                "properties": { discriminator: tag },
+                "required": [discriminator],
+                "oneOf": branches,
+            })
+        } else {
+            let values: Vec<&str> = e.variants.iter().map(|v| v.name.as_str()).collect();
+            let mut schema = json!({ "type": "string", "enum": values });
+            if e.variants.iter().any(|v| v.meta.doc.is_some()) {
+                let docs: Vec<&str> = e
+                    .variants
+                    .iter()
+                    .map(|v| v.meta.doc.as_deref().unwrap_or("").trim())
+                    .collect();
+                schema["x-enum-descriptions"] = json!(docs);
+            }
+            schema
+        };
+        // The branch enum of a union has no docs of its own; its `doc` is
+        // the description of the discriminator
+        let meta = match e.meta.attribute("discriminator") {
+            Some(_) => Metadata::default(),
+            None => e.meta.clone(),
+        };
+        self.insert(&e.name, &meta, schema);
+    }
+
+    fn insert(&mut self, name: &str, meta: &Metadata, mut schema: Value) {
+        annotate(&mut schema, meta);
+        self.schemas.insert(name.to_string(), schema);
+    }
+
+    fn object(&self, fields: &[StructField]) -> Value {
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        let mut flattened = Vec::new();
+        for field in fields {
+            if field.meta.has_attribute("flatten") {
+                flattened.push(self.field(field));
+                continue;
+            }
+            let mut schema = self.field(field);
+            annotate(&mut schema, &field.meta);
+            properties.insert(field.name.clone(), schema);
+            if !field.optional {
+                required.push(field.name.clone());
+            }
+        }
+        let mut schema = json!({ "type": "object" });
+        if !properties.is_empty() {
+            schema["properties"] = json!(properties);
+        }
+        if !required.is_empty() {
+            schema["required"] = json!(required);
+        }
+        if !flattened.is_empty() {
+            schema["allOf"] = json!(flattened);
+        }
+        schema
+    }
+
+    fn field(&self, field: &StructField) -> Value {
+        self.type_ref(&field.r#type, field.array)
+    }
+
+    fn type_ref(&self, r#type: &str, array: bool) -> Value {
+        let schema = match builtin(r#type) {
+            Some(schema) => schema,
+            None => json!({ "$ref": format!("{}{}", self.refs, r#type) }),
+        };
+        if array {
+            json!({ "type": "array", "items": schema })
+        } else {
+            schema
+        }
+    }
+
+    /// The whole document
+    pub fn to_value(&self) -> Value {
+        if self.refs.starts_with("#/components/") {
+            return json!({
+                "openapi": "3.1.0",
+                "info": { "title": "QEMU Machine Protocol", "version": "1" },
+                "jsonSchemaDialect": JSON_SCHEMA_DIALECT,
+                "paths": {},
+                "components": { "schemas": self.schemas },
+            });
+        }
+        let commands: Vec<Value> = self
+            .commands
+            .iter()
+            .map(|name| json!({ "$ref": format!("{}{}", self.refs, name) }))
+            .collect();
+        json!({
+            "$schema": JSON_SCHEMA_DIALECT,
+            "title": "QEMU Machine Protocol command",
+            "oneOf": commands,
+            "$defs": self.schemas,
+        })
+    }
+}
+
+impl Backend for SchemaDocument {
+    fn add_struct(&mut self, s: &Struct) -> Result<()> {
+        self.add_struct_schema(s);
+        Ok(())
+    }
+
+    fn add_enum(&mut self, e: &Enum) -> Result<()> {
+        self.add_enum_schema(e);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.to_value())? + "\n")
+    }
+}
+
+/// Add the description, and mark the schema deprecated when QAPI does
+fn annotate(schema: &mut Value, meta: &Metadata) {
+    if let Some(doc) = meta.render_doc(None) {
+        schema["description"] = json!(doc);
+    }
+    let deprecated = meta.attributes.iter().any(|a| match a {
+        Attribute::List(attrs) => attrs.contains(&Attribute::with_value("feature", "deprecated")),
+        _ => false,
+    });
+    if deprecated {
+        schema["deprecated"] = json!(true);
+    }
+}
+
+/// When QEMU sent an event
+fn timestamp() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "seconds": { "type": "integer" },
+            "microseconds": { "type": "integer" },
+        },
+        "required": ["seconds", "microseconds"],
+    })
+}
+
+fn builtin(r#type: &str) -> Option<Value> {
+    let integer = |min: i64, max: i64| json!({ "type": "integer", "minimum": min, "maximum": max });
+    Some(match r#type {
+        "str" | "QType" => json!({ "type": "string" }),
+        "number" => json!({ "type": "number" }),
+        "int" | "int64" => json!({ "type": "integer" }),
+        "int8" => integer(i8::MIN.into(), i8::MAX.into()),
+        "int16" => integer(i16::MIN.into(), i16::MAX.into()),
+        "int32" => integer(i32::MIN.into(), i32::MAX.into()),
+        "uint8" => integer(0, u8::MAX.into()),
+        "uint16" => integer(0, u16::MAX.into()),
+        "uint32" => integer(0, u32::MAX.into()),
+        "uint64" | "size" => json!({ "type": "integer", "minimum": 0 }),
+        "bool" => json!({ "type": "boolean" }),
+        "null" => json!({ "type": "null" }),
+        "any" => json!({}),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qapi_ir::{process_alternate, process_command, process_enum, process_union};
+    use crate::qapi_ir::{process_event, process_struct};
+    use crate::{take_schema, DependencyGraph, QapiSchemaToken};
+    use std::collections::HashMap;
+
+    fn document(input: &str, mut document: SchemaDocument) -> Value {
+        let schemas = vec![take_schema(input).unwrap().1];
+        let mut structs = HashMap::new();
+        let mut enums = HashMap::new();
+        for token in DependencyGraph::new(&schemas).order() {
+            match token.clone() {
+                QapiSchemaToken::Enum(v) => {
+                    let e = process_enum(v).unwrap();
+                    document.add_enum(&e).unwrap();
+                    enums.insert(e.name.clone(), e);
+                }
+                QapiSchemaToken::Alternate(v) => {
+                    let e = process_alternate(v, &structs, &enums).unwrap();
+                    document.add_enum(&e).unwrap();
+                    enums.insert(e.name.clone(), e);
+                }
+                QapiSchemaToken::Struct(v) => {
+                    let s = process_struct(v, &structs).unwrap();
+                    document.add_struct(&s).unwrap();
+                    structs.insert(s.name.clone(), s);
+                }
+                QapiSchemaToken::Union(v) => {
+                    let (s, e) = process_union(v, &structs, &enums).unwrap();
+                    document.add_enum(&e).unwrap();
+                    document.add_struct(&s).unwrap();
+                    structs.insert(s.name.clone(), s);
+                }
+                QapiSchemaToken::Command(v) => {
+                    document
+                        .add_struct(&process_command(v, &structs).unwrap())
+                        .unwrap();
+                }
+                QapiSchemaToken::Event(v) => {
+                    document
+                        .add_struct(&process_event(v, &structs).unwrap())
+                        .unwrap();
+                }
+                _ => {}
+            }
+        }
+        document.to_value()
+    }
+
+    const SCHEMA: &str = r#"
+{ 'enum': 'Kind', 'data': [ 'file', 'null' ] }
+{ 'struct': 'FileOpts', 'data': { 'filename': 'str', '*size': 'uint32' } }
+{ 'union': 'Opts', 'base': { 'kind': 'Kind', '*id': 'str' },
+  'discriminator': 'kind', 'data': { 'file': 'FileOpts' } }
+{ 'alternate': 'OptsRef', 'data': { 'definition': 'Opts', 'reference': 'str' } }
+{ 'command': 'opts-add', 'data': 'Opts', 'boxed': true }
+{ 'command': 'query-opts', 'data': { '*ids': [ 'str' ] }, 'returns': [ 'Opts' ] }
+{ 'event': 'OPTS_CHANGED', 'data': { 'ref': 'OptsRef' } }
+"#;
+
+    #[test]
+    fn test_types() {
+        let doc = document(SCHEMA, SchemaDocument::json_schema());
+        let defs = &doc["$defs"];
+        assert_eq!(
+            defs["Kind"],
+            json!({ "type": "string", "enum": ["file", "null"] })
+        );
+        assert_eq!(
+            defs["FileOpts"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "filename": { "type": "string" },
+                    "size": { "type": "integer", "minimum": 0, "maximum": 4294967295u32 },
+                },
+                "required": ["filename"],
+            })
+        );
+        assert_eq!(
+            defs["Opts"],
+            json!({
+                "type": "object",
+                "properties": { "id": { "type": "string" } },
+                "allOf": [{ "$ref": "#/$defs/OptsBranch" }],
+            })
+        );
+        assert_eq!(
+            defs["OptsBranch"],
+            json!({
+                "type": "object",
+                "properties": { "kind": { "type": "string" } },
+                "required": ["kind"],
+                "oneOf": [
+                    { "properties": { "kind": { "const": "file" } }, "$ref": "#/$defs/FileOpts" },
+                    { "properties": { "kind": { "const": "null" } } },
+                ],
+            })
+        );
+        assert_eq!(
+            defs["OptsRef"],
+            json!({ "oneOf": [{ "$ref": "#/$defs/Opts" }, { "type": "string" }] })
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let doc = document(SCHEMA, SchemaDocument::json_schema());
+        assert_eq!(
+            doc["oneOf"],
+            json!([{ "$ref": "#/$defs/opts-add" }, { "$ref": "#/$defs/query-opts" }])
+        );
+        let defs = &doc["$defs"];
+        assert_eq!(
+            defs["opts-add"]["properties"]["arguments"],
+            json!({ "type": "object", "allOf": [{ "$ref": "#/$defs/Opts" }] })
+        );
+        assert_eq!(
+            defs["opts-add"]["required"],
+            json!(["execute", "arguments"])
+        );
+        assert_eq!(
+            defs["opts-add"]["x-returns"],
+            json!({ "type": "object", "maxProperties": 0 })
+        );
+        assert_eq!(defs["query-opts"]["required"], json!(["execute"]));
+        assert_eq!(
+            defs["query-opts"]["x-returns"],
+            json!({ "type": "array", "items": { "$ref": "#/$defs/Opts" } })
+        );
+        assert_eq!(
+            defs["OPTS_CHANGED"]["properties"]["data"]["properties"]["ref"],
+            json!({ "$ref": "#/$defs/OptsRef" })
+        );
+        assert_eq!(
+            defs["OPTS_CHANGED"]["required"],
+            json!(["event", "timestamp", "data"])
+        );
+        assert!(defs["opts-add"].get("x-success-response").is_none());
+
+        let input = "{ 'command': 'guest-shutdown', 'success-response': false }";
+        let doc = document(input, SchemaDocument::json_schema());
+        assert_eq!(
+            doc["$defs"]["guest-shutdown"]["x-success-response"],
+            json!(false)
+        );
+    }
+
+    #[test]
+    fn test_openapi() {
+        let doc = document(SCHEMA, SchemaDocument::openapi());
+        assert_eq!(doc["openapi"], "3.1.0");
+        let schemas = &doc["components"]["schemas"];
+        assert_eq!(
+            schemas["OptsRef"]["oneOf"][0],
+            json!({ "$ref": "#/components/schemas/Opts" })
+        );
+        assert_eq!(schemas.as_object().unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_docs_and_features() {
+        let input = r#"
+##
+# @Thing:
+#
+# A thing.
+#
+# @old: not used any more
+#
+# Features:
+#
+# @deprecated: Member @old is deprecated.
+#
+# Since: 1.0
+##
+{ 'struct': 'Thing',
+  'data': { '*old': { 'type': 'int', 'features': [ 'deprecated' ] } } }
+"#;
+        let doc = document(input, SchemaDocument::json_schema());
+        let thing = &doc["$defs"]["Thing"];
+        assert_eq!(
+            thing["description"],
+            "A thing.\n\n# Features\n\n- `deprecated`: Member @old is deprecated."
+        );
+        assert_eq!(
+            thing["properties"]["old"],
+            json!({ "type": "integer", "description": "not used any more", "deprecated": true })
+        );
+    }
+}
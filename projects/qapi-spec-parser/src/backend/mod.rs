@@ -0,0 +1,53 @@
+/// Code generation backends
+///
+/// A backend turns the IR of a whole schema into one output. It is handed the
+/// definitions in source order, one schema file at a time, and produces the
+/// output once everything has been added.
+use crate::qapi_ir::{Enum, Struct};
+use anyhow::Result;
+use proc_macro2::TokenStream;
+use std::path::Path;
+
+mod json_schema;
+pub use json_schema::SchemaDocument;
+
+mod qapi_macros;
+pub use qapi_macros::QapiMacros;
+
+mod serde_derive;
+pub use serde_derive::SerdeDerive;
+
+pub trait Backend {
+    /// Called once, before anything else
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the path of each schema file, relative to the QEMU source
+    /// root, before its definitions
+    fn begin_file(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn end_file(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_struct(&mut self, s: &Struct) -> Result<()>;
+
+    fn add_enum(&mut self, e: &Enum) -> Result<()>;
+
+    /// The complete output
+    fn finish(&mut self) -> Result<String>;
+}
+
+/// Format generated Rust code
+fn unparse(code: TokenStream) -> Result<String> {
+    let syntree: syn::File = syn::parse2(code)?;
+    Ok(prettyplease::unparse(&syntree))
+}
+
+/// Marker comments delimiting the items of each schema file in Rust output
+fn path_marker(which: &str, path: &Path) -> String {
+    format!("// path {}:\t{}\n", which, path.display())
+}
@@ -0,0 +1,62 @@
+/// Rust types annotated with `#[qapi(...)]` attributes, for the `qapi_macros`
+/// crate to expand
+use super::{path_marker, unparse, Backend};
+use crate::qapi_ir::{generate_builtins, Enum, Struct, DEFAULT_DOCTEST_CRATE};
+use anyhow::Result;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct QapiMacros {
+    out: String,
+    doctest_crate: String,
+}
+
+impl QapiMacros {
+    /// Doc-tests will `use` the generated code from `doctest_crate`
+    pub fn new<S: ToString>(doctest_crate: S) -> Self {
+        Self {
+            out: String::new(),
+            doctest_crate: doctest_crate.to_string(),
+        }
+    }
+}
+
+impl Default for QapiMacros {
+    fn default() -> Self {
+        Self::new(DEFAULT_DOCTEST_CRATE)
+    }
+}
+
+impl Backend for QapiMacros {
+    fn begin(&mut self) -> Result<()> {
+        self.out.push_str("use qapi_macros::qapi;\n");
+        self.out.push_str("use serde_json;\n");
+        self.out.push_str(&unparse(generate_builtins())?);
+        Ok(())
+    }
+
+    fn begin_file(&mut self, path: &Path) -> Result<()> {
+        self.out.push_str(&path_marker("begin", path));
+        Ok(())
+    }
+
+    fn end_file(&mut self, path: &Path) -> Result<()> {
+        self.out.push_str(&path_marker("end", path));
+        Ok(())
+    }
+
+    fn add_struct(&mut self, s: &Struct) -> Result<()> {
+        self.out
+            .push_str(&unparse(s.generate(&self.doctest_crate))?);
+        Ok(())
+    }
+
+    fn add_enum(&mut self, e: &Enum) -> Result<()> {
+        self.out.push_str(&unparse(e.generate())?);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String> {
+        Ok(std::mem::take(&mut self.out))
+    }
+}
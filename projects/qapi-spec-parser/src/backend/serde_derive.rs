@@ -0,0 +1,501 @@
+/// Plain Rust types deriving `serde` traits, needing no proc-macro of our own
+///
+/// The IR attributes the `qapi_macros` crate would expand are spelled out
+/// here instead: `name` becomes a serde rename, `flatten` a flattened field,
+/// a union's branch enum is tagged by its discriminator and an alternate is
+/// untagged. Commands and events implement the `Command` and `Event` traits
+/// defined at the top of the output, or the `Command` trait a QMP client
+/// brings along (see `command_trait`). When there are commands, the names of
+/// those without a success response are listed at the end, for QMP clients
+/// that only know commands by name.
+///
+/// Conditions are not known outside a QEMU build, so conditional definitions
+/// and members are always included.
+use super::{path_marker, unparse, Backend};
+use crate::qapi_ir::{rustify_field, DocItem, DEFAULT_DOCTEST_CRATE};
+use crate::qapi_ir::{rustify_type, Enum, EnumVariantKind, Metadata, Struct, StructKind};
+use anyhow::{anyhow, Result};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct SerdeDerive {
+    out: String,
+    doctest_crate: String,
+    /// The `Command` trait to use, when not defining one
+    command_trait: Option<String>,
+    /// The commands without a success response, once any command was added
+    no_success_response: Option<Vec<String>>,
+}
+
+impl SerdeDerive {
+    /// Doc-tests will `use` the generated code from `doctest_crate`
+    pub fn new<S: ToString>(doctest_crate: S) -> Self {
+        Self {
+            out: String::new(),
+            doctest_crate: doctest_crate.to_string(),
+            command_trait: None,
+            no_success_response: None,
+        }
+    }
+
+    /// Re-export the `Command` trait at `path` instead of defining it
+    pub fn command_trait<S: ToString>(mut self, path: S) -> Self {
+        self.command_trait = Some(path.to_string());
+        self
+    }
+}
+
+impl Default for SerdeDerive {
+    fn default() -> Self {
+        Self::new(DEFAULT_DOCTEST_CRATE)
+    }
+}
+
+fn rename(meta: &Metadata) -> Option<TokenStream> {
+    meta.attribute("name").map(|name| {
+        quote! {
+            #[serde(rename = #name)]
+        }
+    })
+}
+
+impl Backend for SerdeDerive {
+    fn begin(&mut self) -> Result<()> {
+        let null_doc = " The QAPI builtin `null` type, which only ever holds JSON `null`";
+        let command = match &self.command_trait {
+            Some(path) => {
+                let path: TokenStream = path.parse().map_err(|e| anyhow!("{}: {}", path, e))?;
+                quote! {
+                    pub use #path;
+                }
+            }
+            None => quote! {
+                #[doc = " A QMP command, sent as `{ \"execute\": NAME, \"arguments\": ... }`"]
+                #[doc = " with the command as its arguments"]
+                pub trait Command: Serialize {
+                    const NAME: &'static str;
+                    #[doc = " Whether QEMU replies when the command succeeds. Without a"]
+                    #[doc = " success response only failures are answered, so nothing"]
+                    #[doc = " should wait for a reply."]
+                    const SUCCESS_RESPONSE: bool = true;
+                    #[doc = " What QEMU replies with when the command succeeds"]
+                    type Returns: DeserializeOwned;
+                }
+            },
+        };
+        let code = quote! {
+            use serde::de::DeserializeOwned;
+            use serde::{Deserialize, Serialize};
+
+            #command
+
+            #[doc = " A QMP event, received as `{ \"event\": NAME, \"data\": ... }`"]
+            pub trait Event: DeserializeOwned {
+                const NAME: &'static str;
+            }
+
+            #[doc = #null_doc]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+            pub struct Null;
+
+            #[doc = " The empty object QEMU replies with when a command returns nothing"]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+            pub struct Empty {}
+        };
+        self.out.push_str(&unparse(code)?);
+        Ok(())
+    }
+
+    fn begin_file(&mut self, path: &Path) -> Result<()> {
+        self.out.push_str(&path_marker("begin", path));
+        Ok(())
+    }
+
+    fn end_file(&mut self, path: &Path) -> Result<()> {
+        self.out.push_str(&path_marker("end", path));
+        Ok(())
+    }
+
+    fn add_struct(&mut self, s: &Struct) -> Result<()> {
+        let rust_name = s.rust_name();
+        let struct_name = format_ident!("{}", rust_name);
+        let returns = match &s.kind {
+            StructKind::Command(Some(returns)) => Some(returns.rust_type()),
+            StructKind::Command(None) => Some(quote!(Empty)),
+            _ => None,
+        };
+        let returns_name = returns.as_ref().map(|r| r.to_string().replace(' ', ""));
+        let item = DocItem {
+            name: &s.name,
+            rust_name: &rust_name,
+            returns: returns_name.as_deref().filter(|r| *r != "Empty"),
+            krate: &self.doctest_crate,
+        };
+        let struct_doc = s.meta.generate_doc(Some(item));
+
+        let fields = s.fields.iter().map(|field| {
+            let field_name: TokenStream = rustify_field(&field.name).parse().unwrap();
+            let field_type = field.rust_type();
+            let field_doc = field.meta.generate_doc(None);
+            let serde = if field.meta.has_attribute("flatten") {
+                Some(quote! {
+                    #[serde(flatten)]
+                })
+            } else if field.optional {
+                Some(quote! {
+                    #[serde(default, skip_serializing_if = "Option::is_none")]
+                })
+            } else {
+                None
+            };
+            let rename = rename(&field.meta);
+            quote! {
+                #field_doc
+                #rename
+                #serde
+                pub #field_name: #field_type,
+            }
+        });
+
+        let name = &s.name;
+        let message = match (&s.kind, returns) {
+            (StructKind::Command(_), Some(returns)) => {
+                let listed = self.no_success_response.get_or_insert_with(Vec::new);
+                let success_response = s.meta.has_attribute("no_success_response").then(|| {
+                    listed.push(name.clone());
+                    quote! {
+                        const SUCCESS_RESPONSE: bool = false;
+                    }
+                });
+                Some(quote! {
+                    impl Command for #struct_name {
+                        const NAME: &'static str = #name;
+                        #success_response
+                        type Returns = #returns;
+                    }
+                })
+            }
+            (StructKind::Event, _) => Some(quote! {
+                impl Event for #struct_name {
+                    const NAME: &'static str = #name;
+                }
+            }),
+            _ => None,
+        };
+
+        let builder = s.generate_builder(false);
+        let code = quote! {
+            #struct_doc
+            #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+            pub struct #struct_name {
+                #(#fields)*
+            }
+            #builder
+            #message
+        };
+        self.out.push_str(&unparse(code)?);
+        Ok(())
+    }
+
+    fn add_enum(&mut self, e: &Enum) -> Result<()> {
+        let enum_name = format_ident!("{}", rustify_type(&e.name));
+        let enum_doc = e.meta.generate_doc(None);
+        let alternate = e.meta.has_attribute("alternate");
+        let unit = e
+            .variants
+            .iter()
+            .all(|v| matches!(v.kind, EnumVariantKind::Unit));
+
+        let (derive, serde) = if alternate {
+            (
+                quote! {
+                    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+                },
+                Some(quote! {
+                    #[serde(untagged)]
+                }),
+            )
+        } else if let Some(tag) = e.meta.attribute("discriminator") {
+            (
+                quote! {
+                    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+                },
+                Some(quote! {
+                    #[serde(tag = #tag)]
+                }),
+            )
+        } else if unit {
+            (
+                quote! {
+                    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+                },
+                None,
+            )
+        } else {
+            unreachable! {"only alternates and unions have variants holding a type"}
+        };
+
+        let variants = e.variants.iter().map(|variant| {
+            let variant_name = format_ident!("{}", rustify_type(&variant.name));
+            let variant_doc = variant.meta.generate_doc(None);
+            // An untagged variant is never named on the wire
+            let rename = rename(&variant.meta).filter(|_| !alternate);
+            match &variant.kind {
+                EnumVariantKind::Unit => quote! {
+                    #variant_doc
+                    #rename
+                    #variant_name,
+                },
+                EnumVariantKind::Tuple(type_name) => {
+                    let mut variant_type: TokenStream = rustify_type(type_name).parse().unwrap();
+                    if variant.array {
+                        variant_type = quote!( Vec<#variant_type> );
+                    }
+                    if variant.boxed {
+                        variant_type = quote!( Box<#variant_type> );
+                    }
+                    quote! {
+                        #variant_doc
+                        #rename
+                        #variant_name(#variant_type),
+                    }
+                }
+                EnumVariantKind::Struct(_) => {
+                    unreachable! {"struct variants are not made from QAPI definitions"}
+                }
+            }
+        });
+
+        let code = quote! {
+            #enum_doc
+            #derive
+            #serde
+            pub enum #enum_name {
+                #(#variants)*
+            }
+        };
+        self.out.push_str(&unparse(code)?);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<String> {
+        if let Some(names) = self.no_success_response.take() {
+            let code = quote! {
+                #[doc = " The commands that are only answered when they fail, see"]
+                #[doc = " `Command::SUCCESS_RESPONSE`"]
+                pub const NO_SUCCESS_RESPONSE: &[&str] = &[#(#names),*];
+            };
+            self.out.push_str(&unparse(code)?);
+        }
+        Ok(std::mem::take(&mut self.out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qapi_ir::{Attribute, EnumVariant, StructField};
+
+    fn field(name: &str, r#type: &str, optional: bool) -> StructField {
+        let mut meta = Metadata::default();
+        if name.contains('-') {
+            meta.attributes.push(Attribute::with_value("name", name));
+        }
+        StructField {
+            name: name.into(),
+            meta,
+            r#type: r#type.into(),
+            optional,
+            array: false,
+            boxed: false,
+        }
+    }
+
+    fn variant(name: &str, kind: EnumVariantKind) -> EnumVariant {
+        EnumVariant {
+            name: name.into(),
+            meta: Metadata::default(),
+            kind,
+            array: false,
+            boxed: false,
+        }
+    }
+
+    #[test]
+    fn test_command() {
+        let mut returns = field("return", "ThingInfo", false);
+        returns.array = true;
+        let mut meta = Metadata::default();
+        meta.attributes
+            .push(Attribute::with_value("name", "query-things"));
+        let s = Struct {
+            name: "query-things".into(),
+            meta,
+            kind: StructKind::Command(Some(returns)),
+            fields: vec![field("node-name", "str", true)],
+        };
+        let mut backend = SerdeDerive::default();
+        backend.add_struct(&s).unwrap();
+        let out = backend.finish().unwrap();
+        assert!(
+            out.starts_with(
+                r#"#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryThings {
+    #[serde(rename = "node-name")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+}
+"#
+            ),
+            "{}",
+            out
+        );
+        assert!(out.contains(
+            r#"impl Command for QueryThings {
+    const NAME: &'static str = "query-things";
+    type Returns = Vec<ThingInfo>;
+}
+"#
+        ));
+        assert!(out.ends_with("pub const NO_SUCCESS_RESPONSE: &[&str] = &[];\n"));
+    }
+
+    #[test]
+    fn test_command_without_success_response() {
+        let mut meta = Metadata::default();
+        meta.attributes.push(Attribute::new("no_success_response"));
+        let s = Struct {
+            name: "shutdown".into(),
+            meta,
+            kind: StructKind::Command(None),
+            fields: Vec::new(),
+        };
+        let mut backend = SerdeDerive::default();
+        backend.add_struct(&s).unwrap();
+        let out = backend.finish().unwrap();
+        assert!(out.contains(
+            r#"impl Command for Shutdown {
+    const NAME: &'static str = "shutdown";
+    const SUCCESS_RESPONSE: bool = false;
+    type Returns = Empty;
+}
+"#
+        ));
+        assert!(out.ends_with("pub const NO_SUCCESS_RESPONSE: &[&str] = &[\"shutdown\"];\n"));
+    }
+
+    #[test]
+    fn test_command_trait() {
+        let mut backend = SerdeDerive::default();
+        backend.begin().unwrap();
+        let out = backend.finish().unwrap();
+        assert!(out.contains("pub trait Command: Serialize {"));
+
+        let mut backend = SerdeDerive::default().command_trait("qmp::qapi::Command");
+        backend.begin().unwrap();
+        let out = backend.finish().unwrap();
+        assert!(out.contains("pub use qmp::qapi::Command;\n"), "{}", out);
+        assert!(!out.contains("trait Command"));
+        assert!(out.contains("pub trait Event: DeserializeOwned {"));
+    }
+
+    #[test]
+    fn test_names_do_not_clash() {
+        let mut meta = Metadata::default();
+        meta.attributes.push(Attribute::with_value("name", "STOP"));
+        let event = Struct {
+            name: "STOP".into(),
+            meta,
+            kind: StructKind::Event,
+            fields: Vec::new(),
+        };
+        let command = Struct {
+            name: "stop".into(),
+            meta: Metadata::default(),
+            kind: StructKind::Command(None),
+            fields: Vec::new(),
+        };
+        let string = Struct {
+            name: "String".into(),
+            meta: Metadata::default(),
+            kind: StructKind::Type,
+            fields: vec![field("str", "str", false)],
+        };
+        let mut backend = SerdeDerive::default();
+        for s in [&event, &command, &string] {
+            backend.add_struct(s).unwrap();
+        }
+        let out = backend.finish().unwrap();
+        assert!(out.contains("pub struct StopEvent {}"), "{}", out);
+        assert!(out.contains("impl Event for StopEvent {"));
+        assert!(out.contains("pub struct Stop {}"));
+        assert!(out.contains("impl Command for Stop {"));
+        assert!(out.contains("pub struct QapiString {\n    pub str: String,\n}"));
+    }
+
+    #[test]
+    fn test_union_branches() {
+        let mut meta = Metadata::default();
+        meta.attributes
+            .push(Attribute::with_value("discriminator", "driver"));
+        let mut null_co = variant("null-co", EnumVariantKind::Unit);
+        null_co
+            .meta
+            .attributes
+            .push(Attribute::with_value("name", "null-co"));
+        let mut file = variant("file", EnumVariantKind::Tuple("FileOpts".into()));
+        file.meta
+            .attributes
+            .push(Attribute::with_value("name", "file"));
+        file.boxed = true;
+        let e = Enum {
+            name: "OptsBranch".into(),
+            meta,
+            variants: vec![file, null_co],
+        };
+        let mut backend = SerdeDerive::default();
+        backend.add_enum(&e).unwrap();
+        assert_eq!(
+            backend.finish().unwrap(),
+            r#"#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "driver")]
+pub enum OptsBranch {
+    #[serde(rename = "file")]
+    File(Box<FileOpts>),
+    #[serde(rename = "null-co")]
+    NullCo,
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_alternate() {
+        let mut meta = Metadata::default();
+        meta.attributes.push(Attribute::new("alternate"));
+        let mut s = variant("s", EnumVariantKind::Tuple("str".into()));
+        s.meta.attributes.push(Attribute::with_value("name", "s"));
+        let mut l = variant("l", EnumVariantKind::Tuple("str".into()));
+        l.array = true;
+        let e = Enum {
+            name: "StrOrNull".into(),
+            meta,
+            variants: vec![s, l, variant("n", EnumVariantKind::Tuple("null".into()))],
+        };
+        let mut backend = SerdeDerive::default();
+        backend.add_enum(&e).unwrap();
+        assert_eq!(
+            backend.finish().unwrap(),
+            r#"#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StrOrNull {
+    S(String),
+    L(Vec<String>),
+    N(Null),
+}
+"#
+        );
+    }
+}
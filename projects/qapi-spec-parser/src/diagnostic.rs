@@ -0,0 +1,207 @@
+/// Diagnostics for malformed or invalid QAPI schemas
+///
+/// Every parser in this crate hands out `&str` slices borrowed from the
+/// original schema source. That means any slice we get back, whether it is the
+/// unparsed remainder from nom or a name held in the parsed structs, still
+/// points into the source it came from. We use that to recover the file, line,
+/// and column for an error without threading spans through every parser.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl Location {
+    /// Build a location from a byte offset into `source`
+    pub fn from_offset(path: &Path, source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or(source.len());
+        Self {
+            path: path.to_path_buf(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            snippet: source[line_start..line_end].to_string(),
+        }
+    }
+
+    /// Find the location of `span` if it was borrowed from one of `sources`
+    pub fn from_span(sources: &[(PathBuf, String)], span: Span) -> Option<Self> {
+        sources.iter().find_map(|(path, source)| {
+            span.offset_in(source)
+                .map(|offset| Self::from_offset(path, source, offset))
+        })
+    }
+}
+
+/// The address of a `&str` slice borrowed from a schema source.
+///
+/// Only the address is kept so a `Diagnostic` does not need to carry the
+/// lifetime of the source it came from. It is never dereferenced; it is only
+/// compared against the address range of each source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span(usize);
+
+impl Span {
+    pub fn of(s: &str) -> Self {
+        Self(s.as_ptr() as usize)
+    }
+
+    fn offset_in(&self, source: &str) -> Option<usize> {
+        let start = source.as_ptr() as usize;
+        if self.0 >= start && self.0 <= start + source.len() {
+            Some(self.0 - start)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn new<M: ToString>(message: M) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.to_string(),
+            span: None,
+            location: None,
+        }
+    }
+
+    pub fn warning<M: ToString>(message: M) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Attach the slice of schema source this diagnostic is about
+    pub fn with_span(mut self, span: &str) -> Self {
+        self.span = Some(Span::of(span));
+        self
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Resolve the span, if any, against the loaded schema sources
+    pub fn locate(mut self, sources: &[(PathBuf, String)]) -> Self {
+        if self.location.is_none() {
+            if let Some(span) = self.span {
+                self.location = Location::from_span(sources, span);
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(loc) = &self.location {
+            let gutter = " ".repeat(loc.line.to_string().len());
+            let caret_pad: String = loc
+                .snippet
+                .chars()
+                .take(loc.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{gutter}--> {}:{}:{}",
+                loc.path.display(),
+                loc.line,
+                loc.column
+            )?;
+            writeln!(f, "{gutter} |")?;
+            writeln!(f, "{} | {}", loc.line, loc.snippet)?;
+            write!(f, "{gutter} | {caret_pad}^")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "{ 'enum': 'Foo',\n  'data': [ 'a', 'b' ] }\n";
+
+    #[test]
+    fn test_location_from_offset() {
+        let offset = SOURCE.find("'data'").unwrap();
+        let loc = Location::from_offset(Path::new("foo.json"), SOURCE, offset);
+        assert_eq!(loc.line, 2);
+        assert_eq!(loc.column, 3);
+        assert_eq!(loc.snippet, "  'data': [ 'a', 'b' ] }");
+    }
+
+    #[test]
+    fn test_locate_span() {
+        let sources = vec![
+            (
+                PathBuf::from("other.json"),
+                "{ 'include': 'foo.json' }".to_string(),
+            ),
+            (PathBuf::from("foo.json"), SOURCE.to_string()),
+        ];
+        let name = &sources[1].1[11..14];
+        assert_eq!(name, "Foo");
+        let diag = Diagnostic::new("unknown type")
+            .with_span(name)
+            .locate(&sources);
+        let loc = diag.location.expect("span should resolve");
+        assert_eq!(loc.path, PathBuf::from("foo.json"));
+        assert_eq!((loc.line, loc.column), (1, 12));
+    }
+
+    #[test]
+    fn test_display() {
+        let offset = SOURCE.find("'b'").unwrap();
+        let loc = Location::from_offset(Path::new("foo.json"), SOURCE, offset);
+        let diag = Diagnostic::new("bad value").with_location(loc);
+        assert_eq!(
+            diag.to_string(),
+            "bad value\n --> foo.json:2:18\n  |\n2 |   'data': [ 'a', 'b' ] }\n  |                  ^"
+        );
+    }
+}
@@ -0,0 +1,335 @@
+/// Dependency graph over every definition in a QAPI schema
+///
+/// Turning a parsed item into IR sometimes needs another item to have been
+/// processed first: a struct copies the fields of its base, a union needs its
+/// base and its discriminator enum, an alternate needs to know what kind of
+/// JSON each of its alternatives is, and commands and events can take their
+/// arguments from a named struct or union. Those items may live anywhere in
+/// the included files, before or after the item that needs them.
+///
+/// Only these structural dependencies are edges in the graph. Members that
+/// merely refer to another type are not, since QAPI allows types to refer to
+/// each other recursively through their members.
+use crate::{Diagnostic, MembersOrRef, QapiSchema, QapiSchemaToken, QapiTypeRef};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+#[derive(Debug)]
+struct Node<'a, 'i> {
+    name: &'i str,
+    token: &'a QapiSchemaToken<'i>,
+    dependencies: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct DependencyGraph<'a, 'i> {
+    nodes: Vec<Node<'a, 'i>>,
+}
+
+fn token_name<'i>(token: &QapiSchemaToken<'i>) -> Option<&'i str> {
+    match token {
+        QapiSchemaToken::Enum(v) => Some(v.name),
+        QapiSchemaToken::Alternate(v) => Some(v.name),
+        QapiSchemaToken::Struct(v) => Some(v.name),
+        QapiSchemaToken::Union(v) => Some(v.name),
+        QapiSchemaToken::Command(v) => Some(v.name),
+        QapiSchemaToken::Event(v) => Some(v.name),
+        _ => None,
+    }
+}
+
+impl<'a, 'i: 'a> DependencyGraph<'a, 'i> {
+    /// Build the graph from all schema files. The order of `schemas` is kept as
+    /// a tie breaker so the resolved order stays close to the source order.
+    pub fn new(schemas: &'a [QapiSchema<'i>]) -> Self {
+        let mut index = HashMap::new();
+        let mut nodes = Vec::new();
+        for token in schemas.iter().flat_map(|s| s.iter()) {
+            if let Some(name) = token_name(token) {
+                // Duplicates are reported by validation; the first one wins
+                if !index.contains_key(name) {
+                    index.insert(name, nodes.len());
+                    nodes.push(Node {
+                        name,
+                        token,
+                        dependencies: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let tokens: HashMap<&str, &QapiSchemaToken> =
+            nodes.iter().map(|n| (n.name, n.token)).collect();
+        for node in &mut nodes {
+            node.dependencies = Self::dependencies_of(node.token, &tokens)
+                .into_iter()
+                .filter_map(|name| index.get(name).copied())
+                .collect();
+        }
+        Self { nodes }
+    }
+
+    fn dependencies_of(
+        token: &QapiSchemaToken<'i>,
+        tokens: &HashMap<&str, &QapiSchemaToken<'i>>,
+    ) -> Vec<&'i str> {
+        match token {
+            QapiSchemaToken::Struct(v) => match v.base {
+                Some(MembersOrRef::Ref(base)) => vec![base],
+                _ => Vec::new(),
+            },
+            QapiSchemaToken::Union(v) => {
+                let mut deps = Vec::new();
+                if let MembersOrRef::Ref(base) = v.base {
+                    deps.push(base);
+                }
+                if let Some(t) = Self::discriminator_type(&v.base, v.discriminator, tokens) {
+                    deps.push(t);
+                }
+                deps
+            }
+            QapiSchemaToken::Alternate(v) => v
+                .data
+                .iter()
+                .map(|a| match a.r#type {
+                    QapiTypeRef::Ref(t) | QapiTypeRef::ArrayRef(t) => t,
+                    QapiTypeRef::Unset => unreachable! {"this should have failed the parser"},
+                })
+                .collect(),
+            QapiSchemaToken::Command(v) => match v.data {
+                Some(MembersOrRef::Ref(data)) => vec![data],
+                _ => Vec::new(),
+            },
+            QapiSchemaToken::Event(v) => match v.data {
+                Some(MembersOrRef::Ref(data)) => vec![data],
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Find the type of a union's discriminator, following base structs
+    fn discriminator_type(
+        base: &MembersOrRef<'i>,
+        discriminator: &str,
+        tokens: &HashMap<&str, &QapiSchemaToken<'i>>,
+    ) -> Option<&'i str> {
+        let find = |members: &crate::QapiMembers<'i>| {
+            members
+                .iter()
+                .find(|m| m.name == discriminator)
+                .and_then(|m| match m.r#type {
+                    QapiTypeRef::Ref(t) => Some(t),
+                    _ => None,
+                })
+        };
+        let mut next = match base {
+            MembersOrRef::Members(members) => return find(members),
+            MembersOrRef::Ref(base) => Some(*base),
+            MembersOrRef::Unset => None,
+        };
+        let mut seen = HashSet::new();
+        while let Some(name) = next {
+            if !seen.insert(name) {
+                break;
+            }
+            let Some(QapiSchemaToken::Struct(s)) = tokens.get(name) else {
+                break;
+            };
+            if let Some(t) = find(&s.data) {
+                return Some(t);
+            }
+            next = match &s.base {
+                Some(MembersOrRef::Members(members)) => return find(members),
+                Some(MembersOrRef::Ref(base)) => Some(*base),
+                _ => None,
+            };
+        }
+        None
+    }
+
+    /// Every definition ordered so that each one comes after everything it
+    /// depends on. Definitions caught in a cycle are left out; see `cycles`.
+    pub fn order(&self) -> Vec<&'a QapiSchemaToken<'i>> {
+        let mut dependents = vec![Vec::new(); self.nodes.len()];
+        let mut pending: Vec<usize> = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &dep in &node.dependencies {
+                dependents[dep].push(i);
+                pending[i] += 1;
+            }
+        }
+        // Kahn's algorithm, always taking the earliest ready definition
+        let mut ready: BinaryHeap<Reverse<usize>> = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, &n)| n == 0)
+            .map(|(i, _)| Reverse(i))
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(Reverse(i)) = ready.pop() {
+            order.push(self.nodes[i].token);
+            for &dependent in &dependents[i] {
+                pending[dependent] -= 1;
+                if pending[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+        order
+    }
+
+    /// Report every dependency cycle, one diagnostic per cycle
+    pub fn cycles(&self) -> Vec<Diagnostic> {
+        let edges: Vec<Vec<usize>> = self.nodes.iter().map(|n| n.dependencies.clone()).collect();
+        cycles(&edges)
+            .into_iter()
+            .map(|component| {
+                let names: Vec<&str> = component.iter().map(|&i| self.nodes[i].name).collect();
+                let first = names[0];
+                let message = if names.len() == 1 {
+                    format!("`{}` depends on itself", first)
+                } else {
+                    let names: Vec<String> = names.iter().map(|n| format!("`{}`", n)).collect();
+                    format!("dependency cycle between {}", names.join(", "))
+                };
+                Diagnostic::new(message).with_span(first)
+            })
+            .collect()
+    }
+}
+
+/// Every group of nodes that depend on each other through `edges`, where
+/// `edges[i]` lists the nodes `i` points at. Groups and their members are
+/// sorted by index so callers get a stable result.
+pub fn cycles(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; edges.len()],
+        lowlink: vec![0; edges.len()],
+        on_stack: vec![false; edges.len()],
+        stack: Vec::new(),
+        next: 0,
+        components: Vec::new(),
+    };
+    for i in 0..edges.len() {
+        if tarjan.index[i].is_none() {
+            tarjan.visit(i);
+        }
+    }
+    let mut components = tarjan.components;
+    components.retain(|c| c.len() > 1 || edges[c[0]].contains(&c[0]));
+    components.sort();
+    components
+}
+
+/// Tarjan's strongly connected components
+struct Tarjan<'g> {
+    edges: &'g [Vec<usize>],
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next);
+        self.lowlink[v] = self.next;
+        self.next += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for &w in &self.edges[v] {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                }
+                Some(index) if self.on_stack[w] => {
+                    self.lowlink[v] = self.lowlink[v].min(index);
+                }
+                Some(_) => {}
+            }
+        }
+        if Some(self.lowlink[v]) == self.index[v] {
+            let mut component = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            component.sort();
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::take_schema;
+
+    fn names<'i>(order: &[&QapiSchemaToken<'i>]) -> Vec<&'i str> {
+        order.iter().filter_map(|t| token_name(t)).collect()
+    }
+
+    #[test]
+    fn test_forward_references() {
+        let first = r#"
+{ 'command': 'thing-add', 'data': 'Thing', 'boxed': true }
+{ 'event': 'THING_CHANGED', 'data': 'Thing' }
+{ 'union': 'Thing', 'base': 'ThingBase', 'discriminator': 'kind',
+  'data': { 'a': 'ThingA' } }
+"#;
+        let second = r#"
+{ 'struct': 'ThingA', 'base': 'ThingBase', 'data': {} }
+{ 'struct': 'ThingBase', 'base': 'Root', 'data': {} }
+{ 'struct': 'Root', 'data': { 'kind': 'Kind' } }
+{ 'enum': 'Kind', 'data': [ 'a' ] }
+"#;
+        let schemas = vec![
+            take_schema(first).unwrap().1,
+            take_schema(second).unwrap().1,
+        ];
+        let graph = DependencyGraph::new(&schemas);
+        assert!(graph.cycles().is_empty());
+        assert_eq!(
+            names(&graph.order()),
+            vec![
+                "Root",
+                "ThingBase",
+                "ThingA",
+                "Kind",
+                "Thing",
+                "thing-add",
+                "THING_CHANGED"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let input = r#"
+{ 'struct': 'A', 'base': 'B', 'data': {} }
+{ 'struct': 'B', 'base': 'C', 'data': {} }
+{ 'struct': 'C', 'base': 'A', 'data': {} }
+{ 'struct': 'Selfish', 'base': 'Selfish', 'data': {} }
+{ 'struct': 'Fine', 'data': { 'a': 'A', 'fine': [ 'Fine' ] } }
+"#;
+        let schemas = vec![take_schema(input).unwrap().1];
+        let graph = DependencyGraph::new(&schemas);
+        let cycles: Vec<String> = graph.cycles().into_iter().map(|d| d.message).collect();
+        assert_eq!(
+            cycles,
+            vec![
+                "dependency cycle between `A`, `B`, `C`",
+                "`Selfish` depends on itself",
+            ]
+        );
+        assert_eq!(names(&graph.order()), vec!["Fine"]);
+    }
+}
@@ -0,0 +1,606 @@
+//! Parse QEMU's QAPI schema and generate bindings from it
+//!
+//! [`generate`] is meant to be called from a build script, so a crate can
+//! build its bindings against whichever schema is on the build host, including
+//! schemas QEMU never shipped:
+//!
+//! ```no_run
+//! use qapi_spec_parser::{Options, Output};
+//! # fn main() -> anyhow::Result<()> {
+//! let schema_root = std::path::Path::new("/usr/src/qemu");
+//! let options = Options::default()
+//!     .output(Output::Serde)
+//!     .doctest_crate("my_qapi");
+//! let code = qapi_spec_parser::generate(schema_root, &options)?;
+//! let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+//! std::fs::write(out_dir.join("qapi.rs"), code)?;
+//! println!("cargo:rerun-if-changed={}", schema_root.join("qapi").display());
+//! # Ok(())
+//! # }
+//! ```
+mod qapi_ir;
+use qapi_ir::*;
+
+mod backend;
+use backend::{Backend, QapiMacros, SchemaDocument, SerdeDerive};
+
+mod diagnostic;
+pub use diagnostic::{Diagnostic, Location, Severity};
+
+mod graph;
+pub use graph::DependencyGraph;
+
+mod validate;
+pub use validate::validate;
+
+mod parsers;
+pub use parsers::{
+    qapi_alternate::{take_alternate, QapiAlternate},
+    qapi_alternative::{take_alternative, QapiAlternative},
+    qapi_alternatives::{take_alternatives, QapiAlternatives},
+    qapi_branch::{take_branch, QapiBranch},
+    qapi_branches::{take_branches, QapiBranches},
+    qapi_command::{take_command, QapiCommand},
+    qapi_cond::{take_cond, QapiCond},
+    qapi_documentation::{extract_since_from_comment, QapiDocumentation, QapiSectionDocumentation},
+    qapi_enum::{take_enum, QapiEnum},
+    qapi_enum_value::{take_enum_value, QapiEnumValue},
+    qapi_event::{take_event, QapiEvent},
+    qapi_feature::{take_feature, QapiFeature},
+    qapi_features::{take_features, QapiFeatures},
+    qapi_include::{take_include, QapiInclude},
+    qapi_member::{take_member, QapiMember},
+    qapi_members::{take_members, take_members_or_ref, MembersOrRef, QapiMembers},
+    qapi_pragma::{take_pragma, QapiPragma},
+    qapi_schema::{take_schema, QapiSchema, QapiSchemaToken},
+    qapi_struct::{take_struct, QapiStruct},
+    qapi_type_ref::{take_type_ref, QapiTypeRef},
+    qapi_union::{take_union, QapiUnion},
+};
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// Parse a full schema file. Any input left over means an item failed to
+/// parse, which is reported with its location in `path`.
+fn parse_schema<'i>(path: &Path, input: &'i str) -> Result<QapiSchema<'i>, Diagnostic> {
+    let (rest, schema) = match take_schema(input) {
+        Ok(v) => v,
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => (e.input, QapiSchema(Vec::new())),
+        Err(nom::Err::Incomplete(_)) => unreachable! {"complete parsers never return Incomplete"},
+    };
+    if !rest.is_empty() {
+        let err = QapiSchema::furthest_error(rest);
+        let offset = input.len() - err.len();
+        let location = Location::from_offset(path, input, offset);
+        return Err(Diagnostic::new("failed to parse QAPI schema").with_location(location));
+    }
+    Ok(schema)
+}
+
+/// Load the root schema file and everything it includes. Each file is placed
+/// after the files it includes, matching the order QEMU expands includes in.
+fn read_schema(schema_file: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut sources = Vec::new();
+    let mut seen = HashSet::new();
+    read_schema_file(&normalize_path(schema_file), &mut sources, &mut seen)?;
+    Ok(sources)
+}
+
+fn read_schema_file(
+    schema_file: &Path,
+    sources: &mut Vec<(PathBuf, String)>,
+    seen: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    if !seen.insert(schema_file.to_path_buf()) {
+        // we have already seen this file, or are still reading its includes
+        return Ok(());
+    }
+
+    let content = std::fs::read_to_string(schema_file)
+        .with_context(|| format!("failed to read {}", schema_file.display()))?;
+    let schema_tokens = parse_schema(schema_file, &content)?;
+    let includes: Vec<&str> = schema_tokens
+        .into_iter()
+        .filter_map(|token| match token {
+            QapiSchemaToken::Include(filename) => Some(filename.0),
+            _ => None,
+        })
+        .collect();
+    for include in includes {
+        let include_path = normalize_path(&schema_file.parent().unwrap().join(include));
+        read_schema_file(&include_path, sources, seen)?;
+    }
+    sources.push((schema_file.to_path_buf(), content));
+    Ok(())
+}
+
+/// Resolve `.` and `..` components without touching the filesystem, so the
+/// same file included through different relative paths is only read once
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// What [`generate`] produces
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Rust types annotated for the `qapi_macros` crate, as in `projects/qapi`
+    #[default]
+    QapiMacros,
+    /// Plain Rust types deriving `serde` traits
+    Serde,
+    /// A JSON Schema document
+    JsonSchema,
+    /// An OpenAPI 3.1 document
+    OpenApi,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The root schema file, relative to the schema root
+    pub schema: PathBuf,
+    pub output: Output,
+    /// The crate the generated Rust code lives in, as seen from its doc-tests
+    pub doctest_crate: String,
+    /// Where the serde output takes its `Command` trait from, in place of
+    /// defining its own
+    pub command_trait: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            schema: PathBuf::from("qapi/qapi-schema.json"),
+            output: Output::default(),
+            doctest_crate: DEFAULT_DOCTEST_CRATE.to_string(),
+            command_trait: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn schema<P: Into<PathBuf>>(mut self, schema: P) -> Self {
+        self.schema = schema.into();
+        self
+    }
+
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn doctest_crate<S: ToString>(mut self, doctest_crate: S) -> Self {
+        self.doctest_crate = doctest_crate.to_string();
+        self
+    }
+
+    /// Implement the `Command` trait at `path`, such as `qmp::Command`, so
+    /// the commands can be executed by code that does not know the generated
+    /// crate. The trait must match the one the serde output defines.
+    pub fn command_trait<S: ToString>(mut self, path: S) -> Self {
+        self.command_trait = Some(path.to_string());
+        self
+    }
+
+    fn backend(&self) -> Box<dyn Backend> {
+        match self.output {
+            Output::QapiMacros => Box::new(QapiMacros::new(&self.doctest_crate)),
+            Output::Serde => {
+                let backend = SerdeDerive::new(&self.doctest_crate);
+                match &self.command_trait {
+                    Some(path) => Box::new(backend.command_trait(path)),
+                    None => Box::new(backend),
+                }
+            }
+            Output::JsonSchema => Box::new(SchemaDocument::json_schema()),
+            Output::OpenApi => Box::new(SchemaDocument::openapi()),
+        }
+    }
+}
+
+/// Parse the schema and everything it includes, then report every problem
+/// found in it. Only a schema that cannot be read or parsed is an `Err`.
+pub fn lint(schema_root: &Path, options: &Options) -> Result<Vec<Diagnostic>> {
+    let sources = read_schema(&schema_root.join(&options.schema))?;
+    let schemas = parse_all(&sources)?;
+    let graph = DependencyGraph::new(&schemas);
+    Ok(check(&sources, &schemas, &graph))
+}
+
+/// Generate bindings for the schema under `schema_root`, which is laid out
+/// like a QEMU source tree. Any error found in the schema fails generation;
+/// warnings are left to [`lint`].
+pub fn generate(schema_root: &Path, options: &Options) -> Result<String> {
+    let sources = read_schema(&schema_root.join(&options.schema))?;
+    let schemas = parse_all(&sources)?;
+    let graph = DependencyGraph::new(&schemas);
+    let errors = check(&sources, &schemas, &graph)
+        .into_iter()
+        .filter(|d| d.is_error())
+        .collect::<Vec<_>>();
+    if let Some(first) = errors.first() {
+        let count = errors.len();
+        return Err(anyhow! {"{} error(s) found in the QAPI schema, first:\n{}", count, first});
+    }
+    generate_with(
+        schema_root,
+        &sources,
+        &schemas,
+        &graph,
+        options.backend().as_mut(),
+    )
+}
+
+fn parse_all(sources: &[(PathBuf, String)]) -> Result<Vec<QapiSchema<'_>>, Diagnostic> {
+    sources
+        .iter()
+        .map(|(path, source)| parse_schema(path, source))
+        .collect()
+}
+
+/// Check the schema as a whole, with each diagnostic pointing into `sources`
+fn check(
+    sources: &[(PathBuf, String)],
+    schemas: &[QapiSchema],
+    graph: &DependencyGraph,
+) -> Vec<Diagnostic> {
+    validate(schemas)
+        .into_iter()
+        .chain(graph.cycles())
+        .map(|d| d.locate(sources))
+        .collect()
+}
+
+/// Turn every definition into IR. Each is processed after the ones it
+/// depends on, wherever in the included files they are declared.
+fn lower(
+    sources: &[(PathBuf, String)],
+    graph: &DependencyGraph,
+) -> Result<(HashMap<String, Struct>, HashMap<String, Enum>)> {
+    let locate = |d: Diagnostic| d.locate(sources);
+    let mut structs_lookup = HashMap::new();
+    let mut enums_lookup = HashMap::new();
+    for token in graph.order() {
+        match token.clone() {
+            QapiSchemaToken::Enum(v) => {
+                let processed = process_enum(v).map_err(locate)?;
+                enums_lookup.insert(processed.name.clone(), processed);
+            }
+            QapiSchemaToken::Alternate(v) => {
+                let processed =
+                    process_alternate(v, &structs_lookup, &enums_lookup).map_err(locate)?;
+                enums_lookup.insert(processed.name.clone(), processed);
+            }
+            QapiSchemaToken::Command(v) => {
+                let processed = process_command(v, &structs_lookup).map_err(locate)?;
+                structs_lookup.insert(processed.name.clone(), processed);
+            }
+            QapiSchemaToken::Event(v) => {
+                // Events can reference structs which we expand out (TODO: Is this correct behaviour?)
+                let processed = process_event(v, &structs_lookup).map_err(locate)?;
+                structs_lookup.insert(processed.name.clone(), processed);
+            }
+            QapiSchemaToken::Struct(v) => {
+                let processed = process_struct(v, &structs_lookup).map_err(locate)?;
+                structs_lookup.insert(processed.name.clone(), processed);
+            }
+            QapiSchemaToken::Union(v) => {
+                let (processed_struct, processed_enum) =
+                    process_union(v, &structs_lookup, &enums_lookup).map_err(locate)?;
+                enums_lookup.insert(processed_enum.name.clone(), processed_enum);
+                structs_lookup.insert(processed_struct.name.clone(), processed_struct);
+            }
+            _ => continue,
+        }
+    }
+    box_recursive_types(&mut structs_lookup, &mut enums_lookup);
+    Ok((structs_lookup, enums_lookup))
+}
+
+/// Hand every validated definition to `backend`. Paths given to the backend
+/// are relative to `qemu_src_root`.
+fn generate_with(
+    qemu_src_root: &Path,
+    sources: &[(PathBuf, String)],
+    schemas: &[QapiSchema],
+    graph: &DependencyGraph,
+    backend: &mut dyn Backend,
+) -> Result<String> {
+    let (structs_lookup, enums_lookup) = lower(sources, graph)?;
+
+    // With `enums_lookup` and `structs_lookup` in hand, we loop over all the
+    // paths and tokens once more and render everything in the same order as the
+    // QAPI spec expects. This might be helpful to anyone reading the generated
+    // code, but it doesn't matter at all during compliation.
+    // The source paths were normalized when read, the root has to match them
+    let qemu_src_root = normalize_path(qemu_src_root);
+    backend.begin()?;
+    for ((path, _), schema) in sources.iter().zip(schemas) {
+        let path = path.strip_prefix(&qemu_src_root).map_err(|_| {
+            anyhow! {"{} is not under {}", path.display(), qemu_src_root.display()}
+        })?;
+        backend.begin_file(path)?;
+        for token in schema.iter() {
+            match token {
+                QapiSchemaToken::Enum(v) => backend.add_enum(&enums_lookup[v.name])?,
+                QapiSchemaToken::Alternate(v) => backend.add_enum(&enums_lookup[v.name])?,
+                QapiSchemaToken::Command(v) => backend.add_struct(&structs_lookup[v.name])?,
+                QapiSchemaToken::Event(v) => backend.add_struct(&structs_lookup[v.name])?,
+                QapiSchemaToken::Struct(v) => backend.add_struct(&structs_lookup[v.name])?,
+                QapiSchemaToken::Union(v) => {
+                    backend.add_enum(&enums_lookup[&(v.name.to_owned() + "Branch")])?;
+                    backend.add_struct(&structs_lookup[v.name])?;
+                }
+                _ => continue,
+            }
+        }
+        backend.end_file(path)?;
+    }
+    backend.finish()
+}
+
+/// The serde snapshot has to compile, not just match
+#[cfg(test)]
+#[allow(dead_code, clippy::all)]
+mod serde_fixture {
+    include!("../fixtures/serde.rs");
+
+    #[test]
+    fn test_fixture_serde_messages() {
+        let shutdown = r#"{ "guest": true, "reason": "guest-shutdown" }"#;
+        let shutdown: ShutdownEvent = serde_json::from_str(shutdown).unwrap();
+        assert!(shutdown.guest);
+        assert_eq!(<StopEvent as Event>::NAME, "STOP");
+        assert_eq!(<Stop as Command>::NAME, "stop");
+        assert_eq!(serde_json::to_string(&Stop::new()).unwrap(), "{}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trimmed snapshot of QEMU's `qapi/` directory, laid out like a QEMU
+    /// source tree
+    fn fixture_root() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/qemu")
+    }
+
+    fn fixture_sources() -> Vec<(PathBuf, String)> {
+        read_schema(&fixture_root().join("qapi/qapi-schema.json")).unwrap()
+    }
+
+    fn definition<'a, 'i>(
+        token: &'a QapiSchemaToken<'i>,
+    ) -> Option<(&'i str, Option<&'a QapiDocumentation<'i>>)> {
+        match token {
+            QapiSchemaToken::Alternate(v) => Some((v.name, v.doc.as_ref())),
+            QapiSchemaToken::Command(v) => Some((v.name, v.doc.as_ref())),
+            QapiSchemaToken::Enum(v) => Some((v.name, v.doc.as_ref())),
+            QapiSchemaToken::Event(v) => Some((v.name, v.doc.as_ref())),
+            QapiSchemaToken::Struct(v) => Some((v.name, v.doc.as_ref())),
+            QapiSchemaToken::Union(v) => Some((v.name, v.doc.as_ref())),
+            _ => None,
+        }
+    }
+
+    fn find_doc<'a, 'i>(schemas: &'a [QapiSchema<'i>], name: &str) -> &'a QapiDocumentation<'i> {
+        schemas
+            .iter()
+            .flat_map(|s| s.iter())
+            .filter_map(definition)
+            .find(|(n, _)| *n == name)
+            .and_then(|(_, doc)| doc)
+            .unwrap_or_else(|| panic!("no documentation for `{}`", name))
+    }
+
+    #[test]
+    fn test_fixture_parses() {
+        let sources = fixture_sources();
+        let files: Vec<String> = sources
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                "pragma.json",
+                "error.json",
+                "common.json",
+                "sockets.json",
+                "run-state.json",
+                "block-core.json",
+                "machine.json",
+                "qom.json",
+                "control.json",
+                "qapi-schema.json",
+            ]
+        );
+        // `parse_schema` fails unless the whole file was consumed
+        let schemas = parse_all(&sources).unwrap();
+        let graph = DependencyGraph::new(&schemas);
+        let diagnostics: Vec<String> = validate(&schemas)
+            .into_iter()
+            .chain(graph.cycles())
+            .map(|d| d.locate(&sources).to_string())
+            .collect();
+        assert_eq!(diagnostics, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_fixture_item_counts() {
+        let sources = fixture_sources();
+        let schemas = parse_all(&sources).unwrap();
+        // alternate, command, enum, event, struct, union, include, pragma
+        let expected = [
+            ("pragma.json", [0, 0, 0, 0, 0, 0, 0, 2]),
+            ("error.json", [0, 0, 1, 0, 0, 0, 0, 0]),
+            ("common.json", [1, 0, 3, 0, 0, 0, 0, 0]),
+            ("sockets.json", [0, 0, 1, 0, 4, 1, 1, 0]),
+            ("run-state.json", [0, 3, 3, 3, 1, 0, 0, 0]),
+            ("block-core.json", [1, 4, 3, 1, 7, 1, 2, 0]),
+            ("machine.json", [0, 4, 1, 0, 1, 0, 1, 0]),
+            ("qom.json", [0, 4, 1, 0, 3, 1, 1, 0]),
+            ("control.json", [0, 3, 1, 0, 2, 0, 0, 0]),
+            ("qapi-schema.json", [0, 0, 0, 0, 0, 0, 9, 0]),
+        ];
+        for ((path, _), schema) in sources.iter().zip(&schemas) {
+            let mut counts = [0; 8];
+            for token in schema.iter() {
+                let kind = match token {
+                    QapiSchemaToken::Alternate(_) => 0,
+                    QapiSchemaToken::Command(_) => 1,
+                    QapiSchemaToken::Enum(_) => 2,
+                    QapiSchemaToken::Event(_) => 3,
+                    QapiSchemaToken::Struct(_) => 4,
+                    QapiSchemaToken::Union(_) => 5,
+                    QapiSchemaToken::Include(_) => 6,
+                    QapiSchemaToken::Pragma(_) => 7,
+                    _ => continue,
+                };
+                counts[kind] += 1;
+            }
+            let file = path.file_name().unwrap().to_string_lossy();
+            let (_, expected) = expected.iter().find(|(f, _)| *f == file).unwrap();
+            assert_eq!(&counts, expected, "item counts of {}", file);
+        }
+    }
+
+    #[test]
+    fn test_fixture_docs_attach_to_their_item() {
+        let sources = fixture_sources();
+        let schemas = parse_all(&sources).unwrap();
+        for token in schemas.iter().flat_map(|s| s.iter()) {
+            if let Some((name, doc)) = definition(token) {
+                let doc = doc.unwrap_or_else(|| panic!("`{}` has no documentation", name));
+                assert_eq!(doc.name, name);
+            }
+        }
+
+        // A member's text runs on across indented paragraphs
+        let doc = find_doc(&schemas, "ObjectPropertyInfo");
+        let fields: Vec<&str> = doc.fields.iter().map(|(name, _)| *name).collect();
+        assert_eq!(fields, vec!["name", "type", "description", "default-value"]);
+        let text = doc.fields[1].1.join("\n");
+        assert!(text.contains("2) A child type"), "{}", text);
+        assert!(doc.description.is_empty());
+
+        let doc = find_doc(&schemas, "SHUTDOWN");
+        assert_eq!(doc.since, Some("0.12"));
+        assert_eq!(doc.notes.len(), 1);
+        assert_eq!(doc.qmp_examples.len(), 1);
+        let doc = find_doc(&schemas, "qmp_capabilities");
+        assert_eq!(doc.notes.len(), 2);
+        assert_eq!(doc.since, Some("0.13"));
+        let doc = find_doc(&schemas, "BlockdevOptionsFile");
+        let features: Vec<&str> = doc.features.iter().map(|(name, _)| *name).collect();
+        assert_eq!(features, vec!["dynamic-auto-read-only", "unstable"]);
+        let doc = find_doc(&schemas, "object-add");
+        assert!(doc.errors.is_some());
+        assert!(doc.fields.is_empty());
+    }
+
+    /// Regenerate the snapshots with `UPDATE_SNAPSHOTS=1 cargo test`
+    fn check_snapshot(backend: &mut dyn Backend, file: &str) {
+        let sources = fixture_sources();
+        let schemas = parse_all(&sources).unwrap();
+        let graph = DependencyGraph::new(&schemas);
+        let generated =
+            generate_with(&fixture_root(), &sources, &schemas, &graph, backend).unwrap();
+        let snapshot = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join(file);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&snapshot, &generated).unwrap();
+        }
+        let expected = std::fs::read_to_string(&snapshot).unwrap();
+        assert!(
+            generated == expected,
+            "generated code differs from {}; rerun with UPDATE_SNAPSHOTS=1 to update it",
+            snapshot.display()
+        );
+    }
+
+    #[test]
+    fn test_fixture_snapshot() {
+        check_snapshot(&mut QapiMacros::default(), "lib.rs");
+    }
+
+    #[test]
+    fn test_fixture_serde_snapshot() {
+        check_snapshot(&mut SerdeDerive::default(), "serde.rs");
+    }
+
+    #[test]
+    fn test_generate() {
+        let options = Options::default()
+            .output(Output::Serde)
+            .doctest_crate("my_qapi");
+        let generated = generate(&fixture_root(), &options).unwrap();
+        let snapshot = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/serde.rs");
+        let expected = std::fs::read_to_string(snapshot).unwrap();
+        assert!(!generated.contains("# use qapi::*;"));
+        assert_eq!(
+            generated,
+            expected.replace("# use qapi::*;", "# use my_qapi::*;")
+        );
+
+        // A `./` prefixed root, relative to the crate directory tests run in
+        let relative = generate(Path::new("./fixtures/qemu"), &options).unwrap();
+        assert_eq!(relative, generated);
+
+        let options = options.schema("qapi/missing.json");
+        assert!(generate(&fixture_root(), &options).is_err());
+    }
+
+    #[test]
+    fn test_fixture_json_schema() {
+        fn refs<'v>(value: &'v serde_json::Value, out: &mut Vec<&'v str>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(r)) = map.get("$ref") {
+                        out.push(r);
+                    }
+                    map.values().for_each(|v| refs(v, out));
+                }
+                serde_json::Value::Array(values) => values.iter().for_each(|v| refs(v, out)),
+                _ => {}
+            }
+        }
+
+        let sources = fixture_sources();
+        let schemas = parse_all(&sources).unwrap();
+        let graph = DependencyGraph::new(&schemas);
+        let mut backend = SchemaDocument::json_schema();
+        let document =
+            generate_with(&fixture_root(), &sources, &schemas, &graph, &mut backend).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&document).unwrap();
+        let defs = document["$defs"].as_object().unwrap();
+        for token in schemas.iter().flat_map(|s| s.iter()) {
+            if let Some((name, _)) = definition(token) {
+                assert!(defs.contains_key(name), "no schema for `{}`", name);
+            }
+        }
+        let mut found = Vec::new();
+        refs(&document, &mut found);
+        for r in found {
+            let name = r.strip_prefix("#/$defs/").unwrap();
+            assert!(defs.contains_key(name), "dangling reference {}", r);
+        }
+        assert_eq!(document["oneOf"].as_array().unwrap().len(), 18);
+    }
+}
@@ -220,4 +220,131 @@ mod tests {
             "(CONFIG_OPTION1 || !(CONFIG_OPTION2 && CONFIG_OPTION3))"
         );
     }
+
+    /// xorshift64, so the property tests below need no extra dependency and
+    /// every run checks the same cases
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    const NAMES: [&str; 4] = ["CONFIG_A", "CONFIG_B", "CONFIG_LINUX", "TARGET_X86_64"];
+
+    fn arbitrary(rng: &mut Rng, depth: usize) -> QapiCond<'static> {
+        if depth == 0 || rng.below(3) == 0 {
+            return QapiCond::ConfigName(NAMES[rng.below(NAMES.len())]);
+        }
+        let kind = rng.below(3);
+        if kind == 0 {
+            return QapiCond::Not(Box::new(arbitrary(rng, depth - 1)));
+        }
+        // QAPI lists of conditions are never empty
+        let conds = (0..=rng.below(3))
+            .map(|_| arbitrary(rng, depth - 1))
+            .collect();
+        if kind == 1 {
+            QapiCond::All(conds)
+        } else {
+            QapiCond::Any(conds)
+        }
+    }
+
+    /// Render a condition the way it is written in a schema
+    fn to_schema(cond: &QapiCond) -> String {
+        let list = |conds: &[QapiCond]| {
+            let conds: Vec<String> = conds.iter().map(to_schema).collect();
+            format!("[ {} ]", conds.join(", "))
+        };
+        match cond {
+            QapiCond::All(conds) => format!("{{ 'all': {} }}", list(conds)),
+            QapiCond::Any(conds) => format!("{{ 'any': {} }}", list(conds)),
+            QapiCond::Not(cond) => format!("{{ 'not': {} }}", to_schema(cond)),
+            QapiCond::ConfigName(name) => format!("'{}'", name),
+        }
+    }
+
+    /// Read back the output of `Display`. A parenthesized list of one can be
+    /// either `all` or `any`, so it comes back as `all`.
+    fn parse_display(input: &str) -> (QapiCond<'_>, &str) {
+        if let Some(rest) = input.strip_prefix('!') {
+            let (cond, rest) = parse_display(rest);
+            return (QapiCond::Not(Box::new(cond)), rest);
+        }
+        if let Some(mut rest) = input.strip_prefix('(') {
+            let mut conds = Vec::new();
+            let mut any = false;
+            loop {
+                let (cond, after) = parse_display(rest);
+                conds.push(cond);
+                if let Some(after) = after.strip_prefix(" || ") {
+                    any = true;
+                    rest = after;
+                } else if let Some(after) = after.strip_prefix(" && ") {
+                    rest = after;
+                } else {
+                    let rest = after.strip_prefix(')').expect("unbalanced parentheses");
+                    let cond = if any {
+                        QapiCond::Any(conds)
+                    } else {
+                        QapiCond::All(conds)
+                    };
+                    return (cond, rest);
+                }
+            }
+        }
+        let end = input
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(input.len());
+        (QapiCond::ConfigName(&input[..end]), &input[end..])
+    }
+
+    fn eval(cond: &QapiCond, enabled: &[&str]) -> bool {
+        match cond {
+            QapiCond::All(conds) => conds.iter().all(|c| eval(c, enabled)),
+            QapiCond::Any(conds) => conds.iter().any(|c| eval(c, enabled)),
+            QapiCond::Not(cond) => !eval(cond, enabled),
+            QapiCond::ConfigName(name) => enabled.contains(name),
+        }
+    }
+
+    #[test]
+    fn test_schema_roundtrip_property() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let cond = arbitrary(&mut rng, 4);
+            let input = to_schema(&cond);
+            assert_eq!(QapiCond::parse(&input), Ok(("", cond)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_display_roundtrip_property() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let cond = arbitrary(&mut rng, 4);
+            let display = cond.to_string();
+            let (parsed, rest) = parse_display(&display);
+            assert_eq!(rest, "", "{}", display);
+            assert_eq!(parsed.to_string(), display);
+            for mask in 0..1 << NAMES.len() {
+                let enabled: Vec<&str> = (0..NAMES.len())
+                    .filter(|i| mask & (1 << i) != 0)
+                    .map(|i| NAMES[i])
+                    .collect();
+                assert_eq!(
+                    eval(&parsed, &enabled),
+                    eval(&cond, &enabled),
+                    "{} with {:?}",
+                    display,
+                    enabled
+                );
+            }
+        }
+    }
 }
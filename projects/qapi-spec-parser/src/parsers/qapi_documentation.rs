@@ -1,10 +1,9 @@
 // TODO: Refactor/comment/tests this code.
-// BUG: sometimes struct documentation will get consumed into an option doc
 use nom::branch::alt;
 use nom::bytes::complete::{is_a, tag, tag_no_case, take_until, take_while1};
-use nom::character::complete::{line_ending, multispace0, not_line_ending, space0};
+use nom::character::complete::{line_ending, multispace0, not_line_ending, space0, space1};
 use nom::character::is_space;
-use nom::combinator::{map, not, opt, recognize};
+use nom::combinator::{map, not, opt, recognize, verify};
 use nom::multi::{many0, many1};
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::IResult;
@@ -125,12 +124,45 @@ fn take_empty_line(input: &str) -> IResult<&str, &str> {
 }
 
 fn take_value(input: &str) -> IResult<&str, Vec<&str>> {
-    let (input, description) = recognize(tuple((
+    let (input, description) =
+        recognize(tuple((not_line_ending, line_ending, opt(take_member_text))))(input)?;
+    Ok((input, docstr_to_str(description)))
+}
+
+fn take_text_line(input: &str) -> IResult<&str, &str> {
+    verify(
+        recognize(delimited(
+            take_line_continuation,
+            not_line_ending,
+            line_ending,
+        )),
+        |line: &str| !trim_docstr(line).is_empty(),
+    )(input)
+}
+
+fn take_indented_line(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        tag("# "),
+        space1,
+        not(line_ending),
         not_line_ending,
         line_ending,
-        opt(take_multiline_text),
-    )))(input)?;
-    Ok((input, docstr_to_str(description)))
+    )))(input)
+}
+
+// The description of a member or feature continues on the lines directly
+// below it, and on indented paragraphs after empty lines. An unindented
+// paragraph after an empty line is not part of the member; it continues the
+// description of the definition itself.
+fn take_member_text(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        many0(take_text_line),
+        many0(tuple((
+            many1(take_empty_line),
+            take_indented_line,
+            many0(take_text_line),
+        ))),
+    )))(input)
 }
 
 // SUCCESS: on success, this is equivalent to the `take_line_start` parser
@@ -168,6 +200,7 @@ fn take_doc_text(input: &str) -> IResult<&str, &str> {
 }
 
 enum ParserToken<'i> {
+    Text(&'i str),
     Errors(&'i str),
     Since(&'i str),
     Returns(&'i str),
@@ -203,7 +236,10 @@ impl<'i> QapiDocumentation<'i> {
         let (input, _) = many0(take_empty_line)(input)?;
         let (input, description_str) = opt(take_multiline_text)(input)?;
         let (input, _) = many0(take_empty_line)(input)?;
-        let (input, fields) = many0(pair(take_name, take_value))(input)?;
+        let (input, fields) = many0(terminated(
+            pair(take_name, take_value),
+            many0(take_empty_line),
+        ))(input)?;
         let (input, _) = many0(take_empty_line)(input)?;
 
         ////
@@ -236,10 +272,14 @@ impl<'i> QapiDocumentation<'i> {
                         take_line_end,
                         many0(take_empty_line),
                     )),
-                    many1(pair(take_name, take_value)),
+                    many1(terminated(
+                        pair(take_name, take_value),
+                        many0(take_empty_line),
+                    )),
                 ),
                 |v| ParserToken::Features(v),
             ),
+            map(take_multiline_text, |v| ParserToken::Text(v)),
         )))(input)?;
 
         // UPSTREAMFIX: This is because `qga/qapi-schema.json` in the `@GuestNetworkRoute` doc block.
@@ -261,8 +301,10 @@ impl<'i> QapiDocumentation<'i> {
         let mut tables = vec![];
         let mut qmp_examples = vec![];
         let mut admonitions = vec![];
+        let mut body = vec![];
         for token in tokens {
             match token {
+                ParserToken::Text(v) => body.push(v),
                 ParserToken::Since(v) => since = Some(trim_docstr(v)),
                 ParserToken::Errors(v) => errors = Some(trim_docstr(v)),
                 ParserToken::Returns(v) => returns = Some(trim_docstr(v)),
@@ -275,11 +317,31 @@ impl<'i> QapiDocumentation<'i> {
             }
         }
         let name = trim_docstr(name);
-        let description = if let Some(d) = description_str {
+        let mut description = if let Some(d) = description_str {
             docstr_to_str(d)
         } else {
             Vec::new()
         };
+        // Paragraphs found after the members belong to the description
+        for text in body {
+            let mut lines = docstr_to_str(text);
+            while lines.last() == Some(&"") {
+                lines.pop();
+            }
+            while lines.first() == Some(&"") {
+                lines.remove(0);
+            }
+            if lines.is_empty() {
+                continue;
+            }
+            while description.last() == Some(&"") {
+                description.pop();
+            }
+            if !description.is_empty() {
+                description.push("");
+            }
+            description.extend(lines);
+        }
         Ok((
             input,
             Self {
@@ -683,4 +745,37 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_member_does_not_consume_description() {
+        let input = r#"##
+# @MigMode:
+#
+# @normal: the original form of migration.
+#
+# @cpr-reboot: The migrate command stops the VM.
+#
+#     This mode allows the user to quit QEMU.
+#
+# A mode only takes effect on the next migration.
+#
+# Since: 8.2
+##"#;
+        let (remaining, doc) = QapiDocumentation::parse(input).unwrap();
+        assert_eq!(remaining, "");
+        assert_eq!(doc.fields.len(), 2);
+        assert_eq!(
+            doc.fields[1].1,
+            vec![
+                "The migrate command stops the VM.",
+                "",
+                "This mode allows the user to quit QEMU.",
+            ]
+        );
+        assert_eq!(
+            doc.description,
+            vec!["A mode only takes effect on the next migration."]
+        );
+        assert_eq!(doc.since, Some("8.2"));
+    }
 }
@@ -19,11 +19,11 @@ enum ParserToken<'i> {
 
 #[derive(Debug, Clone)]
 pub struct QapiPragma<'i> {
-    doc_required: Option<&'i str>,
-    command_name_exceptions: Option<Vec<&'i str>>,
-    command_returns_exceptions: Option<Vec<&'i str>>,
-    documentation_exceptions: Option<Vec<&'i str>>,
-    member_name_exceptions: Option<Vec<&'i str>>,
+    pub doc_required: Option<&'i str>,
+    pub command_name_exceptions: Option<Vec<&'i str>>,
+    pub command_returns_exceptions: Option<Vec<&'i str>>,
+    pub documentation_exceptions: Option<Vec<&'i str>>,
+    pub member_name_exceptions: Option<Vec<&'i str>>,
 }
 
 impl<'i> QapiPragma<'i> {
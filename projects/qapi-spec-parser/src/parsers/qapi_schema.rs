@@ -1,6 +1,6 @@
-use super::qcomment;
+use super::{clean_lines, qcomment};
 use crate::{
-    QapiAlternate, QapiCommand, QapiEnum, QapiEvent, QapiInclude, QapiPragma,
+    QapiAlternate, QapiCommand, QapiDocumentation, QapiEnum, QapiEvent, QapiInclude, QapiPragma,
     QapiSectionDocumentation, QapiStruct, QapiUnion,
 };
 use nom::branch::alt;
@@ -54,6 +54,45 @@ impl<'i> QapiSchema<'i> {
             |tokens| Self(tokens),
         )(input)
     }
+
+    /// Find where parsing actually went wrong in `input`.
+    ///
+    /// `many0` stops at the first item it cannot parse and swallows the error,
+    /// leaving us with only the start of the broken item. Here every item
+    /// parser is retried on that remainder and we keep the error that made it
+    /// the furthest into the input, which is nearly always the offending key
+    /// or value rather than the opening `{`.
+    ///
+    /// Lists and dicts stop in front of the `,` preceding an element they
+    /// cannot parse, so a leading separator is skipped to land on the element.
+    pub fn furthest_error(input: &'i str) -> &'i str {
+        fn error_input<O>(result: IResult<&str, O>) -> Option<&str> {
+            match result {
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Some(e.input),
+                _ => None,
+            }
+        }
+        [
+            error_input(QapiAlternate::parse(input)),
+            error_input(QapiCommand::parse(input)),
+            error_input(QapiEnum::parse(input)),
+            error_input(QapiEvent::parse(input)),
+            error_input(QapiStruct::parse(input)),
+            error_input(QapiUnion::parse(input)),
+            error_input(QapiInclude::parse(input)),
+            error_input(QapiPragma::parse(input)),
+            error_input(QapiDocumentation::parse(input)),
+            error_input(QapiSectionDocumentation::parse(input)),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|rest| rest.len())
+        .map(|rest| match rest.strip_prefix(',') {
+            Some(rest) => clean_lines(rest).map(|(rest, _)| rest).unwrap_or(rest),
+            None => rest,
+        })
+        .unwrap_or(input)
+    }
 }
 
 impl<'i> Deref for QapiSchema<'i> {
@@ -78,3 +117,25 @@ impl<'i> IntoIterator for QapiSchema<'i> {
         self.0.into_iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_furthest_error() {
+        let input = r#"{ 'enum': 'Foo', 'data': [ 'a' ] }
+{ 'struct': 'Bar',
+  'data': { 'a': 'int' },
+  'bogus': 'key' }
+"#;
+        let (rest, schema) = QapiSchema::parse(input).unwrap();
+        assert!(schema.iter().any(|t| matches!(t, QapiSchemaToken::Enum(_))));
+        assert!(rest.starts_with("{ 'struct'"));
+        let err = QapiSchema::furthest_error(rest);
+        assert!(
+            err.starts_with("'bogus'"),
+            "unexpected error position: {err}"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use super::{qstring, take_dict, take_kv};
-use crate::{take_cond, take_features, take_members};
-use crate::{QapiCond, QapiDocumentation, QapiFeatures, QapiMembers};
+use crate::{take_cond, take_features, take_members, take_members_or_ref};
+use crate::{MembersOrRef, QapiCond, QapiDocumentation, QapiFeatures, QapiMembers};
 use nom::branch::alt;
 use nom::combinator::{map, opt};
 use nom::error::{Error, ErrorKind};
@@ -14,7 +14,7 @@ pub fn take_struct(input: &str) -> IResult<&str, QapiStruct<'_>> {
 pub struct QapiStruct<'i> {
     pub name: &'i str,
     pub data: QapiMembers<'i>,
-    pub base: Option<&'i str>,
+    pub base: Option<MembersOrRef<'i>>,
     pub r#if: Option<QapiCond<'i>>,
     pub features: Option<QapiFeatures<'i>>,
     pub doc: Option<QapiDocumentation<'i>>,
@@ -23,7 +23,7 @@ pub struct QapiStruct<'i> {
 impl<'i> QapiStruct<'i> {
     /// STRUCT = { 'struct': STRING,
     ///            'data': MEMBERS,
-    ///            '*base': STRING,
+    ///            '*base': ( MEMBERS | STRING ),
     ///            '*if': COND,
     ///            '*features': FEATURES }
     pub fn parse(input: &'i str) -> IResult<&'i str, Self> {
@@ -35,7 +35,7 @@ impl<'i> QapiStruct<'i> {
         let start = input;
         let (input, _) = take_dict(alt((
             map(take_kv("struct", qstring), |v| s.name = v),
-            map(take_kv("base", qstring), |v| s.base = Some(v)),
+            map(take_kv("base", take_members_or_ref), |v| s.base = Some(v)),
             map(take_cond, |v| s.r#if = Some(v)),
             map(take_features, |v| s.features = Some(v)),
             map(take_kv("data", take_members), |v| s.data = v),
@@ -51,12 +51,13 @@ impl<'i> QapiStruct<'i> {
 mod tests {
     use super::*;
 
-    const VALID_INPUTS: [&str; 6] = [
+    const VALID_INPUTS: [&str; 7] = [
         "{'struct':'SOMENAME','data':{'membername':'membertype'}}",
         "{ 'struct': 'SOMENAME', 'data': {'membername':{'if':'CONFIG_OPTION', 'type': ['sometype'], 'features': ['yes']}}}",
         "{'struct': 'SOMENAME', 'data':{'membername':'membertype'}, 'if':{'not':'CONFIG_VALUE'}}",
         "{'struct': 'SOMENAME', 'data':{'membername':'membertype'}, 'base':'SOMETHING'}",
         "{'struct': 'SOMENAME', 'data':{'membername':{'type':'membertype'}}}",
+        "{'struct': 'SOMENAME', 'data':{'membername':'membertype'}, 'base':{'*basemember':'basetype'}}",
         r#"##
 # @RbdEncryptionOptionsLUKS:
 #
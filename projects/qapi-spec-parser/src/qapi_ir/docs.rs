@@ -0,0 +1,301 @@
+/// Rendering QAPI documentation as rustdoc
+///
+/// Besides the description, a QAPI doc block has sections for what a command
+/// returns, which errors it raises, notes, cautions, tables and QMP examples.
+/// Each section becomes a rustdoc heading. A QMP example made only of JSON
+/// messages becomes a doc-test checking that the messages deserialize into the
+/// generated types; any other example is kept as plain text.
+use super::Metadata;
+use crate::parsers::qapi_documentation::docstr_to_string_keep_structure;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+/// The crate the generated code lives in unless told otherwise
+pub const DEFAULT_DOCTEST_CRATE: &str = "qapi";
+
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+pub struct DocSection {
+    pub heading: String,
+    pub body: String,
+}
+
+impl DocSection {
+    pub fn new<H: ToString>(heading: H, raw: &str) -> Self {
+        Self {
+            heading: heading.to_string(),
+            body: section_text(raw),
+        }
+    }
+
+    /// Sections such as `.. admonition:: Notes` carry their heading on the
+    /// first line
+    pub fn titled(raw: &str) -> Self {
+        let text = section_text(raw);
+        let (heading, body) = text.split_once('\n').unwrap_or((&text, ""));
+        Self {
+            heading: heading.trim().to_string(),
+            body: dedent(body),
+        }
+    }
+}
+
+/// Turn a raw doc section, still carrying its `#` comment markers, into text
+pub fn section_text(raw: &str) -> String {
+    dedent(&docstr_to_string_keep_structure(raw))
+}
+
+/// Remove the indentation shared by all lines after the first. The first line
+/// usually follows a `Key:` on the same line and has none of its own.
+fn dedent(text: &str) -> String {
+    let mut lines: Vec<&str> = text.lines().collect();
+    let skip = usize::from(lines.first().is_some_and(|l| !l.starts_with(' ')));
+    let indent = lines
+        .iter()
+        .skip(skip)
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    for line in lines.iter_mut().skip(skip) {
+        *line = line.get(indent..).unwrap_or("");
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// One JSON message of a QMP example, either sent to QEMU (`->`) or received
+/// from it (`<-`)
+#[derive(Debug)]
+struct QmpMessage {
+    sent: bool,
+    json: String,
+    value: serde_json::Value,
+}
+
+/// Split an example into its messages. Returns `None` when the example has
+/// anything other than messages made of valid JSON, such as prose or `...`
+/// standing in for elided output.
+fn qmp_messages(example: &str) -> Option<Vec<QmpMessage>> {
+    let mut messages: Vec<(bool, String)> = Vec::new();
+    for line in example.lines() {
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix("->") {
+            messages.push((true, rest.trim().to_string()));
+        } else if let Some(rest) = trimmed.strip_prefix("<-") {
+            messages.push((false, rest.trim().to_string()));
+        } else if let Some((_, json)) = messages.last_mut() {
+            json.push('\n');
+            json.push_str(line);
+        } else if !trimmed.is_empty() {
+            return None;
+        }
+    }
+    if messages.is_empty() {
+        return None;
+    }
+    messages
+        .into_iter()
+        .map(|(sent, json)| {
+            let json = dedent(&json);
+            let value = serde_json::from_str(&json).ok()?;
+            Some(QmpMessage { sent, json, value })
+        })
+        .collect()
+}
+
+/// The generated item whose docs are being rendered, so examples can be
+/// checked against it
+#[derive(Debug, Clone, Copy)]
+pub struct DocItem<'a> {
+    /// The QAPI name, as used by `execute` and `event` in QMP messages
+    pub name: &'a str,
+    pub rust_name: &'a str,
+    /// The Rust type a command returns, when it is not `()`
+    pub returns: Option<&'a str>,
+    /// The crate the generated code lives in, as seen from its doc-tests
+    pub krate: &'a str,
+}
+
+fn doctest(item: DocItem, messages: &[QmpMessage]) -> Option<String> {
+    let mut lines = vec![format!("# use {}::*;", item.krate)];
+    let mut checks = 0;
+    let mut executed = false;
+    for message in messages {
+        let hashes = if message.json.contains("\"#") {
+            "##"
+        } else {
+            "#"
+        };
+        lines.push(if message.sent {
+            "// -> sent to QEMU".to_string()
+        } else {
+            "// <- received from QEMU".to_string()
+        });
+        lines.push(format!(
+            "let message: serde_json::Value = serde_json::from_str(r{hashes}\"{}\"{hashes}).unwrap();",
+            message.json
+        ));
+        let value = &message.value;
+        let check = if value["execute"] == item.name {
+            executed = true;
+            value
+                .get("arguments")
+                .map(|_| ("arguments", item.rust_name))
+        } else if value["event"] == item.name {
+            value.get("data").map(|_| ("data", item.rust_name))
+        } else if executed && value.get("return").is_some() {
+            item.returns.map(|returns| ("return", returns))
+        } else {
+            None
+        };
+        if let Some((key, r#type)) = check {
+            lines.push(format!(
+                "let _: {} = serde_json::from_value(message[\"{}\"].clone()).unwrap();",
+                r#type, key
+            ));
+            checks += 1;
+        }
+    }
+    (checks > 0).then(|| lines.join("\n"))
+}
+
+fn render_example(example: &str, item: Option<DocItem>) -> String {
+    let (title, body) = match example.strip_prefix(":title:") {
+        Some(rest) => {
+            let (title, body) = rest.split_once('\n').unwrap_or((rest, ""));
+            (Some(title.trim()), dedent(body))
+        }
+        None => (None, example.to_string()),
+    };
+    let mut out = String::new();
+    if let Some(title) = title {
+        out.push_str(title);
+        out.push_str("\n\n");
+    }
+    let test = item.and_then(|item| doctest(item, &qmp_messages(&body)?));
+    match test {
+        Some(test) => out.push_str(&format!("```\n{}\n```", test)),
+        None => out.push_str(&format!("```text\n{}\n```", body)),
+    }
+    out
+}
+
+impl Metadata {
+    /// The full rustdoc text: description, then one heading per section
+    pub fn render_doc(&self, item: Option<DocItem>) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(doc) = &self.doc {
+            parts.push(doc.trim().to_string());
+        }
+        for section in &self.sections {
+            parts.push(format!("# {}", section.heading));
+            if !section.body.is_empty() {
+                parts.push(section.body.clone());
+            }
+        }
+        if !self.examples.is_empty() {
+            parts.push("# Examples".into());
+            for example in &self.examples {
+                parts.push(render_example(example, item));
+            }
+        }
+        parts.retain(|p| !p.is_empty());
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+
+    /// One `#[doc]` attribute per line, so the docs render as `///` comments
+    pub fn generate_doc(&self, item: Option<DocItem>) -> TokenStream {
+        let Some(doc) = self.render_doc(item) else {
+            return TokenStream::new();
+        };
+        let lines = doc.lines().map(|line| {
+            let line = if line.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", line)
+            };
+            quote! {
+                #[doc = #line]
+            }
+        });
+        quote! {
+            #(#lines)*
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_text() {
+        let raw = "\n#     - if the given @option doesn't exist\n#     - if it is busy\n";
+        assert_eq!(
+            section_text(raw),
+            "- if the given @option doesn't exist\n- if it is busy"
+        );
+        let raw =
+            "list of @CommandLineOptionInfo for all options (or for the\n#     given @option).\n";
+        assert_eq!(
+            section_text(raw),
+            "list of @CommandLineOptionInfo for all options (or for the\ngiven @option)."
+        );
+    }
+
+    #[test]
+    fn test_doctest_example() {
+        let example = r#"-> { "execute": "query-command-line-options",
+     "arguments": { "option": "option-rom" } }
+<- { "return": [ { "parameters": [], "option": "option-rom" } ] }"#;
+        let item = DocItem {
+            name: "query-command-line-options",
+            rust_name: "QueryCommandLineOptions",
+            returns: Some("Vec<CommandLineOptionInfo>"),
+            krate: DEFAULT_DOCTEST_CRATE,
+        };
+        assert_eq!(
+            render_example(example, Some(item)),
+            r##"```
+# use qapi::*;
+// -> sent to QEMU
+let message: serde_json::Value = serde_json::from_str(r#"{ "execute": "query-command-line-options",
+"arguments": { "option": "option-rom" } }"#).unwrap();
+let _: QueryCommandLineOptions = serde_json::from_value(message["arguments"].clone()).unwrap();
+// <- received from QEMU
+let message: serde_json::Value = serde_json::from_str(r#"{ "return": [ { "parameters": [], "option": "option-rom" } ] }"#).unwrap();
+let _: Vec<CommandLineOptionInfo> = serde_json::from_value(message["return"].clone()).unwrap();
+```"##
+        );
+    }
+
+    #[test]
+    fn test_text_example() {
+        let example = ":title: Elided output\n\n-> { \"execute\": \"query-things\" }\n<- { \"return\": [ ... ] }";
+        let item = DocItem {
+            name: "query-things",
+            rust_name: "QueryThings",
+            returns: None,
+            krate: DEFAULT_DOCTEST_CRATE,
+        };
+        assert_eq!(
+            render_example(example, Some(item)),
+            "Elided output\n\n```text\n-> { \"execute\": \"query-things\" }\n<- { \"return\": [ ... ] }\n```"
+        );
+    }
+
+    #[test]
+    fn test_render_doc() {
+        let meta = Metadata {
+            doc: Some("Query things.\n".into()),
+            sections: vec![
+                DocSection::new("Returns", "a list of things\n"),
+                DocSection::titled("Notes\n#\n#     Things are never empty.\n"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            meta.render_doc(None).unwrap(),
+            "Query things.\n\n# Returns\n\na list of things\n\n# Notes\n\nThings are never empty."
+        );
+    }
+}
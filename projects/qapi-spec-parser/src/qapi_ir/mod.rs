@@ -12,11 +12,20 @@ use quote::{format_ident, quote};
 mod process_qapi;
 pub use process_qapi::*;
 
+mod docs;
+pub use docs::{DocItem, DocSection, DEFAULT_DOCTEST_CRATE};
+
+mod recursive;
+pub use recursive::box_recursive_types;
+
 mod rust_enum;
 mod rust_struct;
 pub use rust_enum::*;
 pub use rust_struct::*;
 
+/// Prelude types a schema could define a type of the same name as
+const PRELUDE_TYPES: [&str; 5] = ["Box", "Option", "Result", "String", "Vec"];
+
 fn qapi_to_rust_type(qapi_type: &str) -> String {
     match qapi_type {
         "str" => "String".into(),
@@ -33,7 +42,11 @@ fn qapi_to_rust_type(qapi_type: &str) -> String {
         "size" => "u64".into(),
         "bool" => "bool".into(),
         "any" => "serde_json::Value".into(),
-        _ => qapi_type.to_pascal_case(),
+        _ => match qapi_type.to_pascal_case() {
+            // The generated code relies on the prelude, so don't shadow it
+            name if PRELUDE_TYPES.contains(&name.as_str()) => format!("Qapi{}", name),
+            name => name,
+        },
     }
 }
 
@@ -50,28 +63,39 @@ fn fix_leading_digit(input: &str) -> String {
     }
 }
 
-fn rustify_name(input: &str) -> String {
+pub(crate) fn rustify_name(input: &str) -> String {
     let input = input.to_pascal_case();
     fix_leading_digit(&input)
 }
 
-fn rustify_field_name(input: &str) -> String {
+pub(crate) fn rustify_field_name(input: &str) -> String {
     let input = input.to_snake_case();
     fix_leading_digit(&input)
 }
 
-fn rustify_type(input: &str) -> String {
+pub(crate) fn rustify_type(input: &str) -> String {
     let input = qapi_to_rust_type(input);
     fix_leading_digit(&input)
 }
 
-fn rustify_field(input: &str) -> String {
+pub(crate) fn rustify_field(input: &str) -> String {
     match input {
         "type" | "abstract" | "in" | "static" | "if" | "match" | "use" => format! {"r#{}", input},
         _ => rustify_field_name(input),
     }
 }
 
+/// Types every schema may use without defining them. Only `null` needs one;
+/// the other builtins map onto Rust primitives.
+pub fn generate_builtins() -> TokenStream {
+    let doc = " The QAPI builtin `null` type, which only ever holds JSON `null`";
+    quote! {
+        #[doc = #doc]
+        #[qapi(null)]
+        pub struct Null;
+    }
+}
+
 pub fn generate_attribute(attribute: &Attribute) -> TokenStream {
     let options = match attribute {
         Attribute::List(attributes) => attributes
@@ -99,6 +123,57 @@ pub fn generate_options(attribute: &Attribute) -> Vec<TokenStream> {
     }
 }
 
+/// A `#[cfg(...)]` for a `condition` attribute. The attribute holds the
+/// condition as `QapiCond` displays it: `A`, `!A`, `(A || B)` or `(A && B)`.
+pub fn generate_cfg(condition: &str) -> TokenStream {
+    let predicate = cfg_predicate(condition.trim());
+    quote! {
+        #[cfg(#predicate)]
+    }
+}
+
+fn cfg_predicate(condition: &str) -> TokenStream {
+    if let Some(operand) = condition.strip_prefix('!') {
+        let operand = cfg_predicate(operand);
+        return quote!(not(#operand));
+    }
+    let Some(inner) = condition
+        .strip_prefix('(')
+        .and_then(|c| c.strip_suffix(')'))
+    else {
+        let name = format_ident!("{}", condition);
+        return quote!(#name);
+    };
+
+    // Split on the operator between the outermost operands
+    let mut any = false;
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ' ' if depth == 0 && i >= start => {
+                any = inner[i..].starts_with(" || ");
+                operands.push(&inner[start..i]);
+                start = i + " || ".len();
+            }
+            _ => {}
+        }
+    }
+    operands.push(&inner[start..]);
+    let operands = operands
+        .into_iter()
+        .filter(|o| !o.is_empty())
+        .map(cfg_predicate);
+    if any {
+        quote!(any(#(#operands),*))
+    } else {
+        quote!(all(#(#operands),*))
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Attribute {
     Item { name: String, value: String },
@@ -140,5 +215,24 @@ impl Attribute {
 #[derive(Debug, Default, Eq, PartialEq, Clone)]
 pub struct Metadata {
     pub doc: Option<String>,
+    pub sections: Vec<DocSection>,
+    pub examples: Vec<String>,
     pub attributes: Vec<Attribute>,
 }
+
+impl Metadata {
+    /// Whether a unit attribute such as `flatten` is set
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|a| matches!(a, Attribute::Unit(n) if n == name))
+    }
+
+    /// The value of an attribute such as `name = "..."`
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find_map(|a| match a {
+            Attribute::Item { name: n, value } if n == name => Some(value.as_str()),
+            _ => None,
+        })
+    }
+}
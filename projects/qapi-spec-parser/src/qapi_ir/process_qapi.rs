@@ -7,11 +7,16 @@
 /// names of the various objects and fields. Those get set to attributes in the
 /// generated code.
 ///
-use super::{Attribute, Enum, EnumVariant, EnumVariantKind, Metadata, Struct, StructField};
-use crate::qapi_ir::{rustify_field_name, rustify_name};
+use super::docs::section_text;
+use super::{
+    Attribute, DocSection, Enum, EnumVariant, EnumVariantKind, Metadata, Struct, StructField,
+    StructKind,
+};
+use crate::qapi_ir::{rustify_field_name, rustify_name, rustify_type};
 use crate::{
-    extract_since_from_comment, MembersOrRef, QapiAlternate, QapiAlternative, QapiCommand,
-    QapiEnum, QapiEnumValue, QapiEvent, QapiMember, QapiStruct, QapiTypeRef, QapiUnion,
+    extract_since_from_comment, Diagnostic, MembersOrRef, QapiAlternate, QapiAlternative,
+    QapiCommand, QapiEnum, QapiEnumValue, QapiEvent, QapiMember, QapiStruct, QapiTypeRef,
+    QapiUnion,
 };
 use std::collections::HashMap;
 
@@ -34,11 +39,47 @@ macro_rules! add_docs {
     ($meta:expr, $docs:expr, $name:expr, $variants:expr) => {
         if let Some(doc) = $docs {
             if doc.name != $name {
-                todo! { "This is a validation error; Doc section name does not match" }
+                return Err(Diagnostic::new(format!(
+                    "documentation for `{}` is attached to `{}`",
+                    doc.name, $name
+                ))
+                .with_span(doc.name));
             }
             if doc.description.len() > 0 {
                 $meta.doc = Some(doc.description.join("\n"));
             }
+            if let Some(returns) = doc.returns {
+                $meta.sections.push(DocSection::new("Returns", returns));
+            }
+            if let Some(errors) = doc.errors {
+                $meta.sections.push(DocSection::new("Errors", errors));
+            }
+            for note in &doc.notes {
+                $meta.sections.push(DocSection::new("Note", note));
+            }
+            for caution in &doc.cautions {
+                $meta.sections.push(DocSection::new("Caution", caution));
+            }
+            for admonition in &doc.admonitions {
+                $meta.sections.push(DocSection::titled(admonition));
+            }
+            for table in &doc.tables {
+                $meta.sections.push(DocSection::titled(table));
+            }
+            if !doc.features.is_empty() {
+                let features: Vec<String> = doc
+                    .features
+                    .iter()
+                    .map(|(name, desc)| format!("- `{}`: {}", name, desc.join("\n  ").trim()))
+                    .collect();
+                $meta.sections.push(DocSection {
+                    heading: "Features".into(),
+                    body: features.join("\n"),
+                });
+            }
+            $meta
+                .examples
+                .extend(doc.qmp_examples.iter().map(|e| section_text(e)));
             if let Some(since) = doc.since {
                 $meta.attributes.push(Attribute::with_value("since", since));
             }
@@ -56,7 +97,11 @@ macro_rules! add_docs {
                         continue 'outer_loop;
                     }
                 }
-                todo! { "This is a validation error; Documented field does not exist" }
+                return Err(Diagnostic::new(format!(
+                    "documented member `{}` does not exist in `{}`",
+                    name, $name
+                ))
+                .with_span(name));
             }
         }
     };
@@ -88,35 +133,79 @@ fn process_type_ref(q: QapiTypeRef) -> (&str, bool) {
     }
 }
 
-fn process_alternative(q: QapiAlternative) -> EnumVariant {
+/// The kind of JSON value a type is written as. Alternates pick their variant
+/// by this, so no two alternatives of one alternate share a kind.
+fn json_kind(
+    r#type: &str,
+    structs_lookup: &HashMap<String, Struct>,
+    enums_lookup: &HashMap<String, Enum>,
+) -> Option<&'static str> {
+    match r#type {
+        "str" | "QType" => Some("string"),
+        "number" | "int" | "int8" | "int16" | "int32" | "int64" | "uint8" | "uint16" | "uint32"
+        | "uint64" | "size" => Some("number"),
+        "bool" => Some("boolean"),
+        "null" => Some("null"),
+        t if enums_lookup.contains_key(t) => Some("string"),
+        t if structs_lookup.contains_key(t) => Some("object"),
+        _ => None,
+    }
+}
+
+fn process_alternative(
+    q: QapiAlternative,
+    alternate: &str,
+    structs_lookup: &HashMap<String, Struct>,
+    enums_lookup: &HashMap<String, Enum>,
+) -> Result<EnumVariant, Diagnostic> {
     let (r#type, array) = process_type_ref(q.r#type);
+    let kind = if array {
+        Some("array")
+    } else {
+        json_kind(r#type, structs_lookup, enums_lookup)
+    };
+    let kind = kind.ok_or_else(|| {
+        Diagnostic::new(format!(
+            "alternative `{}` of `{}` has type `{}`, which cannot be told apart by its JSON kind",
+            q.name, alternate, r#type
+        ))
+        .with_span(q.name)
+    })?;
 
     let mut meta = Metadata::default();
     if let Some(attr) = name_attr(q.name) {
         meta.attributes.push(attr);
     }
+    meta.attributes.push(Attribute::with_value("kind", kind));
     if let Some(condition) = q.r#if {
         meta.attributes
             .push(Attribute::with_value("condition", condition));
     }
 
-    EnumVariant {
+    Ok(EnumVariant {
         name: q.name.into(),
         kind: EnumVariantKind::Tuple(r#type.into()),
         meta,
         array,
-    }
+        boxed: false,
+    })
 }
 
-pub fn process_alternate(q: QapiAlternate) -> Enum {
+/// Alternates are untagged on the wire; the variant is chosen by the kind of
+/// JSON value given, as recorded on each variant.
+pub fn process_alternate(
+    q: QapiAlternate,
+    structs_lookup: &HashMap<String, Struct>,
+    enums_lookup: &HashMap<String, Enum>,
+) -> Result<Enum, Diagnostic> {
     let mut variants = Vec::new();
     for v in q.data {
-        let variant = process_alternative(v);
+        let variant = process_alternative(v, q.name, structs_lookup, enums_lookup)?;
         variants.push(variant);
     }
 
     let mut meta = Metadata::default();
-    //meta.attributes.push(Attribute::new("Alternate"));
+    meta.attributes.push(Attribute::new("alternate"));
     if let Some(attr) = name_attr(q.name) {
         meta.attributes.push(attr);
     }
@@ -126,11 +215,11 @@ pub fn process_alternate(q: QapiAlternate) -> Enum {
     }
     add_docs! {meta, q.doc, q.name, &mut variants};
 
-    Enum {
+    Ok(Enum {
         name: q.name.into(),
         variants,
         meta,
-    }
+    })
 }
 
 fn process_enum_value(q: QapiEnumValue) -> EnumVariant {
@@ -149,10 +238,11 @@ fn process_enum_value(q: QapiEnumValue) -> EnumVariant {
         kind: EnumVariantKind::Unit,
         meta,
         array: false,
+        boxed: false,
     }
 }
 
-pub fn process_enum(q: QapiEnum) -> Enum {
+pub fn process_enum(q: QapiEnum) -> Result<Enum, Diagnostic> {
     let mut variants = Vec::new();
     for v in q.data {
         let variant = process_enum_value(v);
@@ -164,6 +254,10 @@ pub fn process_enum(q: QapiEnum) -> Enum {
     if let Some(attr) = name_attr(q.name) {
         meta.attributes.push(attr);
     }
+    if let Some(prefix) = q.prefix {
+        meta.attributes
+            .push(Attribute::with_value("prefix", prefix));
+    }
     if let Some(condition) = q.r#if {
         meta.attributes
             .push(Attribute::with_value("condition", condition));
@@ -171,11 +265,11 @@ pub fn process_enum(q: QapiEnum) -> Enum {
     add_feat! {meta, q.r#features};
     add_docs! {meta, q.doc, q.name, &mut variants};
 
-    Enum {
+    Ok(Enum {
         name: q.name.into(),
         variants,
         meta,
-    }
+    })
 }
 
 pub fn process_member(q: QapiMember) -> StructField {
@@ -196,18 +290,25 @@ pub fn process_member(q: QapiMember) -> StructField {
         r#type: r#type.into(),
         optional: q.optional,
         array,
+        boxed: false,
     }
 }
 
-pub fn process_struct(q: QapiStruct, structs_lookup: &HashMap<String, Struct>) -> Struct {
-    let mut fields = Vec::new();
-    if let Some(base) = q.base {
-        let struct_ref = structs_lookup.get(base.into()).expect(&format!(
-            "base struct not found for {} with base {}",
-            q.name, base
-        ));
-        fields.extend(struct_ref.fields.clone());
-    }
+pub fn process_struct(
+    q: QapiStruct,
+    structs_lookup: &HashMap<String, Struct>,
+) -> Result<Struct, Diagnostic> {
+    let mut fields = match q.base {
+        Some(MembersOrRef::Ref(base)) => {
+            let struct_ref = structs_lookup.get(base).ok_or_else(|| {
+                Diagnostic::new(format!("base struct `{}` of `{}` not found", base, q.name))
+                    .with_span(base)
+            })?;
+            struct_ref.fields.clone()
+        }
+        Some(base) => process_members_or_ref(base, structs_lookup)?,
+        None => Vec::new(),
+    };
     for member in q.data {
         let field = process_member(member);
         fields.push(field);
@@ -223,65 +324,94 @@ pub fn process_struct(q: QapiStruct, structs_lookup: &HashMap<String, Struct>) -
     add_feat! {meta, q.r#features};
     add_docs! {meta, q.doc, q.name, &mut fields};
 
-    Struct {
+    Ok(Struct {
         name: q.name.into(),
         fields,
         meta,
-    }
+        kind: StructKind::Type,
+    })
 }
 
+/// A flat union becomes a struct holding the base members next to a flattened
+/// `XBranch` enum. The discriminator member moves out of the struct and onto
+/// the enum as its tag, so the discriminator value always selects the variant
+/// when serialized. There is one variant per discriminator enum value, in enum
+/// order; values without a branch map to empty (unit) variants.
 pub fn process_union(
     q: QapiUnion,
     structs_lookup: &HashMap<String, Struct>,
     enums_lookup: &HashMap<String, Enum>,
-) -> (Struct, Enum) {
-    let mut discriminator_opt = None;
-    let mut fields = process_members_or_ref(q.base, structs_lookup);
-    for field in &mut fields {
-        if field.name == q.discriminator {
-            field.meta.attributes.push(Attribute::new("discriminator"));
-            discriminator_opt = Some(field.r#type.clone());
-        }
-    }
-    let discriminator = discriminator_opt.expect("discriminator field not found");
-    let _base_enum = enums_lookup.get(&discriminator).expect(&format!(
-        "{} could not find a base enum named {}",
-        q.name, discriminator
-    ));
+) -> Result<(Struct, Enum), Diagnostic> {
+    let mut fields = process_members_or_ref(q.base, structs_lookup)?;
+    let discriminator = fields
+        .iter()
+        .find(|field| field.name == q.discriminator)
+        .map(|field| field.r#type.clone())
+        .ok_or_else(|| {
+            Diagnostic::new(format!(
+                "discriminator `{}` of `{}` is not a base member",
+                q.discriminator, q.name
+            ))
+            .with_span(q.discriminator)
+        })?;
+    let discriminator_enum = enums_lookup.get(&discriminator).ok_or_else(|| {
+        Diagnostic::new(format!(
+            "discriminator `{}` of `{}` has type `{}`, which is not an enum",
+            q.discriminator, q.name, discriminator
+        ))
+        .with_span(q.discriminator)
+    })?;
 
+    let mut branches = q.data.0;
     let mut variants = Vec::new();
-    for branch in q.data {
-        let (r#type, array) = process_type_ref(branch.r#type);
-        assert!(array == false);
-        let mut meta = Metadata::default();
-        if let Some(attr) = name_attr(branch.name) {
-            meta.attributes.push(attr);
-        }
-        if let Some(condition) = branch.r#if {
-            meta.attributes
-                .push(Attribute::with_value("condition", condition));
-        }
+    for value in &discriminator_enum.variants {
+        let mut meta = value.meta.clone();
+        let kind = match branches.iter().position(|b| b.name == value.name) {
+            Some(i) => {
+                let branch = branches.remove(i);
+                let (r#type, array) = process_type_ref(branch.r#type);
+                if array {
+                    return Err(Diagnostic::new(format!(
+                        "branch `{}` of `{}` cannot be an array",
+                        branch.name, q.name
+                    ))
+                    .with_span(branch.name));
+                }
+                if let Some(condition) = branch.r#if {
+                    let condition = Attribute::with_value("condition", condition);
+                    if !meta.attributes.contains(&condition) {
+                        meta.attributes.push(condition);
+                    }
+                }
+                EnumVariantKind::Tuple(r#type.into())
+            }
+            None => EnumVariantKind::Unit,
+        };
         variants.push(EnumVariant {
-            name: branch.name.into(),
-            kind: EnumVariantKind::Tuple(r#type.into()),
+            name: value.name.clone(),
+            kind,
             meta,
             array: false,
+            boxed: false,
         });
     }
-    let e = Enum {
-        name: q.name.to_owned() + "Branch",
-        variants,
-        meta: Metadata::default(),
-    };
+    if let Some(branch) = branches.first() {
+        return Err(Diagnostic::new(format!(
+            "branch `{}` of `{}` is not a value of `{}`",
+            branch.name, q.name, discriminator
+        ))
+        .with_span(branch.name));
+    }
 
     let mut meta = Metadata::default();
-    meta.attributes.push(Attribute::new("union"));
+    meta.attributes.push(Attribute::new("flatten"));
     fields.push(StructField {
         name: "u".into(),
         meta,
         r#type: q.name.to_owned() + "Branch",
-        optional: true,
+        optional: false,
         array: false,
+        boxed: false,
     });
     let mut meta = Metadata::default();
     if let Some(attr) = name_attr(q.name) {
@@ -293,18 +423,38 @@ pub fn process_union(
     }
     add_feat! {meta, q.r#features};
     add_docs! {meta, q.doc, q.name, &mut fields};
+
+    // The discriminator is carried by the branch enum, along with its docs
+    let i = fields
+        .iter()
+        .position(|field| field.name == q.discriminator)
+        .unwrap();
+    let discriminator_field = fields.remove(i);
+    let mut enum_meta = Metadata {
+        doc: discriminator_field.meta.doc,
+        ..Default::default()
+    };
+    enum_meta
+        .attributes
+        .push(Attribute::with_value("discriminator", q.discriminator));
+    let e = Enum {
+        name: q.name.to_owned() + "Branch",
+        variants,
+        meta: enum_meta,
+    };
     let s = Struct {
         name: q.name.into(),
         fields,
         meta,
+        kind: StructKind::Type,
     };
-    (s, e)
+    Ok((s, e))
 }
 
 fn process_members_or_ref(
     q: MembersOrRef,
     structs_lookup: &HashMap<String, Struct>,
-) -> Vec<StructField> {
+) -> Result<Vec<StructField>, Diagnostic> {
     let mut fields = Vec::new();
     match q {
         MembersOrRef::Unset => unreachable! {"this should have failed the parser"},
@@ -326,24 +476,28 @@ fn process_members_or_ref(
                     meta,
                     optional: field.optional,
                     array,
+                    boxed: false,
                 };
                 fields.push(field);
             }
         }
         MembersOrRef::Ref(v) => {
-            let struct_ref = structs_lookup
-                .get(v)
-                .expect(&format!("failed to lookup base ref for {}", v));
+            let struct_ref = structs_lookup.get(v).ok_or_else(|| {
+                Diagnostic::new(format!("referenced struct `{}` not found", v)).with_span(v)
+            })?;
             fields.extend(struct_ref.fields.clone());
         }
     }
-    fields
+    Ok(fields)
 }
 
-pub fn process_event(q: QapiEvent, structs_lookup: &HashMap<String, Struct>) -> Struct {
+pub fn process_event(
+    q: QapiEvent,
+    structs_lookup: &HashMap<String, Struct>,
+) -> Result<Struct, Diagnostic> {
     let mut fields = Vec::new();
     if let Some(data) = q.data {
-        fields.extend(process_members_or_ref(data, structs_lookup));
+        fields.extend(process_members_or_ref(data, structs_lookup)?);
     }
     let mut meta = Metadata::default();
     //meta.attributes.push(Attribute::new("Event"));
@@ -357,14 +511,25 @@ pub fn process_event(q: QapiEvent, structs_lookup: &HashMap<String, Struct>) ->
     add_feat! {meta, q.r#features};
     add_docs! {meta, q.doc, q.name, &mut fields};
 
-    Struct {
+    Ok(Struct {
         name: q.name.into(),
         fields,
         meta,
-    }
+        kind: StructKind::Event,
+    })
 }
 
-fn command_process_members_or_ref(q: MembersOrRef) -> Vec<StructField> {
+/// Arguments given as a named type are carried as a single flattened field so
+/// the wire format stays the members of that type. `boxed` commands may name a
+/// union as well as a struct; their argument is kept behind a `Box`, as QEMU
+/// passes it by pointer. When every member of the type is optional the command
+/// may be sent without arguments, so the field becomes optional too.
+fn command_process_members_or_ref(
+    q: MembersOrRef,
+    command: &str,
+    boxed: bool,
+    structs_lookup: &HashMap<String, Struct>,
+) -> Result<Vec<StructField>, Diagnostic> {
     let mut fields = Vec::new();
     match q {
         MembersOrRef::Unset => unreachable! {"this should have failed the parser"},
@@ -386,30 +551,69 @@ fn command_process_members_or_ref(q: MembersOrRef) -> Vec<StructField> {
                     meta,
                     optional: field.optional,
                     array,
+                    boxed: false,
                 };
                 fields.push(field);
             }
         }
         MembersOrRef::Ref(v) => {
+            let struct_ref = structs_lookup.get(v).ok_or_else(|| {
+                Diagnostic::new(format!("argument type `{}` of `{}` not found", v, command))
+                    .with_span(v)
+            })?;
             let mut meta = Metadata::default();
             meta.attributes.push(Attribute::new("flatten"));
             let field = StructField {
                 name: "data".into(),
                 r#type: v.into(),
                 meta,
-                optional: false,
+                optional: struct_ref.fields.iter().all(|f| f.optional),
                 array: false,
+                boxed,
             };
             fields.push(field);
         }
     }
-    fields
+    Ok(fields)
 }
 
-pub fn process_command(q: QapiCommand) -> Struct {
+/// The value a command replies with, if any
+fn command_returns(returns: Option<QapiTypeRef>) -> Option<StructField> {
+    returns.map(|returns| {
+        let (r#type, array) = process_type_ref(returns);
+        StructField {
+            name: "return".into(),
+            meta: Metadata::default(),
+            r#type: r#type.into(),
+            optional: false,
+            array,
+            boxed: false,
+        }
+    })
+}
+
+/// The Rust type a command replies with, as used by the `returns` attribute
+fn command_returns_type(returns: Option<&StructField>) -> String {
+    match returns {
+        Some(returns) if returns.array => format! {"Vec<{}>", rustify_type(&returns.r#type)},
+        Some(returns) => rustify_type(&returns.r#type),
+        None => "()".into(),
+    }
+}
+
+pub fn process_command(
+    q: QapiCommand,
+    structs_lookup: &HashMap<String, Struct>,
+) -> Result<Struct, Diagnostic> {
+    let boxed = q.boxed.map(|b| b.parse().unwrap()).unwrap_or(false);
     let mut fields = Vec::new();
     if let Some(data) = q.data {
-        fields.extend(command_process_members_or_ref(data));
+        fields.extend(command_process_members_or_ref(
+            data,
+            q.name,
+            boxed,
+            structs_lookup,
+        )?);
     }
     let mut meta = Metadata::default();
     //meta.attributes.push(Attribute::new("Command"));
@@ -422,17 +626,13 @@ pub fn process_command(q: QapiCommand) -> Struct {
     }
     add_feat! {meta, q.r#features};
     add_docs! {meta, q.doc, q.name, &mut fields};
-    if let Some(returns) = q.returns {
-        let (r#type, array) = process_type_ref(returns);
-        let r#type = if array {
-            format! {"Vec<{}>", r#type}
-        } else {
-            r#type.into()
-        };
-        meta.attributes
-            .push(Attribute::with_value("returns", r#type));
-    } else {
-        meta.attributes.push(Attribute::with_value("returns", "()"));
+    let returns = command_returns(q.returns);
+    meta.attributes.push(Attribute::with_value(
+        "returns",
+        command_returns_type(returns.as_ref()),
+    ));
+    if boxed {
+        meta.attributes.push(Attribute::new("boxed"));
     }
     if let Some(b) = q.allow_oob {
         if b.parse().unwrap() {
@@ -449,10 +649,336 @@ pub fn process_command(q: QapiCommand) -> Struct {
             meta.attributes.push(Attribute::new("no_success_response"));
         }
     }
+    if let Some(b) = q.gen {
+        if !b.parse::<bool>().unwrap() {
+            meta.attributes.push(Attribute::new("no_gen"));
+        }
+    }
+    if let Some(b) = q.coroutine {
+        if b.parse().unwrap() {
+            meta.attributes.push(Attribute::new("coroutine"));
+        }
+    }
 
-    Struct {
+    Ok(Struct {
         name: q.name.into(),
         fields,
         meta,
+        kind: StructKind::Command(returns),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qapi_ir::box_recursive_types;
+    use crate::{take_schema, DependencyGraph, QapiSchemaToken};
+
+    /// The structs and enums of a processed schema, by name
+    type Processed = (HashMap<String, Struct>, HashMap<String, Enum>);
+
+    /// Process a whole schema the way `main` does, in dependency order
+    fn process(input: &str) -> Result<Processed, Diagnostic> {
+        let schemas = vec![take_schema(input).unwrap().1];
+        let mut structs_lookup = HashMap::new();
+        let mut enums_lookup = HashMap::new();
+        for token in DependencyGraph::new(&schemas).order() {
+            match token.clone() {
+                QapiSchemaToken::Enum(v) => {
+                    let e = process_enum(v)?;
+                    enums_lookup.insert(e.name.clone(), e);
+                }
+                QapiSchemaToken::Alternate(v) => {
+                    let e = process_alternate(v, &structs_lookup, &enums_lookup)?;
+                    enums_lookup.insert(e.name.clone(), e);
+                }
+                QapiSchemaToken::Struct(v) => {
+                    let s = process_struct(v, &structs_lookup)?;
+                    structs_lookup.insert(s.name.clone(), s);
+                }
+                QapiSchemaToken::Union(v) => {
+                    let (s, e) = process_union(v, &structs_lookup, &enums_lookup)?;
+                    enums_lookup.insert(e.name.clone(), e);
+                    structs_lookup.insert(s.name.clone(), s);
+                }
+                QapiSchemaToken::Command(v) => {
+                    let s = process_command(v, &structs_lookup)?;
+                    structs_lookup.insert(s.name.clone(), s);
+                }
+                _ => {}
+            }
+        }
+        box_recursive_types(&mut structs_lookup, &mut enums_lookup);
+        Ok((structs_lookup, enums_lookup))
+    }
+
+    fn returns(s: &Struct) -> &str {
+        s.meta
+            .attributes
+            .iter()
+            .find_map(|a| match a {
+                Attribute::Item { name, value } if name == "returns" => Some(value.as_str()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_command_boxed() {
+        let input = r#"
+{ 'struct': 'Opts', 'data': { 'id': 'str', '*size': 'size' } }
+{ 'command': 'thing-add', 'data': 'Opts', 'boxed': true }
+"#;
+        let (structs, _) = process(input).unwrap();
+        let s = &structs["thing-add"];
+        assert!(s.meta.attributes.contains(&Attribute::new("boxed")));
+        assert_eq!(returns(s), "()");
+        assert_eq!(s.fields.len(), 1);
+        let data = &s.fields[0];
+        assert_eq!((data.name.as_str(), data.r#type.as_str()), ("data", "Opts"));
+        assert!(data.boxed);
+        assert!(!data.optional);
+        assert!(data.meta.attributes.contains(&Attribute::new("flatten")));
+    }
+
+    #[test]
+    fn test_command_optional_arguments() {
+        let input = r#"
+{ 'struct': 'Filter', 'data': { '*id': 'str' } }
+{ 'command': 'query-things', 'data': 'Filter', 'returns': [ 'ThingInfo' ] }
+"#;
+        let (structs, _) = process(input).unwrap();
+        let s = &structs["query-things"];
+        assert!(!s.meta.attributes.contains(&Attribute::new("boxed")));
+        assert_eq!(returns(s), "Vec<ThingInfo>");
+        assert!(s.fields[0].optional);
+        assert!(!s.fields[0].boxed);
+    }
+
+    #[test]
+    fn test_command_returns_builtin() {
+        let input = r#"
+{ 'command': 'human-monitor-command', 'data': { 'command-line': 'str' },
+  'returns': 'str' }
+{ 'command': 'qom-get', 'data': { 'path': 'str', 'property': 'str' },
+  'returns': 'any' }
+"#;
+        let (structs, _) = process(input).unwrap();
+        assert_eq!(returns(&structs["human-monitor-command"]), "String");
+        assert_eq!(returns(&structs["qom-get"]), "serde_json::Value");
+    }
+
+    #[test]
+    fn test_command_flags() {
+        let input = r#"
+{ 'command': 'device_add', 'data': { 'driver': 'str' }, 'gen': false }
+{ 'command': 'guest-shutdown', 'success-response': false }
+{ 'command': 'block_resize', 'data': { 'size': 'int' }, 'coroutine': true,
+  'allow-preconfig': true }
+"#;
+        let (structs, _) = process(input).unwrap();
+        let flags = |name: &str| -> Vec<&str> {
+            structs[name]
+                .meta
+                .attributes
+                .iter()
+                .filter_map(|a| match a {
+                    Attribute::Unit(flag) => Some(flag.as_str()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(flags("device_add"), vec!["no_gen"]);
+        assert_eq!(flags("guest-shutdown"), vec!["no_success_response"]);
+        assert_eq!(flags("block_resize"), vec!["allow_preconfig", "coroutine"]);
+    }
+
+    #[test]
+    fn test_command_unknown_argument_type() {
+        let input = "{ 'command': 'thing-add', 'data': 'Missing' }";
+        let err = process(input).unwrap_err();
+        assert_eq!(
+            err.message,
+            "argument type `Missing` of `thing-add` not found"
+        );
+    }
+
+    #[test]
+    fn test_struct_inline_base() {
+        let input = r#"
+{ 'struct': 'Opts', 'base': { 'id': 'str' },
+  'data': { '*x-debug': { 'type': 'bool',
+                          'features': [ { 'name': 'unstable', 'if': 'CONFIG_DEBUG' } ] } } }
+"#;
+        let (structs, _) = process(input).unwrap();
+        let fields = &structs["Opts"].fields;
+        assert_eq!(fields[0].name, "id");
+        assert_eq!(fields[1].name, "x-debug");
+        assert!(fields[1]
+            .meta
+            .attributes
+            .contains(&Attribute::with_values(vec![
+                ("feature", Some("unstable")),
+                ("if", Some("CONFIG_DEBUG")),
+            ])));
+    }
+
+    #[test]
+    fn test_enum_prefix() {
+        let input = "{ 'enum': 'BlkdebugEvent', 'prefix': 'BLKDBG', 'data': [ 'l1_update' ] }";
+        let (_, enums) = process(input).unwrap();
+        assert_eq!(
+            enums["BlkdebugEvent"].meta.attribute("prefix"),
+            Some("BLKDBG")
+        );
+    }
+
+    #[test]
+    fn test_flat_union() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a', { 'name': 'b', 'if': 'CONFIG_B' }, 'c' ] }
+{ 'struct': 'Base', 'data': { 'kind': 'Kind', '*id': 'str' } }
+{ 'struct': 'A', 'data': { 'size': 'int' } }
+{ 'union': 'Thing', 'base': 'Base', 'discriminator': 'kind',
+  'data': { 'b': { 'type': 'A', 'if': 'CONFIG_B' }, 'a': 'A' } }
+"#;
+        let (structs, enums) = process(input).unwrap();
+        let s = &structs["Thing"];
+        let fields: Vec<&str> = s.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(fields, vec!["id", "u"]);
+        let u = &s.fields[1];
+        assert_eq!(u.r#type, "ThingBranch");
+        assert!(!u.optional);
+        assert!(u.meta.attributes.contains(&Attribute::new("flatten")));
+
+        let e = &enums["ThingBranch"];
+        assert!(e
+            .meta
+            .attributes
+            .contains(&Attribute::with_value("discriminator", "kind")));
+        let variants: Vec<(&str, &EnumVariantKind)> = e
+            .variants
+            .iter()
+            .map(|v| (v.name.as_str(), &v.kind))
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("a", &EnumVariantKind::Tuple("A".into())),
+                ("b", &EnumVariantKind::Tuple("A".into())),
+                ("c", &EnumVariantKind::Unit),
+            ]
+        );
+        let condition = Attribute::with_value("condition", "CONFIG_B");
+        let b = &e.variants[1];
+        assert_eq!(
+            b.meta
+                .attributes
+                .iter()
+                .filter(|a| **a == condition)
+                .count(),
+            1
+        );
+        // The base struct itself keeps its discriminator member
+        assert_eq!(structs["Base"].fields.len(), 2);
+    }
+
+    #[test]
+    fn test_flat_union_unknown_branch() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a' ] }
+{ 'struct': 'A', 'data': {} }
+{ 'union': 'Thing', 'base': { 'kind': 'Kind' }, 'discriminator': 'kind',
+  'data': { 'a': 'A', 'z': 'A' } }
+"#;
+        let err = process(input).unwrap_err();
+        assert_eq!(
+            err.message,
+            "branch `z` of `Thing` is not a value of `Kind`"
+        );
+    }
+
+    #[test]
+    fn test_alternate_kinds() {
+        let input = r#"
+{ 'enum': 'Mode', 'data': [ 'on', 'off' ] }
+{ 'struct': 'Opts', 'data': { 'id': 'str' } }
+{ 'alternate': 'Choice', 'data': { 'o': 'Opts', 'm': 'Mode', 'n': 'null', 'i': 'int' } }
+"#;
+        let (_, enums) = process(input).unwrap();
+        let e = &enums["Choice"];
+        assert!(e.meta.attributes.contains(&Attribute::new("alternate")));
+        let kinds: Vec<(&str, &str)> = e
+            .variants
+            .iter()
+            .map(|v| {
+                let kind = v
+                    .meta
+                    .attributes
+                    .iter()
+                    .find_map(|a| match a {
+                        Attribute::Item { name, value } if name == "kind" => Some(value.as_str()),
+                        _ => None,
+                    })
+                    .unwrap();
+                (v.name.as_str(), kind)
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("o", "object"),
+                ("m", "string"),
+                ("n", "null"),
+                ("i", "number")
+            ]
+        );
+        // Nothing here is recursive, so nothing is boxed
+        assert!(e.variants.iter().all(|v| !v.boxed));
+    }
+
+    #[test]
+    fn test_alternate_array() {
+        let input = r#"
+{ 'alternate': 'StrOrList', 'data': { 's': 'str', 'l': ['str'] } }
+"#;
+        let (_, enums) = process(input).unwrap();
+        let l = &enums["StrOrList"].variants[1];
+        assert_eq!(l.kind, EnumVariantKind::Tuple("str".into()));
+        assert!(l.array);
+        assert_eq!(l.meta.attribute("kind"), Some("array"));
+    }
+
+    #[test]
+    fn test_recursive_types_are_boxed() {
+        let input = r#"
+{ 'enum': 'Driver', 'data': [ 'file', 'raw' ] }
+{ 'struct': 'File', 'data': { 'filename': 'str' } }
+{ 'struct': 'Format', 'data': { 'file': 'Ref' } }
+{ 'union': 'Options', 'base': { 'driver': 'Driver' }, 'discriminator': 'driver',
+  'data': { 'file': 'File', 'raw': 'Format' } }
+{ 'alternate': 'Ref', 'data': { 'definition': 'Options', 'reference': 'str' } }
+{ 'struct': 'ImageInfo', 'data': { 'name': 'str', '*backing': 'ImageInfo',
+  'children': [ 'ImageInfo' ] } }
+"#;
+        let (structs, enums) = process(input).unwrap();
+        let boxed: Vec<&str> = enums["Ref"]
+            .variants
+            .iter()
+            .filter(|v| v.boxed)
+            .map(|v| v.name.as_str())
+            .collect();
+        assert_eq!(boxed, vec!["definition"]);
+        assert!(!structs["Format"].fields[0].boxed);
+        assert!(!structs["Options"].fields[0].boxed);
+        assert!(enums["OptionsBranch"].variants.iter().all(|v| !v.boxed));
+
+        let boxed: Vec<&str> = structs["ImageInfo"]
+            .fields
+            .iter()
+            .filter(|f| f.boxed)
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(boxed, vec!["backing"]);
     }
 }
@@ -0,0 +1,118 @@
+/// Recursive types
+///
+/// QAPI types may contain each other. `BlockdevOptions` holds a `BlockdevRef`,
+/// which in turn is either a node name or an inline `BlockdevOptions`. In Rust
+/// such a type would have infinite size, so every cycle of types containing
+/// each other by value needs a `Box` somewhere. Arrays already live behind a
+/// `Vec` and never need one.
+///
+/// Boxes go where a reader would expect them: alternate variants first (the
+/// reference-or-inline pattern), then optional fields, and only then any
+/// other field or branch that is still part of a cycle.
+use super::{Attribute, Enum, EnumVariantKind, Struct};
+use crate::graph::cycles;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Field(usize, usize),
+    Variant(usize, usize),
+}
+
+#[derive(Debug)]
+struct Edge {
+    from: usize,
+    to: usize,
+    slot: Slot,
+    preference: u8,
+}
+
+pub fn box_recursive_types(
+    structs_lookup: &mut HashMap<String, Struct>,
+    enums_lookup: &mut HashMap<String, Enum>,
+) {
+    let mut struct_names: Vec<String> = structs_lookup.keys().cloned().collect();
+    let mut enum_names: Vec<String> = enums_lookup.keys().cloned().collect();
+    struct_names.sort();
+    enum_names.sort();
+    let mut index = HashMap::new();
+    for (i, name) in enum_names.iter().enumerate().rev() {
+        index.insert(name.as_str(), struct_names.len() + i);
+    }
+    // A struct wins over an enum of the same name, as nothing can refer to the
+    // generated union branch enums by name
+    for (i, name) in struct_names.iter().enumerate() {
+        index.insert(name.as_str(), i);
+    }
+
+    loop {
+        let mut edges = Vec::new();
+        for (from, name) in struct_names.iter().enumerate() {
+            for (i, field) in structs_lookup[name].fields.iter().enumerate() {
+                if field.array || field.boxed {
+                    continue;
+                }
+                if let Some(&to) = index.get(field.r#type.as_str()) {
+                    edges.push(Edge {
+                        from,
+                        to,
+                        slot: Slot::Field(from, i),
+                        preference: if field.optional { 1 } else { 2 },
+                    });
+                }
+            }
+        }
+        for (e, name) in enum_names.iter().enumerate() {
+            let from = struct_names.len() + e;
+            let r#enum = &enums_lookup[name];
+            let alternate = r#enum
+                .meta
+                .attributes
+                .contains(&Attribute::new("alternate"));
+            for (i, variant) in r#enum.variants.iter().enumerate() {
+                let EnumVariantKind::Tuple(r#type) = &variant.kind else {
+                    continue;
+                };
+                if variant.array || variant.boxed {
+                    continue;
+                }
+                if let Some(&to) = index.get(r#type.as_str()) {
+                    edges.push(Edge {
+                        from,
+                        to,
+                        slot: Slot::Variant(e, i),
+                        preference: if alternate { 0 } else { 2 },
+                    });
+                }
+            }
+        }
+
+        let mut adjacency = vec![Vec::new(); struct_names.len() + enum_names.len()];
+        for edge in &edges {
+            adjacency[edge.from].push(edge.to);
+        }
+        let components = cycles(&adjacency);
+        if components.is_empty() {
+            return;
+        }
+        for component in components {
+            let inside: Vec<&Edge> = edges
+                .iter()
+                .filter(|e| component.contains(&e.from) && component.contains(&e.to))
+                .collect();
+            let preference = inside.iter().map(|e| e.preference).min().unwrap();
+            for edge in inside.into_iter().filter(|e| e.preference == preference) {
+                match edge.slot {
+                    Slot::Field(s, i) => {
+                        let s = structs_lookup.get_mut(&struct_names[s]).unwrap();
+                        s.fields[i].boxed = true;
+                    }
+                    Slot::Variant(e, i) => {
+                        let e = enums_lookup.get_mut(&enum_names[e]).unwrap();
+                        e.variants[i].boxed = true;
+                    }
+                }
+            }
+        }
+    }
+}
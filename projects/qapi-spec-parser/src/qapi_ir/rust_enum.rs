@@ -16,6 +16,7 @@ pub struct EnumVariant {
     pub meta: Metadata,
     pub kind: EnumVariantKind,
     pub array: bool,
+    pub boxed: bool,
 }
 
 impl Ord for EnumVariant {
@@ -54,21 +55,13 @@ impl Enum {
         let enum_name = format_ident!("{}", rustify_type(&self.name));
 
         let enum_attrs = self.meta.attributes.iter().map(generate_attribute);
-        let enum_doc = self.meta.doc.as_ref().map(|doc| {
-            quote! {
-                #[doc = #doc]
-            }
-        });
+        let enum_doc = self.meta.generate_doc(None);
 
         let variants = self.variants.iter().map(|variant| {
             let variant_name = format_ident!("{}", rustify_type(&variant.name));
 
             let variant_attrs = variant.meta.attributes.iter().map(generate_attribute);
-            let variant_doc = variant.meta.doc.as_ref().map(|doc| {
-                quote! {
-                    #[doc = #doc]
-                }
-            });
+            let variant_doc = variant.meta.generate_doc(None);
 
             match &variant.kind {
                 EnumVariantKind::Unit => {
@@ -79,7 +72,13 @@ impl Enum {
                     }
                 }
                 EnumVariantKind::Tuple(type_name) => {
-                    let type_ident = format_ident!("{}", rustify_type(type_name));
+                    let mut type_ident: TokenStream = rustify_type(type_name).parse().unwrap();
+                    if variant.array {
+                        type_ident = quote!( Vec<#type_ident> );
+                    }
+                    if variant.boxed {
+                        type_ident = quote!( Box<#type_ident> );
+                    }
                     quote! {
                         #variant_doc
                         #(#variant_attrs)*
@@ -92,11 +91,7 @@ impl Enum {
                         let field_type = format_ident!("{}", rustify_type(&field.r#type));
 
                         let field_attrs = field.meta.attributes.iter().map(generate_attribute);
-                        let field_doc = field.meta.doc.as_ref().map(|doc| {
-                            quote! {
-                                #[doc = #doc]
-                            }
-                        });
+                        let field_doc = field.meta.generate_doc(None);
 
                         quote! {
                             #field_doc
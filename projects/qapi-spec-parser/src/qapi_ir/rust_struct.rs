@@ -1,4 +1,4 @@
-use super::{generate_attribute, rustify_field, rustify_type, Metadata};
+use super::{generate_attribute, generate_cfg, rustify_field, rustify_type, DocItem, Metadata};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use std::cmp::Ordering;
@@ -10,6 +10,24 @@ pub struct StructField {
     pub r#type: String,
     pub optional: bool,
     pub array: bool,
+    pub boxed: bool,
+}
+
+impl StructField {
+    /// The type of the field as declared in the struct
+    pub fn rust_type(&self) -> TokenStream {
+        let mut field_type: TokenStream = rustify_type(&self.r#type).parse().unwrap();
+        if self.array {
+            field_type = quote!( Vec<#field_type> );
+        }
+        if self.boxed {
+            field_type = quote!( Box<#field_type> );
+        }
+        if self.optional {
+            field_type = quote!( Option<#field_type> );
+        }
+        field_type
+    }
 }
 
 impl Ord for StructField {
@@ -24,10 +42,22 @@ impl PartialOrd for StructField {
     }
 }
 
+/// The QAPI definition a struct was made from. Structs and unions share the
+/// same shape; events and commands are messages on the wire.
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+pub enum StructKind {
+    #[default]
+    Type,
+    Event,
+    /// A command, with the value it returns if it returns one
+    Command(Option<StructField>),
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Struct {
     pub name: String,
     pub meta: Metadata,
+    pub kind: StructKind,
     pub fields: Vec<StructField>,
 }
 
@@ -43,31 +73,36 @@ impl PartialOrd for Struct {
     }
 }
 impl Struct {
-    pub fn generate(&self) -> TokenStream {
-        let struct_name = format_ident!("{}", rustify_type(&self.name));
+    /// The name of the generated struct. Events get an `Event` suffix, as an
+    /// event may share its name with a command, like `STOP` and `stop`.
+    pub fn rust_name(&self) -> String {
+        match self.kind {
+            StructKind::Event => format!("{}Event", rustify_type(&self.name)),
+            _ => rustify_type(&self.name),
+        }
+    }
+
+    /// The struct and its builder. Doc-tests refer to the generated code as
+    /// `krate`.
+    pub fn generate(&self, krate: &str) -> TokenStream {
+        let rust_name = self.rust_name();
+        let struct_name = format_ident!("{}", rust_name);
         let struct_attrs = self.meta.attributes.iter().map(generate_attribute);
-        let struct_doc = self.meta.doc.as_ref().map(|doc| {
-            quote! {
-                #[doc = #doc]
-            }
-        });
+        let returns = self.meta.attribute("returns").filter(|r| *r != "()");
+        let item = DocItem {
+            name: &self.name,
+            rust_name: &rust_name,
+            returns,
+            krate,
+        };
+        let struct_doc = self.meta.generate_doc(Some(item));
 
         let fields = self.fields.iter().map(|field| {
             let field_name: TokenStream = rustify_field(&field.name).parse().unwrap();
-            let mut field_type: TokenStream = rustify_type(&field.r#type).parse().unwrap();
-            if field.array {
-                field_type = quote!( Vec<#field_type> );
-            }
-            if field.optional {
-                field_type = quote!( Option<#field_type> );
-            }
+            let field_type = field.rust_type();
 
             let field_attrs = field.meta.attributes.iter().map(generate_attribute);
-            let field_doc = field.meta.doc.as_ref().map(|doc| {
-                quote! {
-                    #[doc = #doc]
-                }
-            });
+            let field_doc = field.meta.generate_doc(None);
 
             quote! {
                  #field_doc
@@ -77,6 +112,7 @@ impl Struct {
             }
         });
 
+        let builder = self.generate_builder(true);
         quote! {
             #struct_doc
             #(#struct_attrs)*
@@ -84,6 +120,202 @@ impl Struct {
                 #(#fields)*
 
             }
+            #builder
+        }
+    }
+
+    /// Constructor taking the required fields, a fluent setter for each
+    /// optional field, and `Default` when there is nothing required.
+    ///
+    /// With `conditions`, every place a conditional field is mentioned gets a
+    /// `#[cfg]` for its `condition` so they are compiled out together.
+    pub fn generate_builder(&self, conditions: bool) -> TokenStream {
+        let struct_name = format_ident!("{}", self.rust_name());
+        let conditions = |meta: &Metadata| -> Option<TokenStream> {
+            meta.attribute("condition")
+                .filter(|_| conditions)
+                .map(generate_cfg)
+        };
+        let struct_conditions = conditions(&self.meta);
+
+        let mut args = Vec::new();
+        let mut inits = Vec::new();
+        let mut setters = Vec::new();
+        for field in &self.fields {
+            let field_name: TokenStream = rustify_field(&field.name).parse().unwrap();
+            let mut value_type: TokenStream = rustify_type(&field.r#type).parse().unwrap();
+            if field.array {
+                value_type = quote!( Vec<#value_type> );
+            }
+            let value = if field.boxed {
+                quote!(Box::new(value))
+            } else {
+                quote!(value)
+            };
+            let field_conditions = conditions(&field.meta);
+
+            if field.optional {
+                let field_doc = field.meta.generate_doc(None);
+                inits.push(quote! {
+                    #field_conditions
+                    #field_name: None,
+                });
+                setters.push(quote! {
+                    #field_doc
+                    #field_conditions
+                    pub fn #field_name(mut self, value: #value_type) -> Self {
+                        self.#field_name = Some(#value);
+                        self
+                    }
+                });
+            } else {
+                args.push(quote! {
+                    #field_conditions
+                    #field_name: #value_type
+                });
+                let init = if field.boxed {
+                    quote!( #field_name: Box::new(#field_name) )
+                } else {
+                    quote!(#field_name)
+                };
+                inits.push(quote! {
+                    #field_conditions
+                    #init,
+                });
+            }
+        }
+
+        let allow = (args.len() > 7).then(|| {
+            quote! {
+                #[allow(clippy::too_many_arguments)]
+            }
+        });
+        let default = args.is_empty().then(|| {
+            quote! {
+                #struct_conditions
+                impl Default for #struct_name {
+                    fn default() -> Self {
+                        Self::new()
+                    }
+                }
+            }
+        });
+        quote! {
+            #struct_conditions
+            impl #struct_name {
+                #allow
+                pub fn new(#(#args),*) -> Self {
+                    Self {
+                        #(#inits)*
+                    }
+                }
+                #(#setters)*
+            }
+            #default
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qapi_ir::Attribute;
+
+    fn field(name: &str, r#type: &str, optional: bool) -> StructField {
+        StructField {
+            name: name.into(),
+            meta: Metadata::default(),
+            r#type: r#type.into(),
+            optional,
+            array: false,
+            boxed: false,
+        }
+    }
+
+    fn pretty(code: TokenStream) -> String {
+        prettyplease::unparse(&syn::parse2(code).unwrap())
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut backing = field("backing", "ImageInfo", true);
+        backing.boxed = true;
+        let mut children = field("children", "str", true);
+        children.array = true;
+        let mut cond = field("x-cond", "bool", true);
+        cond.meta
+            .attributes
+            .push(Attribute::with_value("condition", "CONFIG_X"));
+        let s = Struct {
+            name: "ImageInfo".into(),
+            meta: Metadata::default(),
+            kind: StructKind::Type,
+            fields: vec![field("type", "str", false), backing, children, cond],
+        };
+        assert_eq!(
+            pretty(s.generate_builder(true)),
+            r#"impl ImageInfo {
+    pub fn new(r#type: String) -> Self {
+        Self {
+            r#type,
+            backing: None,
+            children: None,
+            #[cfg(CONFIG_X)]
+            x_cond: None,
         }
     }
+    pub fn backing(mut self, value: ImageInfo) -> Self {
+        self.backing = Some(Box::new(value));
+        self
+    }
+    pub fn children(mut self, value: Vec<String>) -> Self {
+        self.children = Some(value);
+        self
+    }
+    #[cfg(CONFIG_X)]
+    pub fn x_cond(mut self, value: bool) -> Self {
+        self.x_cond = Some(value);
+        self
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_builder_nested_condition() {
+        let mut cond = field("x-cond", "bool", true);
+        cond.meta.attributes.push(Attribute::with_value(
+            "condition",
+            "(CONFIG_X || !(CONFIG_Y && !CONFIG_Z))",
+        ));
+        let s = Struct {
+            name: "ImageInfo".into(),
+            meta: Metadata::default(),
+            kind: StructKind::Type,
+            fields: vec![cond],
+        };
+        let code = pretty(s.generate_builder(true));
+        assert_eq!(
+            code.matches("#[cfg(any(CONFIG_X, not(all(CONFIG_Y, not(CONFIG_Z)))))]")
+                .count(),
+            2,
+            "{}",
+            code
+        );
+        assert!(!pretty(s.generate_builder(false)).contains("cfg"));
+    }
+
+    #[test]
+    fn test_builder_default() {
+        let s = Struct {
+            name: "query-things".into(),
+            meta: Metadata::default(),
+            kind: StructKind::Command(None),
+            fields: vec![field("id", "str", true)],
+        };
+        let code = pretty(s.generate_builder(true));
+        assert!(code.contains("pub fn new() -> Self"));
+        assert!(code.contains("impl Default for QueryThings"));
+    }
 }
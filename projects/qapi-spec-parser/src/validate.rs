@@ -0,0 +1,798 @@
+/// Semantic validation of parsed QAPI schemas
+///
+/// The parsers only care about syntax. This pass sits between parsing and the
+/// `process_*` functions and checks the rules QEMU's own `scripts/qapi`
+/// enforces: every type reference resolves, names are unique and well formed,
+/// unions agree with their discriminator enum, and documentation is present
+/// where the `pragma` directives ask for it.
+///
+/// Nothing here stops at the first problem. All findings are returned so the
+/// tool can be used to lint a schema.
+use crate::{
+    Diagnostic, MembersOrRef, QapiAlternate, QapiCommand, QapiDocumentation, QapiEnum, QapiEvent,
+    QapiMember, QapiMembers, QapiSchema, QapiSchemaToken, QapiStruct, QapiTypeRef, QapiUnion,
+};
+use std::collections::{HashMap, HashSet};
+
+const BUILTIN_TYPES: [&str; 16] = [
+    "str", "number", "int", "int8", "int16", "int32", "int64", "uint8", "uint16", "uint32",
+    "uint64", "size", "bool", "any", "null", "QType",
+];
+
+pub fn validate(schemas: &[QapiSchema<'_>]) -> Vec<Diagnostic> {
+    let mut v = Validator::default();
+    // Definitions may be referenced before they are declared, so everything is
+    // collected before any checks run.
+    for schema in schemas {
+        for token in schema.iter() {
+            v.collect(token);
+        }
+    }
+    for schema in schemas {
+        for token in schema.iter() {
+            v.check(token);
+        }
+    }
+    v.diagnostics
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Definition<'a, 'i> {
+    Enum(&'a QapiEnum<'i>),
+    Struct(&'a QapiStruct<'i>),
+    Union(&'a QapiUnion<'i>),
+    Alternate,
+    Command,
+    Event,
+}
+
+/// All `pragma` directives merged together
+#[derive(Debug, Default)]
+struct Pragma<'i> {
+    doc_required: bool,
+    command_name_exceptions: HashSet<&'i str>,
+    command_returns_exceptions: HashSet<&'i str>,
+    documentation_exceptions: HashSet<&'i str>,
+    member_name_exceptions: HashSet<&'i str>,
+}
+
+#[derive(Debug, Default)]
+struct Validator<'a, 'i: 'a> {
+    definitions: HashMap<&'i str, Definition<'a, 'i>>,
+    pragma: Pragma<'i>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Strip the optional `__RFQDN_` and `x-` prefixes from a name and return the
+/// remaining stem, or `None` if the name is not a valid QAPI name at all.
+fn name_stem(name: &str) -> Option<&str> {
+    let mut stem = name;
+    if let Some(rest) = stem.strip_prefix("__") {
+        let end = rest.find('_')?;
+        let vendor = &rest[..end];
+        if vendor.is_empty()
+            || !vendor
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        {
+            return None;
+        }
+        stem = &rest[end + 1..];
+    }
+    stem = stem.strip_prefix("x-").unwrap_or(stem);
+    let mut chars = stem.chars();
+    let first = chars.next()?;
+    if !first.is_ascii_alphabetic()
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    Some(stem)
+}
+
+impl<'a, 'i: 'a> Validator<'a, 'i> {
+    fn error(&mut self, message: String, span: &str) {
+        self.diagnostics
+            .push(Diagnostic::new(message).with_span(span));
+    }
+
+    fn warning(&mut self, message: String, span: &str) {
+        self.diagnostics
+            .push(Diagnostic::warning(message).with_span(span));
+    }
+
+    fn collect(&mut self, token: &'a QapiSchemaToken<'i>) {
+        let (name, definition) = match token {
+            QapiSchemaToken::Enum(v) => (v.name, Definition::Enum(v)),
+            QapiSchemaToken::Struct(v) => (v.name, Definition::Struct(v)),
+            QapiSchemaToken::Union(v) => (v.name, Definition::Union(v)),
+            QapiSchemaToken::Alternate(v) => (v.name, Definition::Alternate),
+            QapiSchemaToken::Command(v) => (v.name, Definition::Command),
+            QapiSchemaToken::Event(v) => (v.name, Definition::Event),
+            QapiSchemaToken::Pragma(p) => {
+                if let Some(v) = p.doc_required {
+                    self.pragma.doc_required = v == "true";
+                }
+                let lists = [
+                    (
+                        &p.command_name_exceptions,
+                        &mut self.pragma.command_name_exceptions,
+                    ),
+                    (
+                        &p.command_returns_exceptions,
+                        &mut self.pragma.command_returns_exceptions,
+                    ),
+                    (
+                        &p.documentation_exceptions,
+                        &mut self.pragma.documentation_exceptions,
+                    ),
+                    (
+                        &p.member_name_exceptions,
+                        &mut self.pragma.member_name_exceptions,
+                    ),
+                ];
+                for (names, merged) in lists {
+                    merged.extend(names.iter().flatten());
+                }
+                return;
+            }
+            _ => return,
+        };
+        if BUILTIN_TYPES.contains(&name) || self.definitions.contains_key(name) {
+            self.error(format!("`{}` is already defined", name), name);
+            return;
+        }
+        self.definitions.insert(name, definition);
+    }
+
+    fn check(&mut self, token: &'a QapiSchemaToken<'i>) {
+        match token {
+            QapiSchemaToken::Enum(v) => self.check_enum(v),
+            QapiSchemaToken::Struct(v) => self.check_struct(v),
+            QapiSchemaToken::Union(v) => self.check_union(v),
+            QapiSchemaToken::Alternate(v) => self.check_alternate(v),
+            QapiSchemaToken::Command(v) => self.check_command(v),
+            QapiSchemaToken::Event(v) => self.check_event(v),
+            _ => {}
+        }
+    }
+
+    fn is_type(&self, name: &str) -> bool {
+        BUILTIN_TYPES.contains(&name)
+            || matches!(
+                self.definitions.get(name),
+                Some(
+                    Definition::Enum(_)
+                        | Definition::Struct(_)
+                        | Definition::Union(_)
+                        | Definition::Alternate
+                )
+            )
+    }
+
+    fn is_object(&self, name: &str) -> bool {
+        matches!(
+            self.definitions.get(name),
+            Some(Definition::Struct(_) | Definition::Union(_))
+        )
+    }
+
+    fn enum_values(&self, name: &str) -> Option<Vec<&'i str>> {
+        match self.definitions.get(name) {
+            Some(Definition::Enum(e)) => Some(e.data.iter().map(|v| v.name).collect()),
+            _ => None,
+        }
+    }
+
+    /// All members of a struct or union base, including inherited ones
+    fn object_members(&self, name: &str) -> Vec<&'a QapiMember<'i>> {
+        let mut seen = HashSet::new();
+        self.object_members_inner(name, &mut seen)
+    }
+
+    fn object_members_inner(
+        &self,
+        name: &str,
+        seen: &mut HashSet<String>,
+    ) -> Vec<&'a QapiMember<'i>> {
+        // Stop following bases that form a cycle
+        if !seen.insert(name.to_string()) {
+            return Vec::new();
+        }
+        match self.definitions.get(name) {
+            Some(Definition::Struct(s)) => {
+                let mut members = match &s.base {
+                    Some(MembersOrRef::Members(m)) => m.iter().collect(),
+                    Some(MembersOrRef::Ref(base)) => self.object_members_inner(base, seen),
+                    _ => Vec::new(),
+                };
+                members.extend(s.data.iter());
+                members
+            }
+            Some(Definition::Union(u)) => match &u.base {
+                MembersOrRef::Members(m) => m.iter().collect(),
+                MembersOrRef::Ref(base) => self.object_members_inner(base, seen),
+                MembersOrRef::Unset => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn check_type_ref(&mut self, r#type: &QapiTypeRef<'i>, owner: &str) {
+        let name = match r#type {
+            QapiTypeRef::Ref(v) | QapiTypeRef::ArrayRef(v) => *v,
+            QapiTypeRef::Unset => return,
+        };
+        if !self.is_type(name) {
+            self.error(
+                format!("`{}` references unknown type `{}`", owner, name),
+                name,
+            );
+        }
+    }
+
+    fn check_name_lower(
+        &mut self,
+        name: &'i str,
+        what: &str,
+        permit_upper: bool,
+        permit_underscore: bool,
+    ) {
+        let Some(stem) = name_stem(name) else {
+            self.error(format!("{} `{}` has an invalid name", what, name), name);
+            return;
+        };
+        if (!permit_upper && stem.chars().any(|c| c.is_ascii_uppercase()))
+            || (!permit_underscore && stem.contains('_'))
+        {
+            self.error(
+                format!("name of {} `{}` must not use uppercase or '_'", what, name),
+                name,
+            );
+        }
+    }
+
+    fn check_name_upper(&mut self, name: &'i str, what: &str) {
+        let Some(stem) = name_stem(name) else {
+            self.error(format!("{} `{}` has an invalid name", what, name), name);
+            return;
+        };
+        if stem.chars().any(|c| c.is_ascii_lowercase() || c == '-') {
+            self.error(
+                format!("name of {} `{}` must not use lowercase or '-'", what, name),
+                name,
+            );
+        }
+    }
+
+    /// Check `members` declared directly by `owner`. `inherited` holds the
+    /// names the owner already has through its base.
+    fn check_members(&mut self, owner: &'i str, members: &'a QapiMembers<'i>, inherited: &[&str]) {
+        let permissive = self.pragma.member_name_exceptions.contains(owner);
+        let mut seen: HashSet<&str> = inherited.iter().copied().collect();
+        for member in members.iter() {
+            self.check_name_lower(member.name, "member", permissive, permissive);
+            if !seen.insert(member.name) {
+                self.error(
+                    format!(
+                        "member `{}` of `{}` is defined more than once",
+                        member.name, owner
+                    ),
+                    member.name,
+                );
+            }
+            self.check_type_ref(&member.r#type, owner);
+        }
+    }
+
+    fn check_docs(
+        &mut self,
+        name: &'i str,
+        doc: &'a Option<QapiDocumentation<'i>>,
+        known: &[&str],
+        required: &[&'i str],
+    ) {
+        let enforce =
+            self.pragma.doc_required && !self.pragma.documentation_exceptions.contains(name);
+        let Some(doc) = doc else {
+            if enforce {
+                self.error(format!("`{}` is missing documentation", name), name);
+            }
+            return;
+        };
+        if doc.name != name {
+            self.error(
+                format!("documentation for `{}` is attached to `{}`", doc.name, name),
+                doc.name,
+            );
+            return;
+        }
+        for (field, _) in &doc.fields {
+            if !known.contains(field) {
+                self.error(
+                    format!("documented member `{}` does not exist in `{}`", field, name),
+                    field,
+                );
+            }
+        }
+        if enforce {
+            for member in required {
+                if !doc.fields.iter().any(|(field, _)| field == member) {
+                    self.error(
+                        format!("member `{}` of `{}` lacks documentation", member, name),
+                        member,
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_enum(&mut self, e: &'a QapiEnum<'i>) {
+        let permissive = self.pragma.member_name_exceptions.contains(e.name);
+        let mut seen = HashSet::new();
+        for value in &e.data {
+            // Enum values are allowed to start with a digit
+            let name = if value.name.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("d{}", value.name)
+            } else {
+                value.name.to_string()
+            };
+            if let Some(stem) = name_stem(&name) {
+                if !permissive && stem.chars().any(|c| c.is_ascii_uppercase() || c == '_') {
+                    self.error(
+                        format!(
+                            "name of enum value `{}` must not use uppercase or '_'",
+                            value.name
+                        ),
+                        value.name,
+                    );
+                }
+            } else {
+                self.error(
+                    format!("enum value `{}` has an invalid name", value.name),
+                    value.name,
+                );
+            }
+            if !seen.insert(value.name) {
+                self.error(
+                    format!(
+                        "value `{}` of `{}` is defined more than once",
+                        value.name, e.name
+                    ),
+                    value.name,
+                );
+            }
+        }
+        let values: Vec<&'i str> = e.data.iter().map(|v| v.name).collect();
+        self.check_docs(e.name, &e.doc, &values, &values);
+    }
+
+    fn check_struct(&mut self, s: &'a QapiStruct<'i>) {
+        // Inline base members are documented with the struct, like its own
+        let mut inherited = Vec::new();
+        let mut own = Vec::new();
+        match &s.base {
+            Some(MembersOrRef::Members(members)) => {
+                self.check_members(s.name, members, &[]);
+                own.extend(members.iter().map(|m| m.name));
+            }
+            Some(MembersOrRef::Ref(base)) => match self.definitions.get(base) {
+                Some(Definition::Struct(_)) => {
+                    inherited = self.object_members(base).iter().map(|m| m.name).collect();
+                }
+                Some(_) => self.error(
+                    format!("base `{}` of `{}` must be a struct", base, s.name),
+                    base,
+                ),
+                None => self.error(
+                    format!("`{}` references unknown type `{}`", s.name, base),
+                    base,
+                ),
+            },
+            _ => {}
+        }
+        let seen: Vec<&str> = inherited.iter().chain(own.iter()).copied().collect();
+        self.check_members(s.name, &s.data, &seen);
+        own.extend(s.data.iter().map(|m| m.name));
+        let known: Vec<&str> = inherited.iter().chain(own.iter()).copied().collect();
+        self.check_docs(s.name, &s.doc, &known, &own);
+    }
+
+    fn check_union(&mut self, u: &'a QapiUnion<'i>) {
+        let mut documented = Vec::new();
+        let base_members: Vec<&'a QapiMember<'i>> = match &u.base {
+            MembersOrRef::Members(members) => {
+                self.check_members(u.name, members, &[]);
+                documented.extend(members.iter().map(|m| m.name));
+                members.iter().collect()
+            }
+            MembersOrRef::Ref(base) => match self.definitions.get(base) {
+                Some(Definition::Struct(_)) => self.object_members(base),
+                _ => {
+                    self.error(
+                        format!("base `{}` of `{}` must be a struct", base, u.name),
+                        base,
+                    );
+                    Vec::new()
+                }
+            },
+            MembersOrRef::Unset => unreachable! {"this should have failed the parser"},
+        };
+
+        let discriminator = base_members.iter().find(|m| m.name == u.discriminator);
+        let values = match discriminator {
+            None => {
+                self.error(
+                    format!(
+                        "discriminator `{}` is not a member of the base of `{}`",
+                        u.discriminator, u.name
+                    ),
+                    u.discriminator,
+                );
+                None
+            }
+            Some(member) => {
+                if member.optional {
+                    self.error(
+                        format!(
+                            "discriminator `{}` of `{}` must not be optional",
+                            u.discriminator, u.name
+                        ),
+                        u.discriminator,
+                    );
+                }
+                let values = match member.r#type {
+                    QapiTypeRef::Ref(t) => self.enum_values(t).map(|v| (t, v)),
+                    _ => None,
+                };
+                if values.is_none() {
+                    self.error(
+                        format!(
+                            "discriminator `{}` of `{}` must be an enum",
+                            u.discriminator, u.name
+                        ),
+                        u.discriminator,
+                    );
+                }
+                values
+            }
+        };
+
+        for branch in u.data.iter() {
+            self.check_type_ref(&branch.r#type, u.name);
+            match branch.r#type {
+                QapiTypeRef::Ref(t) if self.is_object(t) || !self.is_type(t) => {}
+                QapiTypeRef::Ref(t) | QapiTypeRef::ArrayRef(t) => self.error(
+                    format!(
+                        "branch `{}` of `{}` must be a struct or union",
+                        branch.name, u.name
+                    ),
+                    t,
+                ),
+                QapiTypeRef::Unset => {}
+            }
+            if let Some((enum_name, values)) = &values {
+                if !values.contains(&branch.name) {
+                    self.error(
+                        format!(
+                            "branch `{}` of `{}` is not a value of `{}`",
+                            branch.name, u.name, enum_name
+                        ),
+                        branch.name,
+                    );
+                }
+            }
+        }
+        if let Some((enum_name, values)) = &values {
+            for value in values {
+                if !u.data.iter().any(|b| b.name == *value) {
+                    self.warning(
+                        format!(
+                            "value `{}` of `{}` has no branch in `{}` and maps to an empty branch",
+                            value, enum_name, u.name
+                        ),
+                        u.name,
+                    );
+                }
+            }
+        }
+
+        let known: Vec<&str> = base_members.iter().map(|m| m.name).collect();
+        self.check_docs(u.name, &u.doc, &known, &documented);
+    }
+
+    fn check_alternate(&mut self, a: &'a QapiAlternate<'i>) {
+        let mut kinds: HashMap<&str, &str> = HashMap::new();
+        for alternative in a.data.iter() {
+            self.check_type_ref(&alternative.r#type, a.name);
+            let kind = match alternative.r#type {
+                QapiTypeRef::ArrayRef(_) => Some("array"),
+                // `QType` is a builtin enum, so a JSON string like `str`
+                QapiTypeRef::Ref("str" | "QType") => Some("string"),
+                QapiTypeRef::Ref("bool") => Some("boolean"),
+                QapiTypeRef::Ref("null") => Some("null"),
+                QapiTypeRef::Ref("any") => None,
+                QapiTypeRef::Ref(t) if BUILTIN_TYPES.contains(&t) => Some("number"),
+                QapiTypeRef::Ref(t) => match self.definitions.get(t) {
+                    Some(Definition::Enum(_)) => Some("string"),
+                    Some(Definition::Struct(_) | Definition::Union(_)) => Some("object"),
+                    _ => None,
+                },
+                QapiTypeRef::Unset => None,
+            };
+            let Some(kind) = kind else {
+                if let QapiTypeRef::Ref(t) = alternative.r#type {
+                    if self.is_type(t) {
+                        self.error(
+                            format!(
+                                "alternative `{}` of `{}` cannot be of type `{}`",
+                                alternative.name, a.name, t
+                            ),
+                            alternative.name,
+                        );
+                    }
+                }
+                continue;
+            };
+            if let Some(other) = kinds.insert(kind, alternative.name) {
+                self.error(
+                    format!(
+                        "alternatives `{}` and `{}` of `{}` are both JSON {}s",
+                        other, alternative.name, a.name, kind
+                    ),
+                    alternative.name,
+                );
+            }
+        }
+        let names: Vec<&'i str> = a.data.iter().map(|v| v.name).collect();
+        self.check_docs(a.name, &a.doc, &names, &names);
+    }
+
+    /// Shared checks for the `data` of commands and events. Returns the
+    /// members that are known and those that must be documented.
+    fn check_arguments(
+        &mut self,
+        owner: &'i str,
+        data: &'a Option<MembersOrRef<'i>>,
+        boxed: Option<&'i str>,
+    ) -> (Vec<&'i str>, Vec<&'i str>) {
+        let boxed = boxed == Some("true");
+        match data {
+            None => {
+                if boxed {
+                    self.error(format!("`{}` is boxed but has no 'data'", owner), owner);
+                }
+                (Vec::new(), Vec::new())
+            }
+            Some(MembersOrRef::Members(members)) => {
+                if boxed {
+                    self.error(
+                        format!("'boxed' `{}` requires 'data' to name a type", owner),
+                        owner,
+                    );
+                }
+                self.check_members(owner, members, &[]);
+                let names: Vec<&'i str> = members.iter().map(|m| m.name).collect();
+                (names.clone(), names)
+            }
+            Some(MembersOrRef::Ref(r)) => {
+                match self.definitions.get(r) {
+                    Some(Definition::Struct(_)) => {}
+                    Some(Definition::Union(_)) if boxed => {}
+                    Some(_) => self.error(
+                        format!(
+                            "'data' of `{}` must be a struct{}",
+                            owner,
+                            if boxed { " or union" } else { "" }
+                        ),
+                        r,
+                    ),
+                    None => self.error(format!("`{}` references unknown type `{}`", owner, r), r),
+                }
+                let names = self.object_members(r).iter().map(|m| m.name).collect();
+                (names, Vec::new())
+            }
+            Some(MembersOrRef::Unset) => unreachable! {"this should have failed the parser"},
+        }
+    }
+
+    fn check_command(&mut self, c: &'a QapiCommand<'i>) {
+        let permit_underscore = self.pragma.command_name_exceptions.contains(c.name);
+        self.check_name_lower(c.name, "command", false, permit_underscore);
+        let (known, documented) = self.check_arguments(c.name, &c.data, c.boxed);
+        if let Some(returns) = &c.returns {
+            self.check_type_ref(returns, c.name);
+            let element = match returns {
+                QapiTypeRef::Ref(t) | QapiTypeRef::ArrayRef(t) => *t,
+                QapiTypeRef::Unset => "",
+            };
+            if self.is_type(element)
+                && !self.is_object(element)
+                && !self.pragma.command_returns_exceptions.contains(c.name)
+            {
+                self.error(
+                    format!(
+                        "'returns' of `{}` must be an object type or an array of one",
+                        c.name
+                    ),
+                    element,
+                );
+            }
+        }
+        self.check_docs(c.name, &c.doc, &known, &documented);
+    }
+
+    fn check_event(&mut self, e: &'a QapiEvent<'i>) {
+        self.check_name_upper(e.name, "event");
+        let (known, documented) = self.check_arguments(e.name, &e.data, e.boxed);
+        self.check_docs(e.name, &e.doc, &known, &documented);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::take_schema;
+
+    fn messages(input: &str) -> Vec<String> {
+        let (rest, schema) = take_schema(input).unwrap();
+        assert_eq!(rest, "");
+        validate(&[schema])
+            .into_iter()
+            .map(|d| format!("{}: {}", d.severity, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_schema() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a', 'b' ] }
+{ 'struct': 'Base', 'data': { 'kind': 'Kind' } }
+{ 'struct': 'BranchA', 'data': { '*x': 'int' } }
+{ 'union': 'Thing', 'base': 'Base', 'discriminator': 'kind',
+  'data': { 'a': 'BranchA', 'b': 'BranchA' } }
+{ 'alternate': 'StrOrNull', 'data': { 's': 'str', 'n': 'null' } }
+{ 'command': 'query-thing', 'returns': [ 'Thing' ] }
+{ 'event': 'THING_CHANGED', 'data': 'Base' }
+"#;
+        assert_eq!(messages(input), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unknown_type_and_duplicate() {
+        let input = r#"
+{ 'struct': 'Foo', 'data': { 'a': 'Missing', 'b': [ 'AlsoMissing' ] } }
+{ 'enum': 'Foo', 'data': [ 'x' ] }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: `Foo` is already defined",
+                "error: `Foo` references unknown type `Missing`",
+                "error: `Foo` references unknown type `AlsoMissing`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_struct_base() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a' ] }
+{ 'struct': 'Base', 'base': { 'kind': 'Kind', 'id': 'Missing' },
+  'data': { 'id': 'str' } }
+{ 'struct': 'Branch', 'data': {} }
+{ 'union': 'Thing', 'base': 'Base', 'discriminator': 'kind',
+  'data': { 'a': 'Branch' } }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: `Base` references unknown type `Missing`",
+                "error: member `id` of `Base` is defined more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_union_discriminator() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a', 'b', 'c' ] }
+{ 'struct': 'Branch', 'data': {} }
+{ 'union': 'Thing', 'base': { 'kind': 'Kind' }, 'discriminator': 'kind',
+  'data': { 'a': 'Branch', 'z': 'Branch' } }
+{ 'union': 'Other', 'base': { 'kind': 'str' }, 'discriminator': 'type',
+  'data': { 'a': 'Branch' } }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: branch `z` of `Thing` is not a value of `Kind`",
+                "warning: value `b` of `Kind` has no branch in `Thing` and maps to an empty branch",
+                "warning: value `c` of `Kind` has no branch in `Thing` and maps to an empty branch",
+                "error: discriminator `type` is not a member of the base of `Other`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alternate_kinds() {
+        let input = r#"
+{ 'enum': 'Kind', 'data': [ 'a' ] }
+{ 'alternate': 'Bad', 'data': { 's': 'str', 'k': 'Kind', 'i': 'int', 'n': 'number' } }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: alternatives `s` and `k` of `Bad` are both JSON strings",
+                "error: alternatives `i` and `n` of `Bad` are both JSON numbers",
+            ]
+        );
+        let input = "{ 'alternate': 'Bad', 'data': { 's': 'str', 'q': 'QType' } }";
+        assert_eq!(
+            messages(input),
+            vec!["error: alternatives `s` and `q` of `Bad` are both JSON strings"]
+        );
+        let input = "{ 'alternate': 'Good', 'data': { 's': 'str', 'l': ['str'] } }";
+        assert!(messages(input).is_empty());
+        let input = "{ 'alternate': 'Bad', 'data': { 'l': ['str'], 'i': ['int'] } }";
+        assert_eq!(
+            messages(input),
+            vec!["error: alternatives `l` and `i` of `Bad` are both JSON arrays"]
+        );
+    }
+
+    #[test]
+    fn test_pragma_exceptions() {
+        let input = r#"
+{ 'pragma': { 'command-name-exceptions': [ 'system_reset' ],
+              'member-name-exceptions': [ 'Loud' ],
+              'command-returns-exceptions': [ 'query-name' ] } }
+{ 'struct': 'Loud', 'data': { 'UPPER': 'int' } }
+{ 'struct': 'Quiet', 'data': { 'UPPER': 'int', 'under_score': 'int' } }
+{ 'command': 'system_reset' }
+{ 'command': 'system_powerdown' }
+{ 'command': 'query-name', 'returns': 'str' }
+{ 'command': 'query-other', 'returns': 'str' }
+{ 'event': 'lower-event' }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: name of member `UPPER` must not use uppercase or '_'",
+                "error: name of member `under_score` must not use uppercase or '_'",
+                "error: name of command `system_powerdown` must not use uppercase or '_'",
+                "error: 'returns' of `query-other` must be an object type or an array of one",
+                "error: name of event `lower-event` must not use lowercase or '-'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_required() {
+        let input = r#"
+{ 'pragma': { 'doc-required': true,
+              'documentation-exceptions': [ 'Excused' ] } }
+
+##
+# @Documented:
+#
+# @a: documented
+#
+# @c: not a member
+##
+{ 'struct': 'Documented', 'data': { 'a': 'int', 'b': 'int' } }
+
+{ 'struct': 'Undocumented', 'data': { 'a': 'int' } }
+
+{ 'struct': 'Excused', 'data': { 'a': 'int' } }
+"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "error: documented member `c` does not exist in `Documented`",
+                "error: member `b` of `Documented` lacks documentation",
+                "error: `Undocumented` is missing documentation",
+            ]
+        );
+    }
+}
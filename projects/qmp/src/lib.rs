@@ -1,42 +1,60 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use std::collections::HashMap;
+use std::io::{self, IoSlice};
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::path::Path;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Interest};
 use tokio::net::{
     unix::{OwnedReadHalf, OwnedWriteHalf},
     UnixStream,
 };
-use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::task::{spawn, JoinHandle};
 
-use qapi::{Event, QMPCommand};
+use qapi::{Command, Event, QMPCommand};
 pub use qapi_old_hack as qapi;
 
+/// A command for the write loop, with the fd to send along and where to
+/// deliver the reply when one is expected
+type Request = (QMPCommand, Option<OwnedFd>, Option<oneshot::Sender<String>>);
+
+/// The commands waiting for their reply, by the `id` they were sent with
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<String>>>>;
+
 #[derive(Debug)]
 pub struct QMP {
-    command: mpsc::Sender<(QMPCommand, oneshot::Sender<String>)>,
+    command: mpsc::Sender<Request>,
+    events: broadcast::Sender<Event>,
     shutdown_signal: watch::Sender<()>,
     read_handle: Option<JoinHandle<()>>,
     write_handle: Option<JoinHandle<()>>,
     event_handle: Option<JoinHandle<()>>,
+    no_success_response: &'static [&'static str],
 }
 
 impl QMP {
+    /// Connect to the QMP socket at `socket_path`. The events QEMU sends go to
+    /// the returned receiver as well as to every `subscribe`r; the receiver
+    /// misses events while it holds 100 unread ones.
     pub async fn new(socket_path: &Path) -> Result<(Self, mpsc::Receiver<Event>)> {
         let stream = UnixStream::connect(socket_path).await?;
         let (stream_read_half, stream_write_half) = stream.into_split();
 
         let (command_tx, command_rx) = mpsc::channel(1);
-        let (response_tx, response_rx) = mpsc::channel(1);
+        let pending = Pending::default();
         let (event_tx, event_rx) = mpsc::channel(100);
         let (event_forwarder, event_receiver) = mpsc::channel(100);
+        let (event_broadcast, _) = broadcast::channel(100);
         let (shutdown_tx, shutdown_rx) = watch::channel(());
 
         // Read loop
         let shutdown_rx_clone = shutdown_rx.clone();
         let read_handle = spawn(Self::read_loop(
             stream_read_half,
-            response_tx,
+            pending.clone(),
             event_tx,
             shutdown_rx_clone,
         ));
@@ -45,7 +63,7 @@ impl QMP {
         let write_handle = spawn(Self::write_loop(
             stream_write_half,
             command_rx,
-            response_rx,
+            pending,
             shutdown_rx.clone(),
         ));
 
@@ -54,14 +72,17 @@ impl QMP {
             event_rx,
             shutdown_rx.clone(),
             event_forwarder,
+            event_broadcast.clone(),
         ));
 
         let qmp = Self {
             command: command_tx,
+            events: event_broadcast,
             shutdown_signal: shutdown_tx,
             write_handle: Some(write_handle),
             read_handle: Some(read_handle),
             event_handle: Some(event_handle),
+            no_success_response: &[],
         };
         Ok((qmp, event_receiver))
     }
@@ -90,6 +111,20 @@ impl QMP {
         }
     }
 
+    /// Name the commands QEMU only answers when they fail, usually the
+    /// `NO_SUCCESS_RESPONSE` generated from the QAPI schema. `execute` does
+    /// not wait for a reply to these.
+    pub fn set_no_success_response(&mut self, commands: &'static [&'static str]) {
+        self.no_success_response = commands;
+    }
+
+    /// Receive the events arriving from now on, alongside the receiver
+    /// returned by `new`. Subscribers that fall more than 100 events behind
+    /// miss the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
     /// Execute a QMP command
     ///
     /// With QMP, some commands have an additional `allow-oob` flag that uses an
@@ -100,12 +135,54 @@ impl QMP {
     /// TODO: this function is already async, the oob cabability should always
     /// be used when available, I dont want to expose the async command queue
     /// depth to the user of this crate.
+    ///
+    /// Commands without a success response, see `set_no_success_response`,
+    /// are only answered by QEMU when they fail, so nothing waits for a reply
+    /// and an empty string is returned once the command has been queued.
+    ///
+    /// The reply is returned as QEMU sent it, whether that holds `return` or
+    /// `error`.
     pub async fn execute(&mut self, cmd: QMPCommand) -> Result<String> {
+        let reply = !self.no_success_response.contains(&cmd.name().as_str());
+        self.send(cmd, None, reply).await
+    }
+
+    /// Execute a QMP command, passing `fd` to QEMU along with it. This is how
+    /// `getfd` receives the fd it names.
+    pub async fn execute_with_fd(&mut self, cmd: QMPCommand, fd: OwnedFd) -> Result<String> {
+        let reply = !self.no_success_response.contains(&cmd.name().as_str());
+        self.send(cmd, Some(fd), reply).await
+    }
+
+    /// Execute `command` as `C::NAME` and parse what it returns. A reply
+    /// holding an `error` is an `Err`. Commands without a success response
+    /// return as soon as they are queued.
+    pub async fn execute_command<C: Command>(&mut self, command: &C) -> Result<C::Returns> {
+        let reply = self
+            .send(QMPCommand::new(command), None, C::SUCCESS_RESPONSE)
+            .await?;
+        if reply.is_empty() {
+            // Nothing to parse; `Returns` is the empty object then
+            return Ok(serde_json::from_value(serde_json::json!({}))?);
+        }
+        let mut reply: serde_json::Value = serde_json::from_str(&reply)?;
+        if let Some(error) = reply.get("error") {
+            return Err(anyhow! {"QMP command {} failed: {}", C::NAME, error["desc"]});
+        }
+        Ok(serde_json::from_value(reply["return"].take())?)
+    }
+
+    async fn send(&mut self, cmd: QMPCommand, fd: Option<OwnedFd>, reply: bool) -> Result<String> {
+        if !reply {
+            self.command.send((cmd, fd, None)).await.unwrap();
+            return Ok(String::new());
+        }
+
         // Set up a message response channel for our command
         let (reply_tx, reply_rx) = oneshot::channel();
 
         // Send the command along with a channel for the response.
-        self.command.send((cmd, reply_tx)).await.unwrap();
+        self.command.send((cmd, fd, Some(reply_tx))).await.unwrap();
         let reply = reply_rx.await?;
         Ok(reply)
     }
@@ -115,12 +192,18 @@ impl QMP {
         mut event_rx: mpsc::Receiver<String>,
         mut shutdown_rx: watch::Receiver<()>,
         event_forwarder: mpsc::Sender<Event>,
+        event_broadcast: broadcast::Sender<Event>,
     ) {
         loop {
             tokio::select! {
                 Some(event) = event_rx.recv() => {
                     let event: Event = serde_json::from_str(&event).unwrap();
-                    event_forwarder.send(event).await.unwrap();
+                    // Having no subscribers is not an error
+                    let _ = event_broadcast.send(event.clone());
+                    // The receiver returned by `new` must not hold up the
+                    // subscribers, so events are dropped when it is full or
+                    // gone
+                    let _ = event_forwarder.try_send(event);
                 },
                 _ = shutdown_rx.changed() => { break; },
             }
@@ -129,7 +212,7 @@ impl QMP {
 
     async fn read_loop(
         read_half: OwnedReadHalf,
-        response_tx: mpsc::Sender<String>,
+        pending: Pending,
         event_tx: mpsc::Sender<String>,
         mut shutdown_rx: watch::Receiver<()>,
     ) {
@@ -144,10 +227,32 @@ impl QMP {
         loop {
             tokio::select! {
                 Ok(Some(line)) = lines.next_line() => {
-                    if line.contains("\"return\":") {
-                        response_tx.send(line).await.unwrap();
-                    } else if line.contains("\"event\":") {
+                    // Failed commands are answered with `error` in place of
+                    // `return`, and must reach the waiting command all the same
+                    let message: serde_json::Value = match serde_json::from_str(&line) {
+                        Ok(message) => message,
+                        Err(_) => {
+                            eprintln!("DEBUG: UNPARSABLE response -- ```{}```", line);
+                            continue;
+                        }
+                    };
+                    if message.get("event").is_some() {
                         event_tx.send(line).await.unwrap();
+                    } else if message.get("return").is_some() || message.get("error").is_some() {
+                        // Replies carry the `id` of their command, so a failed
+                        // command nobody waits on can not take the place of
+                        // the reply to the next one
+                        let reply_tx = message["id"]
+                            .as_u64()
+                            .and_then(|id| pending.lock().unwrap().remove(&id));
+                        match reply_tx {
+                            Some(reply_tx) => {
+                                if let Err(e) = reply_tx.send(line) {
+                                    eprintln!("Failed to send response: {}", e);
+                                }
+                            }
+                            None => eprintln!("DEBUG: UNEXPECTED response -- ```{}```", line),
+                        }
                     } else {
                         eprintln!("DEBUG: UNKNOWN response -- ```{}```", line);
                     }
@@ -160,8 +265,8 @@ impl QMP {
 
     async fn write_loop(
         mut write_half: OwnedWriteHalf,
-        mut command_rx: mpsc::Receiver<(QMPCommand, oneshot::Sender<String>)>,
-        mut response_rx: mpsc::Receiver<String>,
+        mut command_rx: mpsc::Receiver<Request>,
+        pending: Pending,
         mut shutdown_rx: watch::Receiver<()>,
     ) {
         let cmd = serde_json::to_string(&QMPCommand::QmpCapabilities).unwrap();
@@ -169,28 +274,206 @@ impl QMP {
             .write_all(cmd.as_bytes())
             .await
             .expect("Failed to send init message");
+        let mut next_id: u64 = 0;
         loop {
             tokio::select! {
-                Some((command, reply_tx)) = command_rx.recv() => {
-                    let cmd = serde_json::to_string(&command).unwrap();
-                    if let Err(e) = write_half.write_all(cmd.as_bytes()).await {
-                        eprintln!("Failed to send command: {}", e);
-                        continue;
+                Some((command, fd, reply_tx)) = command_rx.recv() => {
+                    let id = next_id;
+                    next_id += 1;
+                    let mut cmd = serde_json::to_value(&command).unwrap();
+                    cmd["id"] = id.into();
+                    let cmd = cmd.to_string();
+                    // Registered before sending, the reply may come right away
+                    if let Some(reply_tx) = reply_tx {
+                        pending.lock().unwrap().insert(id, reply_tx);
                     }
-                    match response_rx.recv().await {
-                        Some(msg) => {
-                            if let Err(e) = reply_tx.send(msg) {
-                                eprintln!("Failed to send response: {}", e);
-                            }
-                        }
-                        None => {
-                            eprintln!("Response channel closed unexpectedly");
-                            break;
-                        }
+                    let sent = match fd {
+                        Some(fd) => Self::write_with_fd(&mut write_half, cmd.as_bytes(), fd).await,
+                        None => write_half.write_all(cmd.as_bytes()).await,
+                    };
+                    if let Err(e) = sent {
+                        eprintln!("Failed to send command: {}", e);
+                        // No reply is coming; dropping the sender fails the
+                        // waiting command
+                        pending.lock().unwrap().remove(&id);
                     }
                 },
                 _ = shutdown_rx.changed() => { break; },
             }
         }
     }
+
+    /// Write `bytes` with `fd` attached as `SCM_RIGHTS`. QEMU picks the fd up
+    /// with the data it arrived with and keeps it for the command that
+    /// follows.
+    async fn write_with_fd(
+        write_half: &mut OwnedWriteHalf,
+        bytes: &[u8],
+        fd: OwnedFd,
+    ) -> io::Result<()> {
+        let stream: &UnixStream = write_half.as_ref();
+        let fds = [fd.as_raw_fd()];
+        let sent = loop {
+            stream.writable().await?;
+            let res = stream.try_io(Interest::WRITABLE, || {
+                let cmsgs = [ControlMessage::ScmRights(&fds)];
+                let iov = [IoSlice::new(bytes)];
+                sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None)
+                    .map_err(io::Error::from)
+            });
+            match res {
+                Ok(sent) => break sent,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        // The fd went out with the first byte, the rest is plain data
+        write_half.write_all(&bytes[sent..]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixListener;
+
+    /// Play QEMU on `listener`: greet, then answer every command with its
+    /// `id`, failing `stop`, returning two names for `query-names` and
+    /// nothing for everything else. `cont` is preceded by 150 `RESUME` events.
+    async fn fake_qemu(listener: UnixListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream
+            .write_all(b"{\"QMP\": {\"version\": {}, \"capabilities\": []}}\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let read = stream.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                return;
+            }
+            buf.extend_from_slice(&chunk[..read]);
+            // Commands are not delimited, parse as many whole ones as arrived
+            let mut commands = serde_json::Deserializer::from_slice(&buf).into_iter::<Value>();
+            let mut replies = String::new();
+            for command in commands.by_ref() {
+                let Ok(command) = command else { break };
+                let mut reply = match command["execute"].as_str() {
+                    Some("stop") => json!({ "error": { "class": "GenericError", "desc": "no" } }),
+                    Some("query-names") => json!({ "return": ["a", "b"] }),
+                    _ => json!({ "return": {} }),
+                };
+                if let Some(id) = command.get("id") {
+                    reply["id"] = id.clone();
+                }
+                if command["execute"] == "cont" {
+                    let event = json!({
+                        "event": "RESUME",
+                        "timestamp": { "seconds": 0, "microseconds": 0 },
+                    });
+                    for _ in 0..150 {
+                        replies.push_str(&format!("{}\n", event));
+                    }
+                }
+                replies.push_str(&format!("{}\n", reply));
+            }
+            let consumed = commands.byte_offset();
+            buf.drain(..consumed);
+            stream.write_all(replies.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_follow_ids() {
+        let path = std::env::temp_dir().join(format!("qmp-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = spawn(fake_qemu(listener));
+
+        let (mut qmp, _events) = QMP::new(&path).await.unwrap();
+        qmp.set_no_success_response(&["stop"]);
+        // The error QEMU answers this with must not reach the next command
+        assert_eq!(qmp.execute(QMPCommand::Stop).await.unwrap(), "");
+        let reply: Value =
+            serde_json::from_str(&qmp.execute(QMPCommand::QueryJobs).await.unwrap()).unwrap();
+        assert_eq!(reply["return"], json!({}));
+        assert_eq!(reply["id"], json!(1));
+
+        qmp.shutdown().await;
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[derive(Serialize)]
+    struct QueryNames {}
+
+    impl Command for QueryNames {
+        const NAME: &'static str = "query-names";
+        type Returns = Vec<String>;
+    }
+
+    #[derive(Serialize)]
+    struct Stop {}
+
+    impl Command for Stop {
+        const NAME: &'static str = "stop";
+        type Returns = serde_json::Map<String, Value>;
+    }
+
+    #[tokio::test]
+    async fn test_execute_command() {
+        let path = std::env::temp_dir().join(format!("qmp-typed-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = spawn(fake_qemu(listener));
+
+        let (mut qmp, _events) = QMP::new(&path).await.unwrap();
+        let names = qmp.execute_command(&QueryNames {}).await.unwrap();
+        assert_eq!(names, vec!["a", "b"]);
+        let error = qmp.execute_command(&Stop {}).await.unwrap_err();
+        assert_eq!(error.to_string(), "QMP command stop failed: \"no\"");
+
+        qmp.shutdown().await;
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unread_events_do_not_block() {
+        let path = std::env::temp_dir().join(format!("qmp-events-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = spawn(fake_qemu(listener));
+
+        // Neither an unread nor a dropped receiver may hold up the rest
+        let (mut qmp, events) = QMP::new(&path).await.unwrap();
+        let mut subscriber = qmp.subscribe();
+        let received = spawn(async move {
+            let mut received = 0;
+            while received < 300 {
+                match subscriber.recv().await {
+                    Ok(_) => received += 1,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => received += missed,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            received
+        });
+        let cont = tokio::time::timeout(Duration::from_secs(5), qmp.execute(QMPCommand::Cont));
+        cont.await.unwrap().unwrap();
+        drop(events);
+        let cont = tokio::time::timeout(Duration::from_secs(5), qmp.execute(QMPCommand::Cont));
+        cont.await.unwrap().unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), received);
+        assert_eq!(received.await.unwrap().unwrap(), 300);
+        qmp.shutdown().await;
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,4 +1,6 @@
 use anyhow::{anyhow, Result};
+use manager::lifecycle::VmState;
+use manager::spec::VmSpec;
 use surrealdb::engine::remote::ws::{Client, Ws};
 use surrealdb::opt::auth::Root;
 use surrealdb::sql::Thing;
@@ -142,6 +144,97 @@ impl QrabDB {
         Ok(event)
     }
 
+    /// Record `backup` of a disk of `vm`, building on `parent`
+    pub async fn insert_backup(
+        &self,
+        vm: &protodbschema::VM,
+        backup: &manager::backup::Backup,
+        parent: Option<&protodbschema::Backup>,
+    ) -> Result<protodbschema::Backup> {
+        let started_at = surrealdb::sql::Datetime(backup.started_at);
+        let record: protodbschema::Backup = singlequery!(
+            self.db,
+            protodbschema::Backup,
+            "INSERT INTO backup {
+                disk: type::string($disk),
+                kind: $kind,
+                target: $target,
+                started_at: type::datetime($started_at),
+            };",
+            ("disk", &backup.disk),
+            ("kind", backup.kind),
+            ("target", &backup.target),
+            ("started_at", started_at)
+        );
+        let _ = self
+            .db
+            .query("RELATE $vm->backed_up->$backup;")
+            .bind(("vm", &vm.id))
+            .bind(("backup", &record.id))
+            .await?;
+        if let Some(parent) = parent {
+            let _ = self
+                .db
+                .query("RELATE $backup->based_on->$parent;")
+                .bind(("backup", &record.id))
+                .bind(("parent", &parent.id))
+                .await?;
+        }
+        Ok(record)
+    }
+
+    /// The backups of the disk `disk` of `vm`, newest first. The first one is
+    /// what the next incremental backup builds on.
+    pub async fn get_backups(&self, vm: &Thing, disk: &str) -> Result<Vec<protodbschema::Backup>> {
+        let backups = listquery!(
+            self.db,
+            protodbschema::Backup,
+            "SELECT * FROM array::group((
+                SELECT ->backed_up.out AS backups FROM type::thing($vm)
+            ).backups) WHERE disk = type::string($disk) ORDER BY started_at DESC;",
+            ("vm", vm),
+            ("disk", disk)
+        );
+        Ok(backups)
+    }
+
+    /// The backup `backup` builds on, if it is an incremental one
+    pub async fn get_backup_parent(
+        &self,
+        backup: &protodbschema::Backup,
+    ) -> Result<Option<protodbschema::Backup>> {
+        let parents = listquery!(
+            self.db,
+            protodbschema::Backup,
+            "SELECT * FROM array::group((
+                SELECT ->based_on.out AS parents FROM type::thing($backup)
+            ).parents);",
+            ("backup", &backup.id)
+        );
+        Ok(parents.into_iter().next())
+    }
+
+    pub async fn update_vm_state(&self, vm: &protodbschema::VM, state: VmState) -> Result<()> {
+        let _ = self
+            .db
+            .query("UPDATE $vm SET state = $state;")
+            .bind(("vm", &vm.id))
+            .bind(("state", state))
+            .await?;
+        Ok(())
+    }
+
+    /// Write back a spec the manager filled in, such as assigned MAC addresses
+    pub async fn update_vm_spec(&self, vm: &protodbschema::VM, spec: &VmSpec) -> Result<()> {
+        let _ = self
+            .db
+            .query("UPDATE $vm SET spec = $spec;")
+            .bind(("vm", &vm.id))
+            .bind(("spec", spec))
+            .await?;
+        Ok(())
+    }
+
     pub async fn relate_event(
         &self,
         event: &protodbschema::Event,
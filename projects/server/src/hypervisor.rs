@@ -1,11 +1,19 @@
 use anyhow::Result;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use surrealdb::sql::Thing;
 
 use crate::qapi;
 use crate::QrabDB;
 
+/// Where the nightly backups go, one directory per VM
+const BACKUP_DIR: &str = "/var/lib/qrab/backups";
+
+/// How many incremental backups may build on a full one before the next
+/// backup of the disk starts over with a full one
+const MAX_INCREMENTALS: usize = 6;
+
 pub(crate) struct Hypervisor {
     pub db: QrabDB,
     pub local_record: protodbschema::Hypervisor,
@@ -66,4 +74,41 @@ impl Hypervisor {
             .unwrap();
         Ok(())
     }
+
+    /// Back up every disk of `vm` into `BACKUP_DIR` and record the backups,
+    /// each building on the last one of its disk unless that chain is long
+    /// enough already
+    pub(crate) async fn backup_vm(
+        &self,
+        vm: &protodbschema::VM,
+        vmm: &mut manager::vm::VM,
+    ) -> Result<()> {
+        let dir = PathBuf::from(BACKUP_DIR).join(vm.id.to_string());
+        tokio::fs::create_dir_all(&dir).await?;
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S");
+        let disks: Vec<String> = vmm.spec().disks.iter().map(|d| d.id.clone()).collect();
+        for disk in disks {
+            let last = self.db.get_backups(&vm.id, &disk).await?.into_iter().next();
+            let parent = match last {
+                Some(last) if self.incrementals(&last).await? < MAX_INCREMENTALS => Some(last),
+                _ => None,
+            };
+            let base = parent.clone().map(manager::backup::Backup::from);
+            let path = dir.join(format!("{}-{}.qcow2", disk, stamp));
+            let backup = vmm.backup_disk(&disk, &path, base.as_ref()).await?;
+            self.db.insert_backup(vm, &backup, parent.as_ref()).await?;
+        }
+        Ok(())
+    }
+
+    /// How many incremental backups lead from a full one up to `backup`
+    async fn incrementals(&self, backup: &protodbschema::Backup) -> Result<usize> {
+        let mut count = 0;
+        let mut current = backup.clone();
+        while let Some(parent) = self.db.get_backup_parent(&current).await? {
+            count += 1;
+            current = parent;
+        }
+        Ok(count)
+    }
 }
@@ -2,9 +2,12 @@ use anyhow::Result;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{interval_at, Instant};
 
 mod systemd;
+use manager::lifecycle::{StateChange, VmState};
 use manager::qmp::qapi;
 
 mod hypervisor;
@@ -13,19 +16,25 @@ use hypervisor::Hypervisor;
 mod db;
 use db::QrabDB;
 
+/// How often every running VM gets backed up
+const BACKUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
 async fn attach_to_vm(
     vm: protodbschema::VM,
 ) -> Result<(
     protodbschema::VM,
     manager::vm::VM,
     mpsc::Receiver<qapi::Event>,
+    broadcast::Receiver<StateChange>,
 )> {
     systemd::create_slice(format!("qrab-{}.slice", &vm.id))
         .await
         .unwrap();
-    let mut vmm = manager::vm::VM::new(vm.id.to_string()).await;
+    let mut vmm = manager::vm::VM::new(vm.id.to_string(), vm.spec.clone()).await;
+    // Subscribed before starting so the changes made by `start` are seen too
+    let states = vmm.subscribe_state();
     let events = vmm.start().await?;
-    Ok((vm, vmm, events))
+    Ok((vm, vmm, events, states))
 }
 
 async fn find_local_vms() -> Result<Vec<String>> {
@@ -67,6 +76,24 @@ async fn wait_for_event(
     (vm, events, event)
 }
 
+async fn wait_for_state_change(
+    vm: protodbschema::VM,
+    mut states: broadcast::Receiver<StateChange>,
+) -> (
+    protodbschema::VM,
+    broadcast::Receiver<StateChange>,
+    Option<StateChange>,
+) {
+    loop {
+        match states.recv().await {
+            Ok(change) => return (vm, states, Some(change)),
+            // Only the latest state is persisted, missing some is fine
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return (vm, states, None),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let preexisting_vm_ids = find_local_vms().await?;
@@ -89,6 +116,10 @@ async fn main() -> Result<()> {
     // of events concurrently.
     let mut event_futures = FuturesUnordered::new();
 
+    // Same as above, for the lifecycle state changes of each VM. The latest
+    // state is written to the VM record.
+    let mut state_futures = FuturesUnordered::new();
+
     // This hashmap holds all our `VM` objects. If we do not hold the state,
     // then the object will go out of scope and our event handler will die. The
     // state of this dictionary is what the loop is trying to reconcile to. The
@@ -96,6 +127,10 @@ async fn main() -> Result<()> {
     // this tracking state up-to-date.
     let mut vms_tracking: HashMap<protodbschema::VM, manager::vm::VM> = HashMap::new();
 
+    // Backups run one interval after the server starts and then every
+    // interval, not right away on every restart of the server
+    let mut backups = interval_at(Instant::now() + BACKUP_INTERVAL, BACKUP_INTERVAL);
+
     // Create an async future which will receive surrealdb::Notifications when
     // records change in the "vm" table of the database. NOTE: This does not
     // currently filter by hypervisor like I want. Perhaps I need to look into
@@ -106,8 +141,14 @@ async fn main() -> Result<()> {
             // This loop awaits the futures that start VMs. The vm state object
             // and events queue for this specific VM are setup here.
             Some(result) = vm_futures.next() => {
-                if let Ok((vm, vmm, events)) = result {
+                if let Ok((vm, vmm, events, states)) = result {
+                    if vmm.spec() != &vm.spec {
+                        // MAC addresses assigned while starting must stay
+                        // the same on the next boot
+                        hypervisor.db.update_vm_spec(&vm, vmm.spec()).await.unwrap();
+                    }
                     vms_tracking.insert(vm.clone(), vmm);
+                    state_futures.push(wait_for_state_change(vm.clone(), states));
                     event_futures.push(wait_for_event(vm, events));
                 } else {
                     dbg!(&result);
@@ -127,6 +168,24 @@ async fn main() -> Result<()> {
                     eprintln!("INFO: {} closed event channel", &vm.id);
                 }
             },
+            Some((vm, states, change)) = state_futures.next() => {
+                if let Some(change) = change {
+                    hypervisor.db.update_vm_state(&vm, change.to).await.unwrap();
+                    state_futures.push(wait_for_state_change(vm, states));
+                }
+            },
+            // Back up the disks of every VM with a guest, one VM after the
+            // other. A failed backup is retried with the next round.
+            _ = backups.tick() => {
+                for (vm, vmm) in vms_tracking.iter_mut() {
+                    if !matches!(vmm.state(), VmState::Running | VmState::Paused) {
+                        continue;
+                    }
+                    if let Err(e) = hypervisor.backup_vm(vm, vmm).await {
+                        eprintln!("ERROR: Failed to back up {}: {:?}", &vm.id, e);
+                    }
+                }
+            },
             // This loop is getting a flood of data from the database to notify
             // on. Ideally, this should already be filtered for hypervisors, but
             // the rust sdk live query doesn't seem to support that yet. TODO